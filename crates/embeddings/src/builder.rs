@@ -104,7 +104,7 @@ async fn get_embedding(client: &Client<OpenAIConfig>, input: String) -> anyhow::
     Ok(embedding)
 }
 
-fn load_embeddings(path: &PathBuf) -> anyhow::Result<HashMap<PathBuf, Embedding>> {
+pub fn load_embeddings(path: &PathBuf) -> anyhow::Result<HashMap<PathBuf, Embedding>> {
     if !path.exists() {
         return Ok(HashMap::new());
     }
@@ -120,7 +120,10 @@ fn load_embeddings(path: &PathBuf) -> anyhow::Result<HashMap<PathBuf, Embedding>
     Ok(embedding_map)
 }
 
-fn save_embeddings(embeddings: &HashMap<PathBuf, Embedding>, path: &PathBuf) -> anyhow::Result<()> {
+pub fn save_embeddings(
+    embeddings: &HashMap<PathBuf, Embedding>,
+    path: &PathBuf,
+) -> anyhow::Result<()> {
     let embedding_list: Vec<Embedding> = embeddings.values().cloned().collect();
 
     let buf = rmp_serde::to_vec(&embedding_list)?;
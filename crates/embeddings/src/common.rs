@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     ffi::OsStr,
     fs,
     path::{Path, PathBuf},
@@ -111,6 +112,14 @@ pub fn file_to_note(path: &Path, root_path: &Path) -> anyhow::Result<Note> {
     })
 }
 
+/// Lowercase words of at least 3 characters, used for cheap lexical matching
+pub fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3)
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
 pub fn note_to_checksum(note: &Note) -> u32 {
     crc32fast::hash(note.text_content.as_bytes())
 }
@@ -1,15 +1,20 @@
 use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::Context;
+use clap::ValueEnum;
 use configparser::ini::Ini;
 use directories::ProjectDirs;
 use tiktoken_rs::tokenizer::Tokenizer;
 
+use crate::{opener::OpenStrategy, rerank::RerankMethod};
+
 pub const EMBEDDING_MODEL: &str = "text-embedding-3-large";
 pub const TOKENIZER: Tokenizer = Tokenizer::Cl100kBase;
 pub const MAX_TOKENS: usize = 8191;
 pub const EMBEDDING_DIM: usize = 3072;
 pub const COST_PER_TOKEN: f64 = 0.00013 / 1000.0;
+pub const RERANK_LLM_MODEL: &str = "gpt-4o-mini";
+pub const RERANK_CANDIDATES: usize = 30;
 
 const EMBEDDING_FILE: &str = "embeddings.msgpack";
 
@@ -19,6 +24,23 @@ pub struct Config {
     pub vault: String,
     pub embedding_path: PathBuf,
     pub plot_colors: HashMap<String, String>,
+    pub unlinked_thresholds: Vec<(PathBuf, u8)>,
+    pub rerank: RerankConfig,
+    pub open: OpenConfig,
+}
+
+pub struct OpenConfig {
+    pub strategy: OpenStrategy,
+    /// Command template with `{path}` and `{line}` placeholders, defaults to $EDITOR
+    pub editor: Option<String>,
+}
+
+pub struct RerankConfig {
+    pub search: RerankMethod,
+    pub related: RerankMethod,
+    pub candidates: usize,
+    pub llm_model: String,
+    pub cross_encoder_url: Option<String>,
 }
 
 pub fn load_config() -> anyhow::Result<Config> {
@@ -52,11 +74,63 @@ pub fn load_config() -> anyhow::Result<Config> {
         })
         .collect();
 
+    let unlinked_thresholds = config_map
+        .get("unlinked_thresholds")
+        .unwrap_or(&HashMap::new())
+        .iter()
+        .filter_map(|(k, v)| v.as_ref().map(|v| (k, v)))
+        .map(|(folder, threshold)| {
+            let threshold: u8 = threshold
+                .trim()
+                .parse()
+                .context(format!("Invalid unlinked threshold for {folder}"))?;
+            Ok((PathBuf::from(folder), threshold))
+        })
+        .collect::<anyhow::Result<Vec<(PathBuf, u8)>>>()?;
+
+    let rerank = load_rerank_config(&config)?;
+    let open = OpenConfig {
+        strategy: match config.get("open", "strategy") {
+            Some(value) => OpenStrategy::from_str(&value, true)
+                .map_err(|err| anyhow::anyhow!("Invalid open.strategy value: {err}"))?,
+            None => OpenStrategy::Obsidian,
+        },
+        editor: config.get("open", "editor"),
+    };
+
     Ok(Config {
         api_key,
         notes_root: notes_path,
         vault,
         embedding_path: project_dirs.data_dir().join(EMBEDDING_FILE),
         plot_colors,
+        unlinked_thresholds,
+        rerank,
+        open,
+    })
+}
+
+fn load_rerank_config(config: &Ini) -> anyhow::Result<RerankConfig> {
+    let parse_method = |key: &str| -> anyhow::Result<RerankMethod> {
+        match config.get("rerank", key) {
+            Some(value) => RerankMethod::from_str(&value, true)
+                .map_err(|err| anyhow::anyhow!("Invalid rerank.{key} value: {err}")),
+            None => Ok(RerankMethod::None),
+        }
+    };
+    let candidates = config
+        .getuint("rerank", "candidates")
+        .map_err(|err| anyhow::anyhow!("Invalid rerank.candidates value: {err}"))?
+        .map(|c| c as usize)
+        .unwrap_or(RERANK_CANDIDATES);
+
+    Ok(RerankConfig {
+        search: parse_method("search")?,
+        related: parse_method("related")?,
+        candidates,
+        llm_model: config
+            .get("rerank", "llm_model")
+            .unwrap_or(RERANK_LLM_MODEL.to_string()),
+        cross_encoder_url: config.get("rerank", "cross_encoder_url"),
     })
 }
@@ -0,0 +1,389 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::load_embeddings,
+    config::{self, Config},
+    rerank::{rerank, RerankMethod},
+    search::{get_query_embedding, rank_by_similarity},
+};
+
+#[derive(Deserialize)]
+struct EvalSet {
+    queries: Vec<EvalQuery>,
+}
+
+#[derive(Deserialize)]
+struct EvalQuery {
+    query: String,
+    relevant: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EvalRun {
+    pub created_at: u64,
+    pub embedding_model: String,
+    pub rerank: String,
+    pub k: usize,
+    pub queries: Vec<QueryResult>,
+    pub summary: Metrics,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueryResult {
+    pub query: String,
+    pub metrics: Metrics,
+    pub retrieved: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct Metrics {
+    pub recall: f64,
+    pub mrr: f64,
+    pub ndcg: f64,
+}
+
+pub async fn evaluate(
+    config: &Config,
+    eval_path: &str,
+    k: usize,
+    rerank_method: Option<RerankMethod>,
+    save_path: Option<&str>,
+    baseline_path: Option<&str>,
+) -> anyhow::Result<()> {
+    let rerank_method = rerank_method.unwrap_or(config.rerank.search);
+    let eval_set: EvalSet = serde_yaml::from_str(
+        &fs::read_to_string(eval_path).context(format!("Can't read {eval_path}"))?,
+    )
+    .context("Invalid eval file")?;
+    // A query without relevant notes scores 0 whatever is retrieved, so it would only drag the mean down
+    let (queries, skipped): (Vec<&EvalQuery>, Vec<&EvalQuery>) = eval_set
+        .queries
+        .iter()
+        .partition(|q| !q.relevant.is_empty());
+    for eval_query in &skipped {
+        println!(
+            "{} {}",
+            "Skipping query without relevant notes:".red(),
+            eval_query.query
+        );
+    }
+    if queries.is_empty() {
+        anyhow::bail!("No queries with relevant notes in {eval_path}");
+    }
+
+    let baseline: Option<EvalRun> = baseline_path
+        .map(|path| -> anyhow::Result<EvalRun> {
+            let buf = fs::read_to_string(path).context(format!("Can't read {path}"))?;
+            serde_json::from_str(&buf).context("Invalid baseline run")
+        })
+        .transpose()?;
+    let rerank_name = format!("{rerank_method:?}");
+    if let Some(run) = &baseline {
+        for mismatch in baseline_mismatches(run, k, &rerank_name, config::EMBEDDING_MODEL) {
+            println!(
+                "{} {mismatch}, the comparison is not meaningful",
+                "Baseline mismatch:".red()
+            );
+        }
+    }
+
+    let embeddings = load_embeddings(config).context("Failed to load embeddings from file")?;
+    let candidate_count = match rerank_method {
+        RerankMethod::None => k,
+        _ => config.rerank.candidates.max(k),
+    };
+
+    println!(
+        "Evaluating {} queries at k={} (rerank: {})",
+        queries.len().green(),
+        k.green(),
+        format!("{rerank_method:?}").blue()
+    );
+    println!();
+
+    let mut results = vec![];
+    for eval_query in queries {
+        let query_embedding = get_query_embedding(&config.api_key, &eval_query.query).await?;
+        let candidates = rank_by_similarity(&embeddings, &query_embedding, candidate_count);
+        let candidates = rerank(config, rerank_method, &eval_query.query, candidates).await?;
+        let retrieved: Vec<PathBuf> = candidates
+            .into_iter()
+            .take(k)
+            .map(|c| c.note_path)
+            .collect();
+
+        let relevant: HashSet<PathBuf> = eval_query
+            .relevant
+            .iter()
+            .map(|p| normalize_note_path(p))
+            .collect();
+        let metrics = compute_metrics(&retrieved, &relevant, k);
+
+        let previous = baseline.as_ref().and_then(|run| {
+            run.queries
+                .iter()
+                .find(|q| q.query == eval_query.query)
+                .map(|q| q.metrics)
+        });
+        println!("{}", eval_query.query.yellow());
+        print_metrics(&metrics, previous.as_ref());
+
+        results.push(QueryResult {
+            query: eval_query.query.to_owned(),
+            metrics,
+            retrieved,
+        });
+    }
+
+    let summary = mean_metrics(results.iter().map(|r| &r.metrics));
+    println!();
+    println!("{}", "Mean".bold());
+    print_metrics(&summary, baseline.as_ref().map(|run| &run.summary));
+
+    if let Some(path) = save_path {
+        let run = EvalRun {
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            embedding_model: config::EMBEDDING_MODEL.to_string(),
+            rerank: rerank_name,
+            k,
+            queries: results,
+            summary,
+        };
+        fs::write(path, serde_json::to_string_pretty(&run)?)
+            .context(format!("Failed to save run to {path}"))?;
+        println!();
+        println!("Saved run to {}", path.blue());
+    }
+
+    Ok(())
+}
+
+/// Settings that differ between the baseline and this run
+fn baseline_mismatches(
+    run: &EvalRun,
+    k: usize,
+    rerank: &str,
+    embedding_model: &str,
+) -> Vec<String> {
+    let mut mismatches = vec![];
+    if run.k != k {
+        mismatches.push(format!("baseline k={}, this run k={k}", run.k));
+    }
+    if run.rerank != rerank {
+        mismatches.push(format!(
+            "baseline rerank {}, this run rerank {rerank}",
+            run.rerank
+        ));
+    }
+    if run.embedding_model != embedding_model {
+        mismatches.push(format!(
+            "baseline model {}, this run model {embedding_model}",
+            run.embedding_model
+        ));
+    }
+    mismatches
+}
+
+fn normalize_note_path(path: &Path) -> PathBuf {
+    if path.extension() == Some(OsStr::new("md")) {
+        path.to_path_buf()
+    } else {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".md");
+        PathBuf::from(path)
+    }
+}
+
+fn compute_metrics(retrieved: &[PathBuf], relevant: &HashSet<PathBuf>, k: usize) -> Metrics {
+    if relevant.is_empty() {
+        return Metrics::default();
+    }
+
+    let top_k = &retrieved[..retrieved.len().min(k)];
+    let hits = top_k.iter().filter(|p| relevant.contains(*p)).count();
+    let recall = hits as f64 / relevant.len() as f64;
+
+    let mrr = top_k
+        .iter()
+        .position(|p| relevant.contains(p))
+        .map(|index| 1.0 / (index + 1) as f64)
+        .unwrap_or(0.0);
+
+    let dcg: f64 = top_k
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| relevant.contains(*p))
+        .map(|(index, _)| discount(index))
+        .sum();
+    let ideal_dcg: f64 = (0..relevant.len().min(k)).map(discount).sum();
+    let ndcg = if ideal_dcg > 0.0 {
+        dcg / ideal_dcg
+    } else {
+        0.0
+    };
+
+    Metrics { recall, mrr, ndcg }
+}
+
+fn discount(index: usize) -> f64 {
+    1.0 / ((index + 2) as f64).log2()
+}
+
+fn mean_metrics<'a>(metrics: impl Iterator<Item = &'a Metrics>) -> Metrics {
+    let mut sum = Metrics::default();
+    let mut count = 0;
+    for m in metrics {
+        sum.recall += m.recall;
+        sum.mrr += m.mrr;
+        sum.ndcg += m.ndcg;
+        count += 1;
+    }
+    if count == 0 {
+        return sum;
+    }
+    Metrics {
+        recall: sum.recall / count as f64,
+        mrr: sum.mrr / count as f64,
+        ndcg: sum.ndcg / count as f64,
+    }
+}
+
+fn print_metrics(metrics: &Metrics, baseline: Option<&Metrics>) {
+    let format_value = |value: f64, previous: Option<f64>| match previous {
+        Some(previous) => {
+            let delta = value - previous;
+            let delta_str = format!("{delta:+.3}");
+            let delta_str = if delta > 0.0005 {
+                delta_str.green().to_string()
+            } else if delta < -0.0005 {
+                delta_str.red().to_string()
+            } else {
+                delta_str.dimmed().to_string()
+            };
+            format!("{value:.3} ({delta_str})")
+        }
+        None => format!("{value:.3}"),
+    };
+
+    println!(
+        "  recall@k: {}  MRR: {}  nDCG: {}",
+        format_value(metrics.recall, baseline.map(|b| b.recall)),
+        format_value(metrics.mrr, baseline.map(|b| b.mrr)),
+        format_value(metrics.ndcg, baseline.map(|b| b.ndcg)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(items: &[&str]) -> Vec<PathBuf> {
+        items.iter().map(PathBuf::from).collect()
+    }
+
+    fn relevant(items: &[&str]) -> HashSet<PathBuf> {
+        items.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn metrics_perfect_ranking() {
+        let metrics = compute_metrics(
+            &paths(&["a.md", "b.md", "c.md"]),
+            &relevant(&["a.md", "b.md"]),
+            3,
+        );
+        assert_eq!(metrics.recall, 1.0);
+        assert_eq!(metrics.mrr, 1.0);
+        assert!((metrics.ndcg - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn metrics_no_hits() {
+        let metrics = compute_metrics(&paths(&["a.md", "b.md"]), &relevant(&["x.md"]), 2);
+        assert_eq!(metrics, Metrics::default());
+    }
+
+    #[test]
+    fn metrics_hit_at_second_position() {
+        let metrics = compute_metrics(
+            &paths(&["a.md", "b.md", "c.md"]),
+            &relevant(&["b.md", "z.md"]),
+            3,
+        );
+        assert_eq!(metrics.recall, 0.5);
+        assert_eq!(metrics.mrr, 0.5);
+        let expected_ndcg = (1.0 / 3f64.log2()) / (1.0 + 1.0 / 3f64.log2());
+        assert!((metrics.ndcg - expected_ndcg).abs() < 1e-9);
+    }
+
+    #[test]
+    fn metrics_ignore_results_beyond_k() {
+        let metrics = compute_metrics(&paths(&["a.md", "b.md"]), &relevant(&["b.md"]), 1);
+        assert_eq!(metrics.recall, 0.0);
+        assert_eq!(metrics.mrr, 0.0);
+    }
+
+    #[test]
+    fn normalize_note_path_adds_extension() {
+        assert_eq!(
+            normalize_note_path(Path::new("Areas/Nix flakes")),
+            PathBuf::from("Areas/Nix flakes.md")
+        );
+        assert_eq!(
+            normalize_note_path(Path::new("Areas/Nix.md")),
+            PathBuf::from("Areas/Nix.md")
+        );
+    }
+
+    #[test]
+    fn mean_metrics_averages() {
+        let metrics = [
+            Metrics {
+                recall: 1.0,
+                mrr: 1.0,
+                ndcg: 1.0,
+            },
+            Metrics {
+                recall: 0.0,
+                mrr: 0.5,
+                ndcg: 0.0,
+            },
+        ];
+        let mean = mean_metrics(metrics.iter());
+        assert_eq!(mean.recall, 0.5);
+        assert_eq!(mean.mrr, 0.75);
+        assert_eq!(mean.ndcg, 0.5);
+    }
+
+    #[test]
+    fn baseline_mismatches_lists_differences() {
+        let run = EvalRun {
+            created_at: 0,
+            embedding_model: "text-embedding-3-large".to_string(),
+            rerank: "None".to_string(),
+            k: 5,
+            queries: vec![],
+            summary: Metrics::default(),
+        };
+        assert!(baseline_mismatches(&run, 5, "None", "text-embedding-3-large").is_empty());
+
+        let mismatches = baseline_mismatches(&run, 10, "Llm", "text-embedding-3-large");
+        assert_eq!(
+            mismatches,
+            vec![
+                "baseline k=5, this run k=10",
+                "baseline rerank None, this run rerank Llm"
+            ]
+        );
+    }
+}
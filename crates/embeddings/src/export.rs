@@ -0,0 +1,510 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use arrow_array::{
+    cast::AsArray,
+    types::{Float32Type, UInt32Type},
+    ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, StringArray, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use owo_colors::OwoColorize;
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+use regex::Regex;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builder,
+    common::load_embeddings,
+    config::{Config, EMBEDDING_DIM},
+    types::Embedding,
+};
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+lazy_static! {
+    static ref NPY_SHAPE: Regex = Regex::new(r"'shape':\s*\((\d+),\s*(\d+)\)").unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Apache Parquet with one row per note and a fixed-size list column for the vector
+    Parquet,
+    /// NumPy float32 matrix, with note metadata in a `.metadata.jsonl` file next to it
+    Npy,
+    /// One JSON object per line
+    Jsonl,
+    /// SQLite `notes` table with the vectors as float32 blobs. With the sqlite-vec extension
+    /// loaded, query it with `ORDER BY vec_distance_cosine(embedding, ?) LIMIT 10`
+    SqliteVec,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    note_path: PathBuf,
+    note_title: String,
+    note_checksum: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    embedding: Vec<f32>,
+}
+
+impl From<&Embedding> for Record {
+    fn from(e: &Embedding) -> Self {
+        Record {
+            note_path: e.note_path.to_owned(),
+            note_title: note_title(&e.note_path),
+            note_checksum: e.note_checksum,
+            embedding: e.embedding.to_owned(),
+        }
+    }
+}
+
+pub fn export(config: &Config, format: Format, output: &str) -> anyhow::Result<()> {
+    let mut embeddings = load_embeddings(config).context("Failed to load embeddings from file")?;
+    embeddings.sort_by(|a, b| a.note_path.cmp(&b.note_path));
+
+    let output = Path::new(output);
+    match format {
+        Format::Parquet => write_parquet(&embeddings, output)?,
+        Format::Npy => write_npy(&embeddings, output)?,
+        Format::Jsonl => write_jsonl(&embeddings, output)?,
+        Format::SqliteVec => write_sqlite(&embeddings, output)?,
+    }
+
+    println!(
+        "Exported {} embeddings to {}",
+        embeddings.len().green(),
+        output.display().blue()
+    );
+    Ok(())
+}
+
+/// Merges embeddings from `input` into the local store, replacing existing entries of the same notes
+pub fn import(config: &Config, format: Format, input: &str) -> anyhow::Result<()> {
+    let input = Path::new(input);
+    let imported = match format {
+        Format::Parquet => read_parquet(input)?,
+        Format::Npy => read_npy(input)?,
+        Format::Jsonl => read_jsonl(input)?,
+        Format::SqliteVec => read_sqlite(input)?,
+    };
+
+    if let Some(e) = imported.iter().find(|e| e.embedding.len() != EMBEDDING_DIM) {
+        anyhow::bail!(
+            "Embedding of {} has {} dimensions, expected {}",
+            e.note_path.display(),
+            e.embedding.len(),
+            EMBEDDING_DIM
+        );
+    }
+
+    let mut embeddings =
+        builder::load_embeddings(&config.embedding_path).context("Failed to load embeddings")?;
+    let imported_count = imported.len();
+    for embedding in imported {
+        embeddings.insert(embedding.note_path.to_owned(), embedding);
+    }
+    builder::save_embeddings(&embeddings, &config.embedding_path)
+        .context("Failed to save embeddings")?;
+
+    println!(
+        "Imported {} embeddings from {}",
+        imported_count.green(),
+        input.display().blue()
+    );
+    println!("Run the build command to re-embed notes that changed since the export");
+    Ok(())
+}
+
+fn note_title(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn write_jsonl(embeddings: &[Embedding], path: &Path) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for e in embeddings {
+        serde_json::to_writer(&mut writer, &Record::from(e))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_jsonl(path: &Path) -> anyhow::Result<Vec<Embedding>> {
+    let embeddings = read_records(path)?
+        .into_iter()
+        .map(|r| Embedding {
+            note_path: r.note_path,
+            note_checksum: r.note_checksum,
+            embedding: r.embedding,
+        })
+        .collect();
+    Ok(embeddings)
+}
+
+fn read_records(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let reader =
+        BufReader::new(File::open(path).context(format!("Can't open {}", path.display()))?);
+    reader
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(&line?).context(format!("Invalid record on line {}", i + 1))
+        })
+        .collect()
+}
+
+fn npy_metadata_path(path: &Path) -> PathBuf {
+    path.with_extension("metadata.jsonl")
+}
+
+fn write_npy(embeddings: &[Embedding], path: &Path) -> anyhow::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        embeddings.len(),
+        EMBEDDING_DIM
+    );
+    // Magic, version and header length take 10 bytes, the whole preamble must be 64-byte aligned
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for e in embeddings {
+        for value in &e.embedding {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+
+    let metadata: Vec<Embedding> = embeddings
+        .iter()
+        .map(|e| Embedding {
+            note_path: e.note_path.to_owned(),
+            note_checksum: e.note_checksum,
+            embedding: vec![],
+        })
+        .collect();
+    write_jsonl(&metadata, &npy_metadata_path(path))
+}
+
+fn read_npy(path: &Path) -> anyhow::Result<Vec<Embedding>> {
+    let mut buf = vec![];
+    File::open(path)
+        .context(format!("Can't open {}", path.display()))?
+        .read_to_end(&mut buf)?;
+
+    if !buf.starts_with(NPY_MAGIC) || buf.len() < 10 {
+        anyhow::bail!("{} is not a .npy file", path.display());
+    }
+    let (header_len, header_start) = match buf[6] {
+        1 => (u16::from_le_bytes([buf[8], buf[9]]) as usize, 10),
+        2 | 3 => (
+            u32::from_le_bytes(buf.get(8..12).context("Truncated header")?.try_into()?) as usize,
+            12,
+        ),
+        version => anyhow::bail!("Unsupported .npy version {version}"),
+    };
+    let data_start = header_start + header_len;
+    let header = std::str::from_utf8(
+        buf.get(header_start..data_start)
+            .context("Truncated header")?,
+    )?;
+    if !header.contains("'descr': '<f4'") || !header.contains("'fortran_order': False") {
+        anyhow::bail!("Only C-ordered little-endian float32 arrays are supported");
+    }
+    let shape = NPY_SHAPE
+        .captures(header)
+        .context("Only 2-dimensional arrays are supported")?;
+    let rows: usize = shape[1].parse()?;
+    let dim: usize = shape[2].parse()?;
+    if dim == 0 {
+        anyhow::bail!("Array has no columns");
+    }
+
+    let data = &buf[data_start..];
+    let data_len = rows
+        .checked_mul(dim)
+        .and_then(|n| n.checked_mul(4))
+        .context(format!("Array shape ({rows}, {dim}) is too large"))?;
+    if data.len() != data_len {
+        anyhow::bail!("Array data doesn't match shape ({rows}, {dim})");
+    }
+
+    let metadata = read_records(&npy_metadata_path(path))?;
+    if metadata.len() != rows {
+        anyhow::bail!(
+            "Metadata has {} notes but the array has {} rows",
+            metadata.len(),
+            rows
+        );
+    }
+
+    let embeddings = metadata
+        .into_iter()
+        .zip(data.chunks_exact(dim * 4))
+        .map(|(record, row)| Embedding {
+            note_path: record.note_path,
+            note_checksum: record.note_checksum,
+            embedding: row
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        })
+        .collect();
+    Ok(embeddings)
+}
+
+fn write_parquet(embeddings: &[Embedding], path: &Path) -> anyhow::Result<()> {
+    let item_field = Arc::new(Field::new("item", DataType::Float32, false));
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("note_path", DataType::Utf8, false),
+        Field::new("note_title", DataType::Utf8, false),
+        Field::new("note_checksum", DataType::UInt32, false),
+        Field::new(
+            "embedding",
+            DataType::FixedSizeList(item_field.clone(), EMBEDDING_DIM as i32),
+            false,
+        ),
+    ]));
+
+    let paths = StringArray::from_iter_values(
+        embeddings
+            .iter()
+            .map(|e| e.note_path.to_string_lossy().to_string()),
+    );
+    let titles = StringArray::from_iter_values(embeddings.iter().map(|e| note_title(&e.note_path)));
+    let checksums = UInt32Array::from_iter_values(embeddings.iter().map(|e| e.note_checksum));
+    let values =
+        Float32Array::from_iter_values(embeddings.iter().flat_map(|e| e.embedding.iter().copied()));
+    let vectors =
+        FixedSizeListArray::try_new(item_field, EMBEDDING_DIM as i32, Arc::new(values), None)?;
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(paths),
+        Arc::new(titles),
+        Arc::new(checksums),
+        Arc::new(vectors),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+fn read_parquet(path: &Path) -> anyhow::Result<Vec<Embedding>> {
+    let file = File::open(path).context(format!("Can't open {}", path.display()))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+
+    let mut embeddings = vec![];
+    for batch in reader {
+        let batch = batch?;
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .context(format!("Missing column {name}"))
+        };
+        let paths = column("note_path")?
+            .as_string_opt::<i32>()
+            .context("note_path must be a string column")?;
+        let checksums = column("note_checksum")?
+            .as_primitive_opt::<UInt32Type>()
+            .context("note_checksum must be a uint32 column")?;
+        let vectors = column("embedding")?
+            .as_fixed_size_list_opt()
+            .context("embedding must be a fixed-size list column")?;
+
+        for i in 0..batch.num_rows() {
+            let vector = vectors.value(i);
+            let vector = vector
+                .as_primitive_opt::<Float32Type>()
+                .context("embedding must contain float32 values")?;
+            embeddings.push(Embedding {
+                note_path: PathBuf::from(paths.value(i)),
+                note_checksum: checksums.value(i),
+                embedding: vector.values().to_vec(),
+            });
+        }
+    }
+    Ok(embeddings)
+}
+
+fn write_sqlite(embeddings: &[Embedding], path: &Path) -> anyhow::Result<()> {
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    // Replaced like the other formats, so that notes removed since the last export don't remain
+    tx.execute("DROP TABLE IF EXISTS notes", ())?;
+    tx.execute(
+        "CREATE TABLE notes (
+            note_path TEXT PRIMARY KEY,
+            note_title TEXT NOT NULL,
+            note_checksum INTEGER NOT NULL,
+            embedding BLOB NOT NULL
+        )",
+        (),
+    )?;
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO notes (note_path, note_title, note_checksum, embedding)
+            VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for e in embeddings {
+            let blob: Vec<u8> = e.embedding.iter().flat_map(|v| v.to_le_bytes()).collect();
+            insert.execute(params![
+                e.note_path.to_string_lossy(),
+                note_title(&e.note_path),
+                e.note_checksum,
+                blob
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn read_sqlite(path: &Path) -> anyhow::Result<Vec<Embedding>> {
+    if !path.exists() {
+        anyhow::bail!("{} doesn't exist", path.display());
+    }
+    let conn = Connection::open(path)?;
+    let mut query = conn.prepare("SELECT note_path, note_checksum, embedding FROM notes")?;
+    let rows = query.query_map((), |row| {
+        let note_path: String = row.get(0)?;
+        let note_checksum: u32 = row.get(1)?;
+        let blob: Vec<u8> = row.get(2)?;
+        Ok(Embedding {
+            note_path: PathBuf::from(note_path),
+            note_checksum,
+            embedding: blob
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn sample_embeddings() -> Vec<Embedding> {
+        vec![
+            Embedding {
+                note_path: PathBuf::from("Areas/First note.md"),
+                note_checksum: 42,
+                embedding: (0..EMBEDDING_DIM).map(|i| i as f32 / 1000.0).collect(),
+            },
+            Embedding {
+                note_path: PathBuf::from("Second.md"),
+                note_checksum: 7,
+                embedding: vec![-0.5; EMBEDDING_DIM],
+            },
+        ]
+    }
+
+    fn assert_roundtrip(
+        format: &str,
+        write: fn(&[Embedding], &Path) -> anyhow::Result<()>,
+        read: fn(&Path) -> anyhow::Result<Vec<Embedding>>,
+    ) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(format!("embeddings.{format}"));
+
+        let embeddings = sample_embeddings();
+        write(&embeddings, &path).unwrap();
+        let restored = read(&path).unwrap();
+
+        assert_eq!(restored.len(), embeddings.len());
+        for (a, b) in embeddings.iter().zip(restored.iter()) {
+            assert_eq!(a.note_path, b.note_path);
+            assert_eq!(a.note_checksum, b.note_checksum);
+            assert_eq!(a.embedding, b.embedding);
+        }
+    }
+
+    #[test]
+    fn jsonl_roundtrip() {
+        assert_roundtrip("jsonl", write_jsonl, read_jsonl);
+    }
+
+    #[test]
+    fn npy_roundtrip() {
+        assert_roundtrip("npy", write_npy, read_npy);
+    }
+
+    #[test]
+    fn parquet_roundtrip() {
+        assert_roundtrip("parquet", write_parquet, read_parquet);
+    }
+
+    #[test]
+    fn sqlite_roundtrip() {
+        assert_roundtrip("sqlite", write_sqlite, read_sqlite);
+    }
+
+    #[test]
+    fn sqlite_export_replaces_existing_notes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("embeddings.sqlite");
+        let embeddings = sample_embeddings();
+        write_sqlite(&embeddings, &path).unwrap();
+        write_sqlite(&embeddings[1..], &path).unwrap();
+
+        let restored = read_sqlite(&path).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].note_path, embeddings[1].note_path);
+    }
+
+    #[test]
+    fn npy_header_is_aligned() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("embeddings.npy");
+        write_npy(&sample_embeddings(), &path).unwrap();
+        let buf = fs::read(&path).unwrap();
+
+        let header_len = u16::from_le_bytes([buf[8], buf[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(buf[10 + header_len - 1], b'\n');
+    }
+
+    #[test]
+    fn npy_with_oversized_shape_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("embeddings.npy");
+        let header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, 2), }}\n",
+            usize::MAX
+        );
+        let mut buf = NPY_MAGIC.to_vec();
+        buf.extend([1, 0]);
+        buf.extend((header.len() as u16).to_le_bytes());
+        buf.extend(header.as_bytes());
+        fs::write(&path, buf).unwrap();
+
+        let Err(error) = read_npy(&path) else {
+            panic!("oversized shape was accepted");
+        };
+        assert!(error.to_string().contains("too large"));
+    }
+}
@@ -6,9 +6,13 @@ mod builder;
 mod common;
 mod config;
 mod cost;
+mod eval;
+mod export;
 mod graph;
+mod opener;
 mod plot;
 mod prompt;
+mod rerank;
 mod search;
 mod types;
 mod unlinked;
@@ -35,12 +39,70 @@ enum Commands {
     Search {
         #[arg(value_name = "STRING")]
         query: Option<String>,
+
+        #[arg(long, value_enum, help = "Rerank top results (overrides config)")]
+        rerank: Option<rerank::RerankMethod>,
+
+        #[arg(long, value_enum, help = "How to open notes (overrides config)")]
+        open_with: Option<opener::OpenStrategy>,
     },
 
     #[command(about = "Get related notes to a specific note")]
     Related {
         #[arg(value_name = "RELATIVE_PATH")]
         path: Option<String>,
+
+        #[arg(long, value_enum, help = "Rerank top results (overrides config)")]
+        rerank: Option<rerank::RerankMethod>,
+
+        #[arg(long, value_enum, help = "How to open notes (overrides config)")]
+        open_with: Option<opener::OpenStrategy>,
+    },
+
+    #[command(
+        about = "Evaluate search quality against a set of queries with known relevant notes"
+    )]
+    Eval {
+        #[arg(value_name = "YAML_FILE")]
+        file: String,
+
+        #[arg(short, long, default_value = "10")]
+        k: usize,
+
+        #[arg(long, value_enum, help = "Rerank top results (overrides config)")]
+        rerank: Option<rerank::RerankMethod>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Save results as JSON for later comparison"
+        )]
+        save: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Compare results to a previously saved run"
+        )]
+        baseline: Option<String>,
+    },
+
+    #[command(about = "Export embeddings and note metadata to a standard format")]
+    Export {
+        #[arg(long, value_enum)]
+        format: export::Format,
+
+        #[arg(value_name = "FILE")]
+        output: String,
+    },
+
+    #[command(about = "Import embeddings previously exported with the export command")]
+    Import {
+        #[arg(long, value_enum)]
+        format: export::Format,
+
+        #[arg(value_name = "FILE")]
+        input: String,
     },
 
     #[command(about = "Plot embeddings in 2D and open result in browser")]
@@ -57,8 +119,14 @@ enum Commands {
         #[arg(long, value_name = "PERCENT", default_value = "70")]
         threshold: u8,
 
+        #[arg(long, value_name = "COUNT", default_value = "5")]
+        top_k: usize,
+
         #[arg(long, value_name = "PATH")]
         exclude: Vec<String>,
+
+        #[arg(long, value_enum, help = "How to open notes (overrides config)")]
+        open_with: Option<opener::OpenStrategy>,
     },
 }
 
@@ -72,16 +140,55 @@ async fn main() -> anyhow::Result<()> {
 
     match &cli.command {
         Commands::Build { dry_run } => builder::build(&config, dry_run.to_owned()).await?,
-        Commands::Search { query } => search::query(&config, query.as_deref()).await?,
+        Commands::Search {
+            query,
+            rerank,
+            open_with,
+        } => search::query(&config, query.as_deref(), *rerank, *open_with).await?,
         Commands::Cost => cost::calculate_cost(&config)?,
-        Commands::Related { path } => search::related(&config, path)?,
+        Commands::Related {
+            path,
+            rerank,
+            open_with,
+        } => search::related(&config, path, *rerank, *open_with).await?,
+        Commands::Eval {
+            file,
+            k,
+            rerank,
+            save,
+            baseline,
+        } => {
+            eval::evaluate(
+                &config,
+                file,
+                *k,
+                *rerank,
+                save.as_deref(),
+                baseline.as_deref(),
+            )
+            .await?
+        }
+        Commands::Export { format, output } => export::export(&config, *format, output)?,
+        Commands::Import { format, input } => export::import(&config, *format, input)?,
         Commands::Plot => plot::plot(&config)?,
         Commands::Prune => builder::prune(&config)?,
         Commands::Unlinked {
             output,
             threshold,
+            top_k,
             exclude,
-        } => unlinked::handle_unlinked(&config, output.as_deref(), *threshold, exclude).await?,
+            open_with,
+        } => {
+            unlinked::handle_unlinked(
+                &config,
+                output.as_deref(),
+                *threshold,
+                *top_k,
+                exclude,
+                *open_with,
+            )
+            .await?
+        }
     }
     Ok(())
 }
@@ -0,0 +1,141 @@
+use std::{path::Path, process::Command};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use owo_colors::OwoColorize;
+use termimad::MadSkin;
+use urlencoding::encode;
+
+use crate::{common::words, config::Config};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OpenStrategy {
+    /// Launch an obsidian:// URI
+    Obsidian,
+    /// Open the note in $EDITOR at the best matching line
+    Editor,
+    /// Render the note in the terminal
+    Preview,
+    /// Print the absolute path of the note
+    Path,
+}
+
+/// Opens a note from the vault. `context` is the text the note was matched against (a search
+/// query or another note), used to find the line to jump to in an editor.
+pub fn open_note(
+    config: &Config,
+    strategy: OpenStrategy,
+    note_path: &Path,
+    context: Option<&str>,
+) -> anyhow::Result<()> {
+    let abs_path = config.notes_root.join(note_path);
+    match strategy {
+        OpenStrategy::Obsidian => {
+            open::that(obsidian_uri(&config.vault, note_path))
+                .context("Failed to launch Obsidian URI")?;
+        }
+        OpenStrategy::Editor => {
+            let content = std::fs::read_to_string(&abs_path)
+                .context(format!("Can't read note {}", note_path.display()))?;
+            let line = context.map_or(1, |context| locate_line(&content, context));
+            let template = match &config.open.editor {
+                Some(template) => template.to_owned(),
+                None => default_editor_template()?,
+            };
+            let args = editor_command(&template, &abs_path, line);
+            let (program, args) = args.split_first().context("Empty editor command")?;
+            let status = Command::new(program)
+                .args(args)
+                .status()
+                .context(format!("Failed to launch {program}"))?;
+            if !status.success() {
+                anyhow::bail!("{program} exited with {status}");
+            }
+        }
+        OpenStrategy::Preview => {
+            let content = std::fs::read_to_string(&abs_path)
+                .context(format!("Can't read note {}", note_path.display()))?;
+            println!();
+            println!("{}", note_path.display().bold().bright_white());
+            println!("{}", "─".repeat(40).dimmed());
+            MadSkin::default().print_text(&content);
+            println!("{}", "─".repeat(40).dimmed());
+        }
+        OpenStrategy::Path => println!("{}", abs_path.display()),
+    }
+    Ok(())
+}
+
+fn obsidian_uri(vault_name: &str, note_path: &Path) -> String {
+    let path_str = note_path.to_string_lossy();
+    let encoded_path = encode(&path_str);
+    format!("obsidian://open?vault={vault_name}&file={encoded_path}")
+}
+
+fn default_editor_template() -> anyhow::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .context("Set $EDITOR or the editor field in the [open] section of config.ini")?;
+    Ok(format!("{editor} +{{line}} {{path}}"))
+}
+
+/// Expands `{line}` and `{path}` in a whitespace-separated command template
+fn editor_command(template: &str, path: &Path, line: usize) -> Vec<String> {
+    let path = path.to_string_lossy();
+    template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{line}", &line.to_string())
+                .replace("{path}", &path)
+        })
+        .collect()
+}
+
+/// 1-based number of the line sharing the most words with `context`
+fn locate_line(content: &str, context: &str) -> usize {
+    let context_words = words(context);
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, words(line).intersection(&context_words).count()))
+        .filter(|(_, overlap)| *overlap > 0)
+        // max_by_key returns the last maximum, prefer the first one instead
+        .min_by_key(|(i, overlap)| (std::cmp::Reverse(*overlap), *i))
+        .map_or(1, |(i, _)| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_command_expands_placeholders() {
+        assert_eq!(
+            editor_command("nvim +{line} {path}", Path::new("/vault/My note.md"), 12),
+            vec!["nvim", "+12", "/vault/My note.md"]
+        );
+        assert_eq!(
+            editor_command("code -g {path}:{line}", Path::new("/vault/a.md"), 3),
+            vec!["code", "-g", "/vault/a.md:3"]
+        );
+    }
+
+    #[test]
+    fn locate_line_finds_best_match() {
+        let content = "# Title\n\nSomething unrelated\nRust lifetimes explained\nMore lifetimes";
+        assert_eq!(locate_line(content, "rust lifetimes"), 4);
+    }
+
+    #[test]
+    fn locate_line_defaults_to_first_line() {
+        assert_eq!(locate_line("# Title\nBody", "nothing in common"), 1);
+    }
+
+    #[test]
+    fn obsidian_uri_encodes_path() {
+        assert_eq!(
+            obsidian_uri("Notes", Path::new("Areas/My note.md")),
+            "obsidian://open?vault=Notes&file=Areas%2FMy%20note.md"
+        );
+    }
+}
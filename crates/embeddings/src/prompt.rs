@@ -1,13 +1,16 @@
 use std::fmt;
 use std::path::{Path, PathBuf};
 
-use anyhow::Context;
 use dialoguer::{theme::ColorfulTheme, FuzzySelect, Input, Select};
 
 use owo_colors::OwoColorize;
-use urlencoding::encode;
 
-use crate::{common::Note, config::Config, unlinked::UnlinkedPair};
+use crate::{
+    common::Note,
+    config::Config,
+    opener::{open_note, OpenStrategy},
+    unlinked::UnlinkedPair,
+};
 
 pub struct PairListItem {
     pub path_a: PathBuf,
@@ -52,6 +55,7 @@ impl fmt::Display for PairListItem {
 pub struct NoteListItem {
     pub note_path: PathBuf,
     pub similarity: f32,
+    pub rerank_score: Option<f32>,
 }
 
 impl fmt::Display for NoteListItem {
@@ -65,7 +69,11 @@ impl fmt::Display for NoteListItem {
             "in".dimmed(),
             path.display().dimmed(),
             format!("{:.0}%", self.similarity * 100.0).green(),
-        )
+        )?;
+        if let Some(score) = self.rerank_score {
+            write!(f, "[rerank {}] ", format!("{score:.2}").yellow())?;
+        }
+        Ok(())
     }
 }
 
@@ -79,6 +87,8 @@ impl fmt::Display for Note {
 pub fn result_selector(
     notes: Vec<NoteListItem>,
     config: &Config,
+    open_with: OpenStrategy,
+    context: &str,
     mut selection_index: usize,
 ) -> anyhow::Result<()> {
     loop {
@@ -93,7 +103,7 @@ pub fn result_selector(
         match selection {
             Some(index) => {
                 let path = notes[index].note_path.as_path();
-                open_note(&config.vault, path)?;
+                open_note(config, open_with, path, Some(context))?;
                 selection_index = index;
             }
             None => {
@@ -125,18 +135,11 @@ pub fn prompt_note_path(notes: &[Note]) -> anyhow::Result<&Note> {
     }
 }
 
-fn open_note(vault_name: &str, note_path: &Path) -> anyhow::Result<()> {
-    open::that(obsidian_uri(vault_name, note_path)).context("Failed to launch Obsidian URI")?;
-    Ok(())
-}
-
-fn obsidian_uri(vault_name: &str, note_path: &Path) -> String {
-    let path_str = note_path.to_string_lossy();
-    let encoded_path = encode(&path_str);
-    format!("obsidian://open?vault={vault_name}&file={encoded_path}")
-}
-
-pub fn unlinked_selector(pairs: Vec<UnlinkedPair>, config: &Config) -> anyhow::Result<()> {
+pub fn unlinked_selector(
+    pairs: Vec<UnlinkedPair>,
+    config: &Config,
+    open_with: OpenStrategy,
+) -> anyhow::Result<()> {
     if pairs.is_empty() {
         println!("No unlinked pairs found");
         return Ok(());
@@ -184,11 +187,13 @@ pub fn unlinked_selector(pairs: Vec<UnlinkedPair>, config: &Config) -> anyhow::R
 
             match action_selection {
                 Some(0) => {
-                    open_note(&config.vault, &pair.path_a)?;
+                    let context = read_note(config, &pair.path_b);
+                    open_note(config, open_with, &pair.path_a, context.as_deref())?;
                     continue;
                 }
                 Some(1) => {
-                    open_note(&config.vault, &pair.path_b)?;
+                    let context = read_note(config, &pair.path_a);
+                    open_note(config, open_with, &pair.path_b, context.as_deref())?;
                     continue;
                 }
                 Some(2) => {
@@ -215,3 +220,7 @@ pub fn unlinked_selector(pairs: Vec<UnlinkedPair>, config: &Config) -> anyhow::R
 
     Ok(())
 }
+
+fn read_note(config: &Config, note_path: &Path) -> Option<String> {
+    std::fs::read_to_string(config.notes_root.join(note_path)).ok()
+}
@@ -0,0 +1,308 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use async_openai::{
+    config::OpenAIConfig,
+    types::chat::{
+        ChatCompletionRequestMessage, ChatCompletionRequestUserMessage,
+        CreateChatCompletionRequestArgs,
+    },
+    Client,
+};
+use clap::ValueEnum;
+use futures::{stream, StreamExt, TryStreamExt};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+// Passages are truncated so that long notes don't blow up the cost of LLM scoring
+const MAX_PASSAGE_CHARS: usize = 2000;
+// Scoring requests in flight at once, one per candidate
+const LLM_CONCURRENCY: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RerankMethod {
+    /// Keep the cosine similarity order
+    None,
+    /// Score each candidate with a chat completion prompt
+    Llm,
+    /// Score candidates with a cross-encoder served over HTTP
+    CrossEncoder,
+}
+
+pub struct Candidate {
+    pub note_path: PathBuf,
+    pub similarity: f32,
+    pub rerank_score: Option<f32>,
+}
+
+/// Reorders the candidates by relevance to `query` using the given method.
+/// Candidates are expected in cosine similarity order, which is kept for `RerankMethod::None`.
+pub async fn rerank(
+    config: &Config,
+    method: RerankMethod,
+    query: &str,
+    candidates: Vec<Candidate>,
+) -> anyhow::Result<Vec<Candidate>> {
+    if method == RerankMethod::None || candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    println!(
+        "Reranking {} candidates using {}...",
+        candidates.len(),
+        format!("{method:?}").blue()
+    );
+    let passages = candidates
+        .iter()
+        .map(|c| load_passage(config, c))
+        .collect::<anyhow::Result<Vec<String>>>()?;
+
+    let scores = match method {
+        RerankMethod::None => unreachable!(),
+        RerankMethod::Llm => llm_scores(config, query, &passages).await?,
+        RerankMethod::CrossEncoder => cross_encoder_scores(config, query, &passages).await?,
+    };
+
+    Ok(apply_scores(candidates, scores))
+}
+
+fn load_passage(config: &Config, candidate: &Candidate) -> anyhow::Result<String> {
+    let path = config.notes_root.join(&candidate.note_path);
+    let content = std::fs::read_to_string(&path)
+        .context(format!("Can't read note {}", candidate.note_path.display()))?;
+    let title = candidate
+        .note_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    Ok(passage(&title, &content))
+}
+
+pub fn passage(title: &str, content: &str) -> String {
+    let content: String = content
+        .replace('\n', " ")
+        .trim()
+        .chars()
+        .take(MAX_PASSAGE_CHARS)
+        .collect();
+    format!("Note title: {title}. Note content: {content}")
+}
+
+/// Candidates without a score keep their cosine rank, the rest are sorted by score around them
+fn apply_scores(candidates: Vec<Candidate>, scores: Vec<Option<f32>>) -> Vec<Candidate> {
+    let mut scored = vec![];
+    let mut slots = vec![];
+    for (candidate, score) in candidates.into_iter().zip(scores) {
+        let candidate = Candidate {
+            rerank_score: score,
+            ..candidate
+        };
+        if score.is_some() {
+            scored.push(candidate);
+            slots.push(None);
+        } else {
+            slots.push(Some(candidate));
+        }
+    }
+    // Stable sort keeps the cosine order for candidates with equal scores
+    scored.sort_by(|a, b| {
+        b.rerank_score
+            .unwrap_or_default()
+            .total_cmp(&a.rerank_score.unwrap_or_default())
+    });
+    let mut scored = scored.into_iter();
+    slots
+        .into_iter()
+        .filter_map(|slot| slot.or_else(|| scored.next()))
+        .collect()
+}
+
+async fn llm_scores(
+    config: &Config,
+    query: &str,
+    passages: &[String],
+) -> anyhow::Result<Vec<Option<f32>>> {
+    let client = Client::with_config(OpenAIConfig::new().with_api_key(config.api_key.clone()));
+    stream::iter(passages)
+        .map(|passage| llm_score(&client, &config.rerank.llm_model, query, passage))
+        .buffered(LLM_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+async fn llm_score(
+    client: &Client<OpenAIConfig>,
+    model: &str,
+    query: &str,
+    passage: &str,
+) -> anyhow::Result<Option<f32>> {
+    let prompt = format!(
+        "Rate how relevant the following note is to the search query on a scale from 0 to 10, \
+        where 0 means unrelated and 10 means it answers the query directly. \
+        Respond with the number only.\n\nQuery: {query}\n\n{passage}"
+    );
+    let message: ChatCompletionRequestMessage =
+        ChatCompletionRequestUserMessage::from(prompt).into();
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .temperature(0.0_f32)
+        .messages(vec![message])
+        .build()?;
+
+    let response = client.chat().create(request).await?;
+    let answer = response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.to_owned())
+        .context("No completion returned")?;
+    let score = parse_llm_score(&answer);
+    if score.is_none() {
+        println!(
+            "{} {}",
+            "Unexpected rerank answer, keeping the cosine rank:".red(),
+            answer
+        );
+    }
+    Ok(score)
+}
+
+/// Parses a 0-10 rating into a 0..1 score
+fn parse_llm_score(answer: &str) -> Option<f32> {
+    let number = answer
+        .trim()
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|s| !s.is_empty())?;
+    let rating: f32 = number.parse().ok()?;
+    Some(rating.clamp(0.0, 10.0) / 10.0)
+}
+
+#[derive(Serialize)]
+struct CrossEncoderRequest<'a> {
+    query: &'a str,
+    texts: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct CrossEncoderScore {
+    index: usize,
+    score: f32,
+}
+
+/// Calls a `/rerank` endpoint compatible with Hugging Face text-embeddings-inference
+async fn cross_encoder_scores(
+    config: &Config,
+    query: &str,
+    passages: &[String],
+) -> anyhow::Result<Vec<Option<f32>>> {
+    let url = config
+        .rerank
+        .cross_encoder_url
+        .as_deref()
+        .context("Can't find cross_encoder_url field in config.ini")?;
+
+    let response: Vec<CrossEncoderScore> = reqwest::Client::new()
+        .post(url)
+        .json(&CrossEncoderRequest {
+            query,
+            texts: passages,
+        })
+        .send()
+        .await
+        .context("Failed to call cross-encoder endpoint")?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid cross-encoder response")?;
+
+    let mut scores = vec![None; passages.len()];
+    for item in response {
+        let score = scores.get_mut(item.index).context(format!(
+            "Cross-encoder returned unknown index {}",
+            item.index
+        ))?;
+        *score = Some(item.score);
+    }
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &str, similarity: f32) -> Candidate {
+        Candidate {
+            note_path: PathBuf::from(path),
+            similarity,
+            rerank_score: None,
+        }
+    }
+
+    #[test]
+    fn parse_llm_score_plain_number() {
+        assert_eq!(parse_llm_score("7"), Some(0.7));
+    }
+
+    #[test]
+    fn parse_llm_score_with_surrounding_text() {
+        assert_eq!(parse_llm_score("Score: 8.5/10"), Some(0.85));
+    }
+
+    #[test]
+    fn parse_llm_score_clamps_out_of_range() {
+        assert_eq!(parse_llm_score("42"), Some(1.0));
+    }
+
+    #[test]
+    fn parse_llm_score_rejects_non_numeric() {
+        assert_eq!(parse_llm_score("very relevant"), None);
+    }
+
+    #[test]
+    fn apply_scores_sorts_by_rerank_score() {
+        let candidates = vec![
+            candidate("a.md", 0.9),
+            candidate("b.md", 0.8),
+            candidate("c.md", 0.7),
+        ];
+        let reranked = apply_scores(candidates, vec![Some(0.2), Some(0.9), Some(0.5)]);
+        let paths: Vec<_> = reranked.iter().map(|c| c.note_path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("b.md"),
+                PathBuf::from("c.md"),
+                PathBuf::from("a.md")
+            ]
+        );
+        assert_eq!(reranked[0].similarity, 0.8);
+    }
+
+    #[test]
+    fn apply_scores_keeps_cosine_order_on_ties() {
+        let candidates = vec![candidate("a.md", 0.9), candidate("b.md", 0.8)];
+        let reranked = apply_scores(candidates, vec![Some(0.5), Some(0.5)]);
+        assert_eq!(reranked[0].note_path, PathBuf::from("a.md"));
+    }
+
+    #[test]
+    fn apply_scores_keeps_cosine_rank_of_unscored() {
+        let candidates = vec![
+            candidate("a.md", 0.9),
+            candidate("b.md", 0.8),
+            candidate("c.md", 0.7),
+        ];
+        let reranked = apply_scores(candidates, vec![Some(0.2), None, Some(0.9)]);
+        let paths: Vec<_> = reranked.iter().map(|c| c.note_path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("c.md"),
+                PathBuf::from("b.md"),
+                PathBuf::from("a.md")
+            ]
+        );
+        assert_eq!(reranked[1].rerank_score, None);
+    }
+}
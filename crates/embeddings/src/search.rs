@@ -4,16 +4,23 @@ use anyhow::Context;
 use owo_colors::OwoColorize;
 
 use async_openai::{config::OpenAIConfig, types::embeddings::CreateEmbeddingRequestArgs, Client};
-use try_partialord::TrySort;
 
 use crate::{
     common::{collect_notes, file_to_note, load_embeddings, note_to_checksum},
     config::{self, Config},
+    opener::OpenStrategy,
     prompt::{prompt_note_path, prompt_query, result_selector, NoteListItem},
+    rerank::{passage, rerank, Candidate, RerankMethod},
     types::Embedding,
 };
 
-pub async fn query(config: &Config, query: Option<&str>) -> anyhow::Result<()> {
+pub async fn query(
+    config: &Config,
+    query: Option<&str>,
+    rerank_method: Option<RerankMethod>,
+    open_with: Option<OpenStrategy>,
+) -> anyhow::Result<()> {
+    let rerank_method = rerank_method.unwrap_or(config.rerank.search);
     let query = match query {
         Some(q) => q.to_owned(),
         None => prompt_query()?,
@@ -27,14 +34,16 @@ pub async fn query(config: &Config, query: Option<&str>) -> anyhow::Result<()> {
 
     let parse_start = Instant::now();
 
-    let mut embeddings: Vec<Embedding> =
+    let embeddings: Vec<Embedding> =
         load_embeddings(config).context("Failed to load embeddings from file")?;
     let parse_duration = parse_start.elapsed();
 
+    let candidate_count = match rerank_method {
+        RerankMethod::None => 10,
+        _ => config.rerank.candidates,
+    };
     let sort_start = Instant::now();
-    embeddings
-        .try_sort_by_cached_key(|e| Some(-cosine_similarity(&e.embedding, &query_embedding)))?;
-    let embeddings = embeddings;
+    let candidates = rank_by_similarity(&embeddings, &query_embedding, candidate_count);
     let sort_duration = sort_start.elapsed();
 
     println!();
@@ -43,23 +52,25 @@ pub async fn query(config: &Config, query: Option<&str>) -> anyhow::Result<()> {
     println!("Sort time: {:?}", sort_duration.green());
     println!("Note count: {}", embeddings.len().to_string().green());
 
+    let candidates = rerank(config, rerank_method, &query, candidates).await?;
+
     println!();
     println!("Best matches for {}:", query.yellow());
 
-    let items = embeddings
-        .iter()
-        .take(10)
-        .map(|e| NoteListItem {
-            note_path: e.note_path.to_path_buf(),
-            similarity: cosine_similarity(&e.embedding, &query_embedding),
-        })
-        .collect();
-    result_selector(items, config, 0)?;
+    let items = candidates.into_iter().take(10).map(to_list_item).collect();
+    let open_with = open_with.unwrap_or(config.open.strategy);
+    result_selector(items, config, open_with, &query, 0)?;
 
     Ok(())
 }
 
-pub fn related(config: &Config, note_path: &Option<String>) -> anyhow::Result<()> {
+pub async fn related(
+    config: &Config,
+    note_path: &Option<String>,
+    rerank_method: Option<RerankMethod>,
+    open_with: Option<OpenStrategy>,
+) -> anyhow::Result<()> {
+    let rerank_method = rerank_method.unwrap_or(config.rerank.related);
     let note_path = match note_path {
         Some(path) => PathBuf::from_str(path)?,
         None => {
@@ -78,7 +89,7 @@ pub fn related(config: &Config, note_path: &Option<String>) -> anyhow::Result<()
     let abs_path = config.notes_root.join(&note_path);
     let note = file_to_note(&abs_path, &config.notes_root)?;
 
-    let mut embeddings: Vec<Embedding> =
+    let embeddings: Vec<Embedding> =
         load_embeddings(config).context("Failed to load embeddings from file")?;
 
     let note_embedding = embeddings
@@ -87,28 +98,60 @@ pub fn related(config: &Config, note_path: &Option<String>) -> anyhow::Result<()
         .context(format!("Can't find {} in local embeddings. Perhaps the file contents changed and it needs a rebuild?", display_path.yellow()))?
         .to_owned();
 
-    embeddings.try_sort_by_cached_key(|e| {
-        Some(-cosine_similarity(&e.embedding, &note_embedding.embedding))
-    })?;
-    let embeddings = embeddings;
+    let candidate_count = match rerank_method {
+        RerankMethod::None => 50,
+        _ => config.rerank.candidates,
+    };
+    // The note itself is the most similar one
+    let candidates =
+        rank_by_similarity(&embeddings, &note_embedding.embedding, candidate_count + 1)
+            .into_iter()
+            .filter(|c| c.note_path != note_path)
+            .take(candidate_count)
+            .collect();
+    let query = passage(&note.title, &note.text_content);
+    let candidates = rerank(config, rerank_method, &query, candidates).await?;
 
     println!();
     println!("Best matches for {}:", display_path.yellow());
-    let items = embeddings
+    let items = candidates.into_iter().map(to_list_item).collect();
+    let open_with = open_with.unwrap_or(config.open.strategy);
+    result_selector(items, config, open_with, &note.text_content, 0)?;
+
+    Ok(())
+}
+
+fn to_list_item(candidate: Candidate) -> NoteListItem {
+    NoteListItem {
+        note_path: candidate.note_path,
+        similarity: candidate.similarity,
+        rerank_score: candidate.rerank_score,
+    }
+}
+
+/// Returns the `count` most similar notes to `target` in descending cosine similarity order
+pub fn rank_by_similarity(
+    embeddings: &[Embedding],
+    target: &[f32],
+    count: usize,
+) -> Vec<Candidate> {
+    let mut scored: Vec<(&Embedding, f32)> = embeddings
         .iter()
-        .skip(1)
-        .take(50)
-        .map(|e| NoteListItem {
+        .map(|e| (e, cosine_similarity(&e.embedding, target)))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+        .into_iter()
+        .take(count)
+        .map(|(e, similarity)| Candidate {
             note_path: e.note_path.to_path_buf(),
-            similarity: cosine_similarity(&e.embedding, &note_embedding.embedding),
+            similarity,
+            rerank_score: None,
         })
-        .collect();
-    result_selector(items, config, 0)?;
-
-    Ok(())
+        .collect()
 }
 
-async fn get_query_embedding(api_key: &str, query: &str) -> anyhow::Result<Vec<f32>> {
+pub async fn get_query_embedding(api_key: &str, query: &str) -> anyhow::Result<Vec<f32>> {
     let client = Client::with_config(OpenAIConfig::new().with_api_key(api_key));
     let request = CreateEmbeddingRequestArgs::default()
         .model(config::EMBEDDING_MODEL)
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -6,9 +7,10 @@ use std::time::Instant;
 use owo_colors::OwoColorize;
 use rayon::prelude::*;
 
-use crate::common::{collect_notes, load_embeddings};
+use crate::common::{collect_notes, load_embeddings, words};
 use crate::config::Config;
 use crate::graph::{LinkGraph, PathPair};
+use crate::opener::OpenStrategy;
 use crate::prompt::unlinked_selector;
 use crate::search::cosine_similarity;
 
@@ -18,6 +20,8 @@ pub struct UnlinkedPair {
     pub similarity: f32,
 }
 
+const MAX_PASSAGE_CHARS: usize = 240;
+
 fn is_excluded(path: &Path, exclude_prefixes: &[PathBuf]) -> bool {
     for prefix in exclude_prefixes {
         if path.starts_with(prefix) {
@@ -27,6 +31,16 @@ fn is_excluded(path: &Path, exclude_prefixes: &[PathBuf]) -> bool {
     false
 }
 
+/// The most specific folder override wins, otherwise the global threshold applies
+fn threshold_for(path: &Path, default: f32, overrides: &[(PathBuf, u8)]) -> f32 {
+    overrides
+        .iter()
+        .filter(|(folder, _)| path.starts_with(folder))
+        .max_by_key(|(folder, _)| folder.components().count())
+        .map(|(_, threshold)| *threshold as f32 / 100.0)
+        .unwrap_or(default)
+}
+
 pub async fn find_unlinked(
     config: &Config,
     threshold: f32,
@@ -68,6 +82,11 @@ pub async fn find_unlinked(
         linked_set_start.elapsed().green()
     );
 
+    let thresholds: Vec<f32> = filtered_embeddings
+        .iter()
+        .map(|e| threshold_for(&e.note_path, threshold, &config.unlinked_thresholds))
+        .collect();
+
     let compare_start = Instant::now();
     let total_comparisons = (filtered_embeddings.len() * (filtered_embeddings.len() - 1)) / 2;
     let embeddings_len = filtered_embeddings.len();
@@ -78,6 +97,7 @@ pub async fn find_unlinked(
     // Each worker thread clones the Arc to get its own reference handle.
     let embeddings_arc = Arc::new(filtered_embeddings);
     let linked_pairs_arc = Arc::new(linked_pairs);
+    let thresholds_arc = Arc::new(thresholds);
 
     let all_pairs: Vec<UnlinkedPair> = (0..embeddings_len - 1)
         .into_par_iter()
@@ -86,6 +106,7 @@ pub async fn find_unlinked(
             // Arc::clone is O(1) - just atomic increment, no data copy.
             let embeddings_ref = Arc::clone(&embeddings_arc);
             let linked_ref = Arc::clone(&linked_pairs_arc);
+            let thresholds_ref = Arc::clone(&thresholds_arc);
 
             let mut local_pairs = Vec::new();
 
@@ -100,7 +121,8 @@ pub async fn find_unlinked(
 
                 let similarity =
                     cosine_similarity(&embeddings_ref[i].embedding, &embeddings_ref[j].embedding);
-                if similarity >= threshold {
+                // Both notes have to be similar enough by their own folder's standard
+                if similarity >= thresholds_ref[i].max(thresholds_ref[j]) {
                     local_pairs.push(UnlinkedPair {
                         path_a: path_a.clone(),
                         path_b: path_b.clone(),
@@ -129,10 +151,13 @@ pub async fn handle_unlinked(
     config: &Config,
     output: Option<&str>,
     threshold: u8,
+    top_k: usize,
     exclude_patterns: &[String],
+    open_with: Option<OpenStrategy>,
 ) -> anyhow::Result<()> {
     let threshold_val = threshold as f32 / 100.0;
     let pairs = find_unlinked(config, threshold_val, exclude_patterns).await?;
+    let pairs = limit_per_note(pairs, top_k);
 
     if pairs.is_empty() {
         println!(
@@ -143,56 +168,152 @@ pub async fn handle_unlinked(
     }
 
     if let Some(output_path) = output {
-        write_unlinked_markdown(output_path, &pairs, threshold)?;
+        write_unlinked_markdown(config, output_path, &pairs, threshold, top_k)?;
         println!("Wrote {} suggestions to {}", pairs.len(), output_path);
     } else {
-        unlinked_selector(pairs, config)?;
+        let open_with = open_with.unwrap_or(config.open.strategy);
+        unlinked_selector(pairs, config, open_with)?;
     }
 
     Ok(())
 }
 
+/// Keeps a pair only if it is among the `k` most similar unlinked pairs of at least one of its notes.
+/// Expects `pairs` sorted by descending similarity.
+fn limit_per_note(pairs: Vec<UnlinkedPair>, k: usize) -> Vec<UnlinkedPair> {
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    pairs
+        .into_iter()
+        .filter(|pair| {
+            let rank_a = *seen.get(&pair.path_a).unwrap_or(&0);
+            let rank_b = *seen.get(&pair.path_b).unwrap_or(&0);
+            *seen.entry(pair.path_a.clone()).or_default() += 1;
+            *seen.entry(pair.path_b.clone()).or_default() += 1;
+            rank_a < k || rank_b < k
+        })
+        .collect()
+}
+
+/// Groups pairs by note, keeping the `k` most similar partners of each note
+fn group_by_note(pairs: &[UnlinkedPair], k: usize) -> Vec<(&Path, Vec<(&Path, f32)>)> {
+    let mut groups: Vec<(&Path, Vec<(&Path, f32)>)> = vec![];
+    let mut index: HashMap<&Path, usize> = HashMap::new();
+    for pair in pairs {
+        for (note, other) in [(&pair.path_a, &pair.path_b), (&pair.path_b, &pair.path_a)] {
+            let i = *index.entry(note.as_path()).or_insert_with(|| {
+                groups.push((note.as_path(), vec![]));
+                groups.len() - 1
+            });
+            if groups[i].1.len() < k {
+                groups[i].1.push((other.as_path(), pair.similarity));
+            }
+        }
+    }
+    groups
+}
+
+fn display_name(path: &Path, ambiguous_titles: &HashSet<String>) -> String {
+    let title = path.file_stem().unwrap_or_default().to_string_lossy();
+    if ambiguous_titles.contains(title.as_ref()) {
+        path.with_extension("").display().to_string()
+    } else {
+        title.to_string()
+    }
+}
+
 fn write_unlinked_markdown(
+    config: &Config,
     path: &str,
     pairs: &[UnlinkedPair],
     threshold: u8,
+    top_k: usize,
 ) -> anyhow::Result<()> {
-    let mut content = format!("# Unlinked but similar note pairs\n\nFound {} unlinked similar note pairs (similarity > {}%)\n\n", pairs.len(), threshold);
+    let groups = group_by_note(pairs, top_k);
+
+    let mut titles = HashSet::new();
+    let ambiguous_titles: HashSet<String> = groups
+        .iter()
+        .map(|(note, _)| note.file_stem().unwrap_or_default().to_string_lossy())
+        .filter(|title| !titles.insert(title.to_string()))
+        .map(|title| title.to_string())
+        .collect();
 
-    for pair in pairs {
-        let title_a = pair
-            .path_a
-            .file_stem()
-            .unwrap_or_default()
-            .to_string_lossy();
-        let title_b = pair
-            .path_b
-            .file_stem()
-            .unwrap_or_default()
-            .to_string_lossy();
-        let similarity_pct = (pair.similarity * 100.0).round() as u8;
-
-        let display_a = if title_a == title_b {
-            pair.path_a.display().to_string()
-        } else {
-            title_a.to_string()
-        };
-        let display_b = if title_a == title_b {
-            pair.path_b.display().to_string()
-        } else {
-            title_b.to_string()
-        };
+    let mut contents: HashMap<&Path, String> = HashMap::new();
+    for (note, _) in &groups {
+        let text = fs::read_to_string(config.notes_root.join(note)).unwrap_or_default();
+        contents.insert(*note, text);
+    }
+
+    let mut content = format!(
+        "# Unlinked but similar notes\n\nFound {} unlinked similar note pairs (similarity > {}%, at most {} per note)\n",
+        pairs.len(),
+        threshold,
+        top_k
+    );
 
+    for (note, similar) in &groups {
+        let note_text = &contents[note];
         content.push_str(&format!(
-            "- [[{}]] <-> [[{}]] ({}%)\n",
-            display_a, display_b, similarity_pct
+            "\n## [[{}]]\n\n",
+            display_name(note, &ambiguous_titles)
         ));
+
+        for (other, similarity) in similar {
+            let other_text = &contents[other];
+            let similarity_pct = (similarity * 100.0).round() as u8;
+            content.push_str(&format!(
+                "- [[{}]] ({}%)\n",
+                display_name(other, &ambiguous_titles),
+                similarity_pct
+            ));
+            if let Some(passage) = best_passage(note_text, other_text) {
+                content.push_str(&format!("    > {passage}\n"));
+            }
+            if let Some(passage) = best_passage(other_text, note_text) {
+                content.push_str(&format!("    >\n    > *{passage}*\n"));
+            }
+        }
     }
 
     fs::write(path, content)?;
     Ok(())
 }
 
+/// Picks the paragraph of `text` sharing the most vocabulary with `other`.
+/// This is a lexical approximation, so that the report doesn't need an embedding per paragraph.
+fn best_passage(text: &str, other: &str) -> Option<String> {
+    let body = strip_frontmatter(text);
+    let other_words = words(strip_frontmatter(other));
+
+    let passage = body
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let paragraph_words = words(p);
+            let overlap = paragraph_words.intersection(&other_words).count();
+            let score = overlap as f32 / (paragraph_words.len().max(1) as f32).sqrt();
+            (p, overlap, score)
+        })
+        .filter(|(_, overlap, _)| *overlap > 0)
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(p, _, _)| p)?;
+
+    let passage = passage.split_whitespace().collect::<Vec<_>>().join(" ");
+    if passage.chars().count() > MAX_PASSAGE_CHARS {
+        let truncated: String = passage.chars().take(MAX_PASSAGE_CHARS).collect();
+        Some(format!("{}…", truncated.trim_end()))
+    } else {
+        Some(passage)
+    }
+}
+
+fn strip_frontmatter(text: &str) -> &str {
+    text.strip_prefix("---\n")
+        .and_then(|rest| rest.find("\n---").map(|end| &rest[end + 4..]))
+        .unwrap_or(text)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,6 +374,103 @@ mod tests {
         assert!(!is_excluded(&path, &excludes));
     }
 
+    fn pair(a: &str, b: &str, similarity: f32) -> UnlinkedPair {
+        UnlinkedPair {
+            path_a: PathBuf::from(a),
+            path_b: PathBuf::from(b),
+            similarity,
+        }
+    }
+
+    #[test]
+    fn test_threshold_for_uses_most_specific_folder() {
+        let overrides = vec![
+            (PathBuf::from("Journal"), 80),
+            (PathBuf::from("Journal/Daily"), 90),
+        ];
+        assert_eq!(
+            threshold_for(Path::new("Journal/Daily/2024-01-16.md"), 0.7, &overrides),
+            0.9
+        );
+        assert_eq!(
+            threshold_for(Path::new("Journal/Weekly/2024-W03.md"), 0.7, &overrides),
+            0.8
+        );
+        assert_eq!(
+            threshold_for(Path::new("Resources/Rust.md"), 0.7, &overrides),
+            0.7
+        );
+    }
+
+    #[test]
+    fn test_limit_per_note_keeps_top_k_of_either_note() {
+        let pairs = vec![
+            pair("a.md", "b.md", 0.95),
+            pair("a.md", "c.md", 0.90),
+            pair("a.md", "d.md", 0.85),
+            pair("e.md", "f.md", 0.80),
+        ];
+        let limited = limit_per_note(pairs, 1);
+        let kept: Vec<_> = limited
+            .iter()
+            .map(|p| (p.path_a.to_str().unwrap(), p.path_b.to_str().unwrap()))
+            .collect();
+        // a-c and a-d are still the best match of c and d
+        assert_eq!(
+            kept,
+            vec![
+                ("a.md", "b.md"),
+                ("a.md", "c.md"),
+                ("a.md", "d.md"),
+                ("e.md", "f.md")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_per_note_drops_pairs_outside_both_top_k() {
+        let pairs = vec![
+            pair("a.md", "b.md", 0.95),
+            pair("a.md", "c.md", 0.90),
+            pair("b.md", "c.md", 0.85),
+        ];
+        let limited = limit_per_note(pairs, 1);
+        assert_eq!(limited.len(), 2);
+        assert!(limited
+            .iter()
+            .all(|p| !(p.path_a == Path::new("b.md") && p.path_b == Path::new("c.md"))));
+    }
+
+    #[test]
+    fn test_group_by_note_limits_partners() {
+        let pairs = vec![
+            pair("a.md", "b.md", 0.95),
+            pair("a.md", "c.md", 0.90),
+            pair("b.md", "c.md", 0.85),
+        ];
+        let groups = group_by_note(&pairs, 1);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].0, Path::new("a.md"));
+        assert_eq!(groups[0].1, vec![(Path::new("b.md"), 0.95)]);
+        assert_eq!(groups[2].0, Path::new("c.md"));
+        assert_eq!(groups[2].1, vec![(Path::new("a.md"), 0.90)]);
+    }
+
+    #[test]
+    fn test_best_passage_picks_overlapping_paragraph() {
+        let text = "---\ntags: [rust]\n---\nShopping list for the weekend\n\nBorrow checker rules and lifetimes in Rust";
+        let other = "Notes about Rust lifetimes and the borrow checker";
+        assert_eq!(
+            best_passage(text, other),
+            Some("Borrow checker rules and lifetimes in Rust".to_string())
+        );
+    }
+
+    #[test]
+    fn test_best_passage_none_without_overlap() {
+        assert_eq!(best_passage("Apples and pears", "Rust lifetimes"), None);
+    }
+
     #[test]
     fn test_is_excluded_nested_directory() {
         let path = PathBuf::from("/Notes/Journal/2024/01/16.md");
@@ -6,109 +6,384 @@ use std::{
 };
 
 use anyhow::Context;
-use ignore::Walk;
+use dialoguer::{theme::ColorfulTheme, Confirm, Select};
+use obsidian_export::serde_yaml::{Mapping, Value};
 use percent_encoding::percent_decode_str;
 use pulldown_cmark::{Event, Options, Parser, Tag};
-use regex::Regex;
 
-pub fn run_clean(root: &str, dry_run: bool) -> anyhow::Result<()> {
-    let root_path = Path::new(root).canonicalize().unwrap();
-    let (notes, other_files) = collect_files(&root_path);
-    let notes_set: HashSet<PathBuf> = notes.into_iter().collect();
-    let other_files_set: HashSet<PathBuf> = other_files.into_iter().collect();
+use crate::{
+    trash::{format_size, move_to_trash, new_run_id, restore, VAULT_TRASH_DIR},
+    vault::{all_vault_files, resolve_link, wikilinks, Note, VaultIndex},
+};
 
-    let mut referenced_files = vec![];
+/// Frontmatter fields holding attachments by default, as used by popular Obsidian plugins
+pub const ATTACHMENT_FIELDS: [&str; 3] = ["cover", "image", "banner"];
 
-    for note in &notes_set {
-        let mut files = collect_referenced_files(note).with_context(|| {
-            format!("Failed to collect referenced files in {}", &note.display())
-        })?;
-        referenced_files.append(&mut files);
+pub struct CleanOptions<'a> {
+    pub dry_run: bool,
+    /// Frontmatter fields referencing attachments
+    pub fields: &'a [String],
+    /// Defaults to the `.trash` folder of the vault
+    pub trash: Option<&'a str>,
+    /// Skip the confirmation prompt
+    pub yes: bool,
+}
+
+pub fn run_clean(root: &str, options: &CleanOptions) -> anyhow::Result<()> {
+    let root_path = Path::new(root).canonicalize().unwrap();
+    let (sources, other_files) = collect_files(&root_path);
+    let index = VaultIndex::from_paths(sources.iter().chain(&other_files).cloned());
+
+    let mut referenced_files_set: HashSet<PathBuf> = HashSet::new();
+    for source in &sources {
+        let files = collect_referenced_files(&root_path, source, &index, options.fields)
+            .with_context(|| {
+                format!(
+                    "Failed to collect referenced files in {}",
+                    &source.display()
+                )
+            })?;
+        referenced_files_set.extend(files);
     }
-    let referenced_files = referenced_files;
 
-    if referenced_files.is_empty() {
+    if referenced_files_set.is_empty() {
         println!("No file references found in notes");
         return Ok(());
     }
 
-    let referenced_files_set: HashSet<PathBuf> = referenced_files.into_iter().collect();
-
     println!("Referenced files from notes:");
     for file in &referenced_files_set {
-        println!("- {}", file.strip_prefix(&root_path).unwrap().display())
+        println!("- {}", file.display())
     }
     println!();
 
-    let unreferenced_files_set = other_files_set.difference(&referenced_files_set);
+    let mut unreferenced_files: Vec<(PathBuf, u64)> = other_files
+        .into_iter()
+        .filter(|file| !referenced_files_set.contains(file))
+        .map(|file| {
+            let size = fs::metadata(root_path.join(&file)).map_or(0, |m| m.len());
+            (file, size)
+        })
+        .collect();
+    unreferenced_files.sort();
+
+    if unreferenced_files.is_empty() {
+        println!("No unreferenced files found");
+        return Ok(());
+    }
     println!("Unreferenced files found:");
-    for file in unreferenced_files_set {
-        print!("- {}", file.strip_prefix(&root_path).unwrap().display());
+    for (file, size) in &unreferenced_files {
+        println!("- {} ({})", file.display(), format_size(*size));
+    }
+    let total: u64 = unreferenced_files.iter().map(|(_, size)| size).sum();
+    println!(
+        "Total: {} files, {}",
+        unreferenced_files.len(),
+        format_size(total)
+    );
+    println!();
 
-        if !dry_run {
-            fs::remove_file(file)
-                .with_context(|| format!("Failed to remove {}", file.display()))?;
-            print!(" => deleted")
-        }
-        println!();
+    if options.dry_run {
+        return Ok(());
     }
 
+    let files = if options.yes {
+        unreferenced_files
+            .into_iter()
+            .map(|(file, _)| file)
+            .collect()
+    } else {
+        confirm_files(unreferenced_files)?
+    };
+    if files.is_empty() {
+        println!("Nothing moved to the trash");
+        return Ok(());
+    }
+
+    let trash_dir = trash_dir(&root_path, options.trash);
+    let run_id = new_run_id(&trash_dir);
+    let log = move_to_trash(&root_path, &trash_dir, &run_id, &files)?;
+    println!(
+        "Moved {} files ({}) to {}",
+        log.files.len(),
+        format_size(log.total_size()),
+        trash_dir.join(&run_id).display()
+    );
+    println!("Undo with: gardener clean --root {root} --restore {run_id}");
+
+    Ok(())
+}
+
+pub fn run_restore(root: &str, trash: Option<&str>, run_id: &str) -> anyhow::Result<()> {
+    let root_path = Path::new(root).canonicalize()?;
+    let log = restore(&root_path, &trash_dir(&root_path, trash), run_id)?;
+    println!(
+        "Restored {} files ({})",
+        log.files.len(),
+        format_size(log.total_size())
+    );
     Ok(())
 }
 
-// First item is list of Markdown files, the second is the rest
-fn collect_files(root: &PathBuf) -> (Vec<PathBuf>, Vec<PathBuf>) {
-    Walk::new(root)
-        .filter_map(|result| {
-            let entry = result.expect("Error iterating over files");
-            let metadata = entry.metadata().unwrap();
-            match metadata.is_dir() {
-                true => None,
-                false => Some(entry.path().to_path_buf()),
+fn trash_dir(root: &Path, trash: Option<&str>) -> PathBuf {
+    match trash {
+        Some(trash) => PathBuf::from(trash),
+        None => root.join(VAULT_TRASH_DIR),
+    }
+}
+
+/// Asks whether to trash all files, pick them one by one, or none of them
+fn confirm_files(files: Vec<(PathBuf, u64)>) -> anyhow::Result<Vec<PathBuf>> {
+    let options = vec!["Move all to the trash", "Confirm each file", "Cancel"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&options)
+        .default(0)
+        .with_prompt(format!("Move {} files to the trash?", files.len()))
+        .interact_opt()?;
+
+    match selection {
+        Some(0) => Ok(files.into_iter().map(|(file, _)| file).collect()),
+        Some(1) => {
+            let mut confirmed = vec![];
+            for (file, size) in files {
+                let prompt = format!("Move {} ({})?", file.display(), format_size(size));
+                if Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(prompt)
+                    .default(true)
+                    .interact()?
+                {
+                    confirmed.push(file);
+                }
             }
-        })
-        .partition(|entry| {
-            let extension = entry
-                .extension()
-                .unwrap_or(OsStr::new(""))
-                .to_str()
-                .unwrap();
-            extension == "md"
-        })
+            Ok(confirmed)
+        }
+        _ => Ok(vec![]),
+    }
 }
 
-fn collect_referenced_files(path: &PathBuf) -> anyhow::Result<Vec<PathBuf>> {
-    let content = fs::read_to_string(path).context("Failed to read file contents")?;
+// First item is the list of notes and canvases (the files referencing others), the second is
+// the rest. Paths are relative to the root.
+/// Notes excluded from exports can still reference attachments, so `.export-ignore` doesn't apply
+fn collect_files(root: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    all_vault_files(root)
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .partition(|path| is_source(path))
+}
 
-    let parser = Parser::new_ext(&content, Options::empty());
-    let mut errors: Vec<anyhow::Error> = vec![];
-    let files: Vec<PathBuf> = parser
-        .filter_map(|e| match e {
-            Event::Start(Tag::Image(_linktype, dest, _title)) => Some(dest.to_string()),
-            Event::Start(Tag::Link(_linktype, dest, _title)) => Some(dest.to_string()),
-            _ => None,
-        })
-        .filter(|dest| !is_web_link(dest))
-        .filter(|dest| !dest.ends_with(".md"))
-        .map(|dest| {
-            let parent = path.parent().context("Note should have a parent folder")?;
-            let dest_decoded = percent_decode_str(&dest).decode_utf8_lossy();
-            let joined = parent.join(dest_decoded.as_ref());
-            let canonical = fs::canonicalize(joined.clone())
-                .context(format!("Problem with path: {joined:?}"))?;
-            Ok(canonical)
-        })
-        .filter_map(|result| result.map_err(|e| errors.push(e)).ok())
+fn is_source(path: &Path) -> bool {
+    let extension = path.extension().unwrap_or(OsStr::new(""));
+    extension == "md" || extension == "canvas"
+}
+
+fn collect_referenced_files(
+    root: &Path,
+    path: &Path,
+    index: &VaultIndex,
+    fields: &[String],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let abs_path = root.join(path);
+    if path.extension() == Some(OsStr::new("canvas")) {
+        let content = fs::read_to_string(&abs_path).context("Failed to read file contents")?;
+        return canvas_references(&content, index);
+    }
+
+    let note = Note::read(root, &abs_path)?;
+    let note_dir = path.parent().unwrap_or(Path::new(""));
+    let mut files = note_references(&note.body, note_dir, index);
+    files.extend(frontmatter_references(
+        &note.frontmatter,
+        fields,
+        note_dir,
+        index,
+    ));
+    Ok(files)
+}
+
+/// Files referenced by wikilinks, embeds and Markdown links and images
+fn note_references(body: &str, note_dir: &Path, index: &VaultIndex) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = wikilinks(body)
+        .iter()
+        .filter_map(|link| index.resolve(link.target))
+        .map(Path::to_path_buf)
         .collect();
 
-    if !errors.is_empty() {
-        println!("Errors: {errors:?}");
+    let parser = Parser::new_ext(body, Options::empty());
+    files.extend(
+        parser
+            .filter_map(|e| match e {
+                Event::Start(Tag::Image(_linktype, dest, _title)) => Some(dest.to_string()),
+                Event::Start(Tag::Link(_linktype, dest, _title)) => Some(dest.to_string()),
+                _ => None,
+            })
+            .filter_map(|dest| resolve_path(&dest, note_dir, index)),
+    );
+    files
+}
+
+/// Markdown links are relative to the note, but Obsidian can also write them relative to the
+/// vault root or as a bare file name
+fn resolve_path(dest: &str, note_dir: &Path, index: &VaultIndex) -> Option<PathBuf> {
+    let (relative, _) = resolve_link(dest, note_dir)?;
+    if index.contains(&relative) {
+        return Some(relative);
     }
+    let path = dest.split('#').next().unwrap_or_default();
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    index.resolve(&decoded).map(Path::to_path_buf)
+}
 
+fn frontmatter_references(
+    frontmatter: &Mapping,
+    fields: &[String],
+    note_dir: &Path,
+    index: &VaultIndex,
+) -> Vec<PathBuf> {
+    let resolve = |value: &str| -> Vec<PathBuf> {
+        let links = wikilinks(value);
+        if links.is_empty() {
+            resolve_path(value.trim(), note_dir, index)
+                .into_iter()
+                .collect()
+        } else {
+            links
+                .iter()
+                .filter_map(|link| index.resolve(link.target))
+                .map(Path::to_path_buf)
+                .collect()
+        }
+    };
+
+    fields
+        .iter()
+        .filter_map(|field| frontmatter.get(field.as_str()))
+        .flat_map(|value| match value {
+            Value::String(value) => resolve(value),
+            Value::Sequence(values) => values
+                .iter()
+                .filter_map(Value::as_str)
+                .flat_map(resolve)
+                .collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// File nodes of a JSON canvas and links in its text nodes
+fn canvas_references(content: &str, index: &VaultIndex) -> anyhow::Result<Vec<PathBuf>> {
+    let canvas: serde_json::Value = serde_json::from_str(content).context("Invalid canvas")?;
+    let nodes = canvas["nodes"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let mut files = vec![];
+    for node in nodes {
+        match node["type"].as_str() {
+            Some("file") => {
+                if let Some(file) = node["file"].as_str() {
+                    files.push(PathBuf::from(file));
+                }
+            }
+            Some("text") => {
+                if let Some(text) = node["text"].as_str() {
+                    files.extend(note_references(text, Path::new(""), index));
+                }
+            }
+            _ => {}
+        }
+    }
     Ok(files)
 }
 
-fn is_web_link(link: &str) -> bool {
-    let re = Regex::new(r"(?i)^[a-z]+://.*").unwrap();
-    re.is_match(link)
+#[cfg(test)]
+mod tests {
+    use obsidian_export::serde_yaml;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn index() -> VaultIndex {
+        VaultIndex::from_paths(
+            [
+                "Notes/Note.md",
+                "Attachments/diagram.png",
+                "Attachments/paper.pdf",
+                "Attachments/cover.jpg",
+                "Notes/local image.png",
+            ]
+            .into_iter()
+            .map(PathBuf::from),
+        )
+    }
+
+    fn paths(items: &[&str]) -> Vec<PathBuf> {
+        items.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn wikilinks_and_embeds_resolve_by_name() {
+        let body = "![[diagram.png]]\nSee [[paper.pdf|the paper]] and [[Note#Heading]]";
+        assert_eq!(
+            note_references(body, Path::new("Notes"), &index()),
+            paths(&[
+                "Attachments/diagram.png",
+                "Attachments/paper.pdf",
+                "Notes/Note.md"
+            ])
+        );
+    }
+
+    #[test]
+    fn markdown_links_resolve_relative_and_by_name() {
+        let body = "![](local%20image.png) ![](diagram.png) [web](https://example.com/a.png)";
+        assert_eq!(
+            note_references(body, Path::new("Notes"), &index()),
+            paths(&["Notes/local image.png", "Attachments/diagram.png"])
+        );
+    }
+
+    #[test]
+    fn frontmatter_fields_are_references() {
+        let frontmatter: Mapping = serde_yaml::from_str(
+            "cover: \"[[cover.jpg]]\"\nimage: [Attachments/paper.pdf]\ntitle: diagram.png",
+        )
+        .unwrap();
+        let fields = ATTACHMENT_FIELDS.map(String::from);
+        assert_eq!(
+            frontmatter_references(&frontmatter, &fields, Path::new("Notes"), &index()),
+            paths(&["Attachments/cover.jpg", "Attachments/paper.pdf"])
+        );
+    }
+
+    #[test]
+    fn canvas_nodes_are_references() {
+        let canvas = r#"{"nodes": [
+            {"id": "1", "type": "file", "file": "Attachments/paper.pdf"},
+            {"id": "2", "type": "text", "text": "![[diagram.png]]"}
+        ], "edges": []}"#;
+        assert_eq!(
+            canvas_references(canvas, &index()).unwrap(),
+            paths(&["Attachments/paper.pdf", "Attachments/diagram.png"])
+        );
+    }
+
+    #[test]
+    fn notes_excluded_from_exports_are_scanned() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Private")).unwrap();
+        fs::create_dir_all(root.join("Attachments")).unwrap();
+        fs::write(root.join(".export-ignore"), "Private/\n").unwrap();
+        fs::write(root.join("Private/Journal.md"), "![[scan.png]]").unwrap();
+        fs::write(root.join("Attachments/scan.png"), "image").unwrap();
+
+        let (sources, other_files) = collect_files(root);
+        assert_eq!(sources, paths(&["Private/Journal.md"]));
+        let index = VaultIndex::from_paths(sources.iter().chain(&other_files).cloned());
+        assert_eq!(
+            collect_referenced_files(root, &sources[0], &index, &[]).unwrap(),
+            paths(&["Attachments/scan.png"])
+        );
+    }
 }
@@ -0,0 +1,368 @@
+use std::{
+    fmt::{Display, Write},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use chrono::{Datelike, Days, Local, Months, NaiveDate};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+pub const DEFAULT_FOLDER: &str = "Daily";
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+const DEFAULT_TEMPLATE: &str = "# {{date:%A, %B %-d, %Y}}\n\n## Tasks\n\n{{tasks}}\n";
+
+lazy_static! {
+    static ref VARIABLE: Regex = Regex::new(r"\{\{\s*(\w+)(?::([^}]+))?\s*\}\}").unwrap();
+    static ref TASK: Regex = Regex::new(r"^\s*[-*+] \[(.)\] (.*)$").unwrap();
+}
+
+/// Daily notes of a vault, named by their date
+pub struct DailyNotes {
+    pub root: PathBuf,
+    /// Relative to the root
+    pub folder: PathBuf,
+    /// File name format of the notes, without the extension
+    pub format: String,
+}
+
+impl DailyNotes {
+    fn name(&self, date: NaiveDate) -> anyhow::Result<String> {
+        format_date(date, &self.format)
+    }
+
+    fn path(&self, date: NaiveDate) -> anyhow::Result<PathBuf> {
+        Ok(self
+            .root
+            .join(&self.folder)
+            .join(format!("{}.md", self.name(date)?)))
+    }
+
+    /// Existing daily notes sorted by date
+    fn notes(&self) -> anyhow::Result<Vec<(NaiveDate, PathBuf)>> {
+        let dir = self.root.join(&self.folder);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut notes = vec![];
+        for entry in fs::read_dir(&dir).context(format!("Can't read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "md") {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                if let Ok(date) = NaiveDate::parse_from_str(&stem, &self.format) {
+                    notes.push((date, path));
+                }
+            }
+        }
+        notes.sort();
+        Ok(notes)
+    }
+}
+
+pub struct DailyOptions<'a> {
+    pub date: NaiveDate,
+    /// Defaults to a heading and the carried over tasks
+    pub template: Option<&'a str>,
+    /// Copy the unchecked tasks of the previous daily note
+    pub carry_over: bool,
+}
+
+pub fn run_daily(notes: &DailyNotes, options: &DailyOptions) -> anyhow::Result<()> {
+    let path = notes.path(options.date)?;
+    if path.exists() {
+        println!("{} already exists", path.display());
+        return Ok(());
+    }
+
+    let template = match options.template {
+        Some(template) => {
+            let template_path = notes.root.join(template);
+            fs::read_to_string(&template_path)
+                .context(format!("Can't read template {}", template_path.display()))?
+        }
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+
+    let mut tasks = vec![];
+    if options.carry_over {
+        let previous = notes
+            .notes()?
+            .into_iter()
+            .rev()
+            .find(|(date, _)| *date < options.date);
+        if let Some((_, previous)) = previous {
+            let content = fs::read_to_string(&previous)?;
+            tasks = unchecked_tasks(&content);
+            println!(
+                "Carried over {} tasks from {}",
+                tasks.len(),
+                previous.display()
+            );
+        }
+    }
+
+    let tasks = tasks.join("\n");
+    let mut content = render_template(&template, notes, options.date, &tasks)?;
+    let has_tasks = VARIABLE
+        .captures_iter(&template)
+        .any(|cap| &cap[1] == "tasks");
+    if !has_tasks && !tasks.is_empty() {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&format!("\n{tasks}\n"));
+    }
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, content).context(format!("Failed to write {}", path.display()))?;
+    println!("Created {}", path.display());
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub enum Period {
+    Week,
+    Month,
+}
+
+pub struct RollupOptions<'a> {
+    pub period: Period,
+    /// Any day of the period
+    pub date: NaiveDate,
+    /// Defaults to the folder of the daily notes
+    pub output_folder: Option<&'a str>,
+    /// Overwrite an existing summary
+    pub force: bool,
+}
+
+/// Compiles a summary note linking the daily notes of the period and their completed tasks
+pub fn run_rollup(notes: &DailyNotes, options: &RollupOptions) -> anyhow::Result<()> {
+    let (start, end) = period_range(options.period, options.date);
+    let name = match options.period {
+        Period::Week => format_date(start, "%G-W%V")?,
+        Period::Month => format_date(start, "%Y-%m")?,
+    };
+    let folder = options
+        .output_folder
+        .map_or(notes.folder.clone(), PathBuf::from);
+    let path = notes.root.join(folder).join(format!("{name}.md"));
+    if path.exists() && !options.force {
+        anyhow::bail!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        );
+    }
+
+    let mut days = vec![];
+    for (date, note) in notes.notes()? {
+        if date < start || date > end {
+            continue;
+        }
+        let content = fs::read_to_string(&note)?;
+        let link = note
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        days.push((link, completed_tasks(&content)));
+    }
+    if days.is_empty() {
+        println!("No daily notes between {start} and {end}");
+        return Ok(());
+    }
+
+    let title = match options.period {
+        Period::Week => format!(
+            "Week {}, {}",
+            start.iso_week().week(),
+            start.iso_week().year()
+        ),
+        Period::Month => format_date(start, "%B %Y")?,
+    };
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, rollup_content(&title, &days))
+        .context(format!("Failed to write {}", path.display()))?;
+    println!("Created {} from {} daily notes", path.display(), days.len());
+    Ok(())
+}
+
+fn period_range(period: Period, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    match period {
+        Period::Week => {
+            let from_monday = date.weekday().num_days_from_monday() as u64;
+            let start = date - Days::new(from_monday);
+            (start, start + Days::new(6))
+        }
+        Period::Month => {
+            let start = date.with_day(1).unwrap();
+            (start, start + Months::new(1) - Days::new(1))
+        }
+    }
+}
+
+fn rollup_content(title: &str, days: &[(String, Vec<String>)]) -> String {
+    let mut content = format!("# {title}\n\n## Daily notes\n\n");
+    for (link, _) in days {
+        content.push_str(&format!("- [[{link}]]\n"));
+    }
+    content.push_str("\n## Completed tasks\n\n");
+    let mut any_completed = false;
+    for (link, tasks) in days {
+        for task in tasks {
+            content.push_str(&format!("- {task} ([[{link}]])\n"));
+            any_completed = true;
+        }
+    }
+    if !any_completed {
+        content.push_str("None\n");
+    }
+    content
+}
+
+/// Replaces `{{date}}`, `{{title}}`, `{{weekday}}`, `{{yesterday}}`, `{{tomorrow}}`, `{{time}}`
+/// and `{{tasks}}`, dates take a strftime format like `{{date:%B %-d}}`
+fn render_template(
+    template: &str,
+    notes: &DailyNotes,
+    date: NaiveDate,
+    tasks: &str,
+) -> anyhow::Result<String> {
+    let mut error = None;
+    let rendered = VARIABLE.replace_all(template, |cap: &Captures| {
+        let format = cap.get(2).map(|f| f.as_str().trim());
+        let value = match &cap[1] {
+            "date" => format_date(date, format.unwrap_or(&notes.format)),
+            "title" => notes.name(date),
+            "tasks" => Ok(tasks.to_string()),
+            "weekday" => format_date(date, "%A"),
+            "yesterday" => format_date(date - Days::new(1), format.unwrap_or(&notes.format)),
+            "tomorrow" => format_date(date + Days::new(1), format.unwrap_or(&notes.format)),
+            "time" => {
+                let format = format.unwrap_or("%H:%M");
+                checked_format(Local::now().format(format), format)
+            }
+            _ => return cap[0].to_string(),
+        };
+        value.unwrap_or_else(|e| {
+            error = Some(e);
+            cap[0].to_string()
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(rendered.into_owned()),
+    }
+}
+
+fn format_date(date: NaiveDate, format: &str) -> anyhow::Result<String> {
+    checked_format(date.format(format), format)
+}
+
+/// Fails on invalid formats instead of panicking like `to_string()`
+fn checked_format(formatted: impl Display, format: &str) -> anyhow::Result<String> {
+    let mut output = String::new();
+    write!(output, "{formatted}").map_err(|_| anyhow::anyhow!("Invalid date format {format}"))?;
+    Ok(output)
+}
+
+/// Subtasks stay indented relative to the shallowest carried task
+fn unchecked_tasks(content: &str) -> Vec<String> {
+    let tasks: Vec<&str> = content
+        .lines()
+        .filter(|line| TASK.captures(line).is_some_and(|cap| &cap[1] == " "))
+        .collect();
+    let indent = tasks
+        .iter()
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    tasks
+        .into_iter()
+        .map(|line| line[indent..].to_string())
+        .collect()
+}
+
+fn completed_tasks(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| TASK.captures(line))
+        .filter(|cap| cap[1].eq_ignore_ascii_case("x"))
+        .map(|cap| cap[2].to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes() -> DailyNotes {
+        DailyNotes {
+            root: PathBuf::new(),
+            folder: PathBuf::from(DEFAULT_FOLDER),
+            format: DEFAULT_FORMAT.to_string(),
+        }
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, DEFAULT_FORMAT).unwrap()
+    }
+
+    #[test]
+    fn template_variables() {
+        let template = "# {{date}} ({{weekday}})\n{{date:%B %-d}} [[{{yesterday}}]] {{ tomorrow }} {{tasks}} {{unknown}}";
+        assert_eq!(
+            render_template(template, &notes(), date("2024-03-01"), "- [ ] a").unwrap(),
+            "# 2024-03-01 (Friday)\nMarch 1 [[2024-02-29]] 2024-03-02 - [ ] a {{unknown}}"
+        );
+    }
+
+    #[test]
+    fn invalid_format_is_an_error() {
+        assert!(render_template("{{date:%Q}}", &notes(), date("2024-03-01"), "").is_err());
+    }
+
+    #[test]
+    fn carried_subtasks_keep_relative_indentation() {
+        let content = "- [x] done parent\n    - [ ] open\n        - [ ] nested\n    - [ ] sibling";
+        assert_eq!(
+            unchecked_tasks(content),
+            vec!["- [ ] open", "    - [ ] nested", "- [ ] sibling"]
+        );
+    }
+
+    #[test]
+    fn tasks_by_status() {
+        let content = "- [ ] open\n  - [ ] nested\n- [x] done\n* [X] also done\n- [-] cancelled\n- not a task";
+        assert_eq!(
+            unchecked_tasks(content),
+            vec!["- [ ] open", "  - [ ] nested"]
+        );
+        assert_eq!(completed_tasks(content), vec!["done", "also done"]);
+    }
+
+    #[test]
+    fn period_ranges() {
+        assert_eq!(
+            period_range(Period::Week, date("2024-03-01")),
+            (date("2024-02-26"), date("2024-03-03"))
+        );
+        assert_eq!(
+            period_range(Period::Month, date("2024-02-10")),
+            (date("2024-02-01"), date("2024-02-29"))
+        );
+    }
+
+    #[test]
+    fn rollup_links_days_and_tasks() {
+        let days = vec![
+            ("2024-02-26".to_string(), vec!["Ship it".to_string()]),
+            ("2024-02-27".to_string(), vec![]),
+        ];
+        assert_eq!(
+            rollup_content("Week 9, 2024", &days),
+            "# Week 9, 2024\n\n## Daily notes\n\n- [[2024-02-26]]\n- [[2024-02-27]]\n\n## Completed tasks\n\n- Ship it ([[2024-02-26]])\n"
+        );
+    }
+}
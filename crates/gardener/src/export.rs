@@ -1,36 +1,171 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
 
 use anyhow::Context;
 use obsidian_export::Context as ObsidianContext;
 use obsidian_export::{serde_yaml, Exporter, MarkdownEvents, PostprocessorResult, WalkOptions};
 
+use crate::{
+    graph::{create_backlinks_appender, LinkGraph},
+    guard::{self, GuardMode},
+    images::ImageOptions,
+    links::{create_link_demoter, LinkReport},
+    manifest,
+    rules::{NoteInfo, PublishFilter},
+    syntax::{
+        create_block_id_stripper, create_callout_converter, create_comment_stripper,
+        create_highlight_converter, create_math_preserver,
+    },
+    target::{create_site_frontmatter, create_site_links, Site, Target},
+    vault::{markdown_files, Note},
+};
+
 // TODO:
 // - Handle line breaks?
 // - PR for configurable warnings
 
-const FRONTMATTER_KEY_TAGS: &str = "tags";
-const FRONTMATTER_TAG_INCLUDE: &str = "public";
+pub struct ExportOptions<'a> {
+    pub rules: Option<&'a str>,
+    pub profile: &'a str,
+    pub unpublished_link_text: Option<&'a str>,
+    pub target: Target,
+    pub dry_run: bool,
+    pub guard: GuardMode,
+    /// Resize and re-encode the exported images
+    pub images: Option<ImageOptions>,
+    /// Append a "Linked from" section to the notes
+    pub backlinks: bool,
+    /// Write the links between the notes to graph.json
+    pub graph: bool,
+}
 
-pub fn run_export(root: &str, dest: &str) -> anyhow::Result<()> {
+pub fn run_export(root: &str, dest: &str, options: &ExportOptions) -> anyhow::Result<()> {
     let root = PathBuf::from(root);
     let destination = PathBuf::from(dest);
     let walk_options = WalkOptions::new();
 
-    ensure_destination_dir(&destination).context("Failed to prepare destination dir")?;
+    let publish_filter = match options.rules {
+        Some(rules) => PublishFilter::load(Path::new(rules), options.profile)?,
+        None => PublishFilter::public_tag(),
+    };
+
+    // Links are only rewritten once the full set of exported notes is known
+    let published = collect_published_notes(&root, &publish_filter)?;
+
+    if options.guard != GuardMode::Off {
+        let findings = guard::audit(&root, &published, &publish_filter.guard)?;
+        if !findings.is_empty() {
+            guard::print_findings(&findings);
+            if options.guard == GuardMode::Fail {
+                anyhow::bail!("Export blocked by the privacy guard, see the problems above");
+            }
+        }
+    }
+    let site = Site::new(&root, options.target, &published, options.images.clone())?;
+
+    let graph = if options.backlinks || options.graph {
+        Some(LinkGraph::build(&root, &published)?)
+    } else {
+        None
+    };
+
+    if !options.dry_run {
+        ensure_destination_dir(&destination).context("Failed to prepare destination dir")?;
+    }
+    let staging = StagingDir::create().context("Failed to prepare staging dir")?;
 
-    let mut exporter = Exporter::new(root, destination);
+    // obsidian-export copies every attachment of the vault, so the final layout is assembled
+    // from a staging dir with only the referenced attachments
+    let mut exporter = Exporter::new(root.clone(), staging.0.clone());
     exporter.walk_options(walk_options);
 
-    let filter_processor = create_frontmatter_filter();
+    let filter_processor = create_frontmatter_filter(&root, &publish_filter, &published);
     exporter.add_postprocessor(&filter_processor);
 
+    // Comments go first so that nothing inside them is converted or reported
+    let syntax = publish_filter.syntax;
+    let comment_processor = create_comment_stripper();
+    if syntax.comments {
+        exporter.add_postprocessor(&comment_processor);
+    }
+    let math_processor = create_math_preserver();
+    if syntax.math {
+        exporter.add_postprocessor(&math_processor);
+    }
+    let highlight_processor = create_highlight_converter();
+    if syntax.highlights {
+        exporter.add_postprocessor(&highlight_processor);
+    }
+    let block_id_processor = create_block_id_stripper();
+    if syntax.block_ids {
+        exporter.add_postprocessor(&block_id_processor);
+    }
+    let callout_processor = create_callout_converter(options.target);
+    if syntax.callouts {
+        exporter.add_postprocessor(&callout_processor);
+    }
+
+    let link_report = LinkReport::default();
+    let link_processor = create_link_demoter(
+        &root,
+        &published,
+        options.unpublished_link_text,
+        &link_report,
+    );
+    exporter.add_postprocessor(&link_processor);
+
     let title_processor = create_title_appender();
     exporter.add_postprocessor(&title_processor);
 
+    let backlinks_processor = graph
+        .as_ref()
+        .filter(|_| options.backlinks)
+        .map(|graph| create_backlinks_appender(&root, graph));
+    if let Some(backlinks_processor) = &backlinks_processor {
+        exporter.add_postprocessor(backlinks_processor);
+    }
+
+    let site_frontmatter_processor = create_site_frontmatter(&root, &site);
+    exporter.add_postprocessor(&site_frontmatter_processor);
+
+    let site_links_processor = create_site_links(&root, &site);
+    exporter.add_postprocessor(&site_links_processor);
+
     exporter.run()?;
+    let mut outputs = site.outputs(&root, &staging.0)?;
+    if let Some(graph) = graph.as_ref().filter(|_| options.graph) {
+        outputs.push(graph.output(&site, &staging.0)?);
+    }
+    let summary = manifest::sync(&destination, &outputs, options.dry_run)?;
+    link_report.print();
+    summary.print(options.dry_run);
     Ok(())
 }
 
+/// Temporary export location, removed when dropped
+struct StagingDir(PathBuf);
+
+impl StagingDir {
+    fn create() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!("gardener-export-{}", process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(StagingDir(path))
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
 fn ensure_destination_dir(destination: &PathBuf) -> io::Result<()> {
     let exists = destination.try_exists()?;
     if !exists {
@@ -39,19 +174,40 @@ fn ensure_destination_dir(destination: &PathBuf) -> io::Result<()> {
     Ok(())
 }
 
-fn create_frontmatter_filter(
-) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
-    move |context, _events| match context.frontmatter.get(FRONTMATTER_KEY_TAGS) {
-        Some(serde_yaml::Value::Sequence(tags)) => {
-            if tags.contains(&serde_yaml::to_value(FRONTMATTER_TAG_INCLUDE).unwrap()) {
-                // Remove all tags, they are not public-facing
-                context.frontmatter.remove(FRONTMATTER_KEY_TAGS);
-                PostprocessorResult::Continue
-            } else {
-                PostprocessorResult::StopAndSkipNote
-            }
+/// Vault-relative paths of the notes passing the publish filter
+fn collect_published_notes(
+    root: &Path,
+    publish_filter: &PublishFilter,
+) -> anyhow::Result<HashSet<PathBuf>> {
+    let mut published = HashSet::new();
+    for path in markdown_files(root) {
+        let note = Note::read(root, &path)?;
+        let info = NoteInfo {
+            path: &note.path,
+            tags: note.tags(),
+            frontmatter: &note.frontmatter,
+        };
+        if publish_filter.is_published(&info) {
+            published.insert(note.path);
+        }
+    }
+    Ok(published)
+}
+
+fn create_frontmatter_filter<'a>(
+    root: &'a Path,
+    publish_filter: &'a PublishFilter,
+    published: &'a HashSet<PathBuf>,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult + 'a {
+    move |context, _events| {
+        let current_file = context.current_file().to_owned();
+        let path = current_file.strip_prefix(root).unwrap_or(&current_file);
+        if published.contains(path) {
+            publish_filter.transform_frontmatter(&mut context.frontmatter);
+            PostprocessorResult::Continue
+        } else {
+            PostprocessorResult::StopAndSkipNote
         }
-        _ => PostprocessorResult::StopAndSkipNote,
     }
 }
 
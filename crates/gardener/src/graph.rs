@@ -0,0 +1,288 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use obsidian_export::serde_yaml::{Mapping, Value};
+use obsidian_export::Context as ObsidianContext;
+use obsidian_export::{MarkdownEvents, PostprocessorResult};
+use pulldown_cmark::{CowStr, Event, HeadingLevel, LinkType, Parser, Tag};
+use serde::Serialize;
+
+use crate::{
+    manifest::OutputFile,
+    target::Site,
+    vault::{link_destination, resolve_link, wikilinks, Note, VaultIndex},
+};
+
+const BACKLINKS_HEADING: &str = "Linked from";
+const GRAPH_FILENAME: &str = "graph.json";
+// Inside the staging dir, so that it can't clash with an attachment of the vault
+const GRAPH_DIR: &str = ".graph";
+
+/// Links between the published notes
+pub struct LinkGraph {
+    titles: BTreeMap<PathBuf, String>,
+    /// Notes linked from each note
+    links: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+#[derive(Serialize)]
+struct GraphJson<'a> {
+    nodes: Vec<GraphNode<'a>>,
+    edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize)]
+struct GraphNode<'a> {
+    /// URL of the note on the site, output path for plain exports
+    id: String,
+    title: &'a str,
+    /// Relative to the vault root
+    path: &'a Path,
+}
+
+#[derive(Serialize)]
+struct GraphEdge {
+    source: String,
+    target: String,
+}
+
+impl LinkGraph {
+    pub fn build(root: &Path, published: &HashSet<PathBuf>) -> anyhow::Result<Self> {
+        let index = VaultIndex::build(root);
+        let mut graph = LinkGraph {
+            titles: BTreeMap::new(),
+            links: BTreeMap::new(),
+        };
+        for path in published {
+            let note = Note::read(root, &root.join(path))?;
+            graph
+                .titles
+                .insert(path.to_owned(), note_title(path, &note.frontmatter));
+            graph.links.insert(
+                path.to_owned(),
+                note_links(&note.body, path, &index, published),
+            );
+        }
+        Ok(graph)
+    }
+
+    /// Notes linking to `path`, sorted by title
+    pub fn backlinks(&self, path: &Path) -> Vec<&Path> {
+        let mut sources: Vec<&Path> = self
+            .links
+            .iter()
+            .filter(|(_, targets)| targets.contains(path))
+            .map(|(source, _)| source.as_path())
+            .collect();
+        sources.sort_by_key(|source| self.title(source));
+        sources
+    }
+
+    fn title(&self, path: &Path) -> &str {
+        self.titles.get(path).map_or("", String::as_str)
+    }
+
+    /// Writes the nodes and edges for the site's backlinks and graph view into `staging`
+    pub fn output(&self, site: &Site, staging: &Path) -> anyhow::Result<OutputFile> {
+        let graph = GraphJson {
+            nodes: self
+                .titles
+                .iter()
+                .map(|(path, title)| GraphNode {
+                    id: node_id(site, path),
+                    title,
+                    path,
+                })
+                .collect(),
+            edges: self
+                .links
+                .iter()
+                .flat_map(|(source, targets)| {
+                    targets.iter().map(move |target| GraphEdge {
+                        source: node_id(site, source),
+                        target: node_id(site, target),
+                    })
+                })
+                .collect(),
+        };
+
+        let from = staging.join(GRAPH_DIR).join(GRAPH_FILENAME);
+        fs::create_dir_all(from.parent().unwrap())?;
+        fs::write(&from, serde_json::to_string_pretty(&graph)?)
+            .context(format!("Failed to write {}", from.display()))?;
+        Ok(OutputFile {
+            source: PathBuf::from(GRAPH_FILENAME),
+            from,
+            output: site.static_output(Path::new(GRAPH_FILENAME)),
+            keep_existing: false,
+        })
+    }
+}
+
+fn node_id(site: &Site, path: &Path) -> String {
+    site.note_url(path)
+        .unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+fn note_title(path: &Path, frontmatter: &Mapping) -> String {
+    match frontmatter.get("title").and_then(Value::as_str) {
+        Some(title) => title.to_string(),
+        None => path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Published notes linked from the note at `path` with wikilinks or Markdown links
+fn note_links(
+    body: &str,
+    path: &Path,
+    index: &VaultIndex,
+    published: &HashSet<PathBuf>,
+) -> BTreeSet<PathBuf> {
+    let note_dir = path.parent().unwrap_or(Path::new(""));
+    let mut targets: BTreeSet<PathBuf> = wikilinks(body)
+        .iter()
+        .filter_map(|link| index.resolve(link.target))
+        .map(Path::to_path_buf)
+        .collect();
+    for event in Parser::new(body) {
+        if let Event::Start(Tag::Link(_, dest, _)) = event {
+            if let Some((target, _)) = resolve_link(&dest, note_dir) {
+                targets.insert(target);
+            }
+        }
+    }
+    targets.retain(|target| target != path && published.contains(target));
+    targets
+}
+
+/// Appends a list of the notes linking to the current note. Runs before the site links are
+/// rewritten, so the links point to the notes in the vault.
+pub fn create_backlinks_appender<'a>(
+    root: &'a Path,
+    graph: &'a LinkGraph,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult + 'a {
+    move |context, events| {
+        let current_file = context.current_file().to_owned();
+        let path = current_file.strip_prefix(root).unwrap_or(&current_file);
+        let backlinks = graph.backlinks(path);
+        if !backlinks.is_empty() {
+            events.extend(backlinks_section(graph, path, &backlinks));
+        }
+        PostprocessorResult::Continue
+    }
+}
+
+fn backlinks_section<'e>(graph: &LinkGraph, path: &Path, backlinks: &[&Path]) -> Vec<Event<'e>> {
+    let note_dir = path.parent().unwrap_or(Path::new(""));
+    let heading = Tag::Heading(HeadingLevel::H2, None, vec![]);
+    let mut events = vec![
+        Event::Start(heading.clone()),
+        Event::Text(BACKLINKS_HEADING.into()),
+        Event::End(heading),
+        Event::Start(Tag::List(None)),
+    ];
+    for source in backlinks {
+        let link = Tag::Link(
+            LinkType::Inline,
+            link_destination(note_dir, source).into(),
+            CowStr::Borrowed(""),
+        );
+        events.extend([
+            Event::Start(Tag::Item),
+            Event::Start(link.clone()),
+            Event::Text(graph.title(source).to_string().into()),
+            Event::End(link),
+            Event::End(Tag::Item),
+        ]);
+    }
+    events.push(Event::End(Tag::List(None)));
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn published() -> HashSet<PathBuf> {
+        ["Notes/A.md", "Notes/B.md", "Other/C.md"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    fn graph() -> LinkGraph {
+        let published = published();
+        let index = VaultIndex::from_paths(
+            published
+                .iter()
+                .cloned()
+                .chain([PathBuf::from("Private.md")]),
+        );
+        let notes = [
+            ("Notes/A.md", "Zeta", "[[B]] [[Private]] [[A#Heading]]"),
+            ("Notes/B.md", "Beta", "[C](../Other/C.md)"),
+            ("Other/C.md", "Alpha", "[[B|the B note]]"),
+        ];
+        LinkGraph {
+            titles: notes
+                .iter()
+                .map(|(path, title, _)| (PathBuf::from(path), title.to_string()))
+                .collect(),
+            links: notes
+                .iter()
+                .map(|(path, _, body)| {
+                    let path = Path::new(path);
+                    (
+                        path.to_path_buf(),
+                        note_links(body, path, &index, &published),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn links_to_published_notes() {
+        let index = VaultIndex::from_paths(published());
+        assert_eq!(
+            note_links(
+                "[[B]] [[A]] [C](../Other/C.md#top) [[Missing]] [web](https://example.com)",
+                Path::new("Notes/A.md"),
+                &index,
+                &published()
+            ),
+            BTreeSet::from([PathBuf::from("Notes/B.md"), PathBuf::from("Other/C.md")])
+        );
+    }
+
+    #[test]
+    fn backlinks_sorted_by_title() {
+        let graph = graph();
+        assert_eq!(
+            graph.backlinks(Path::new("Notes/B.md")),
+            vec![Path::new("Other/C.md"), Path::new("Notes/A.md")]
+        );
+        assert!(graph.backlinks(Path::new("Notes/A.md")).is_empty());
+    }
+
+    #[test]
+    fn backlinks_section_links_relative_to_note() {
+        let graph = graph();
+        let section =
+            backlinks_section(&graph, Path::new("Notes/B.md"), &[Path::new("Other/C.md")]);
+        assert!(section.contains(&Event::Start(Tag::Link(
+            LinkType::Inline,
+            "../Other/C.md".into(),
+            "".into()
+        ))));
+        assert!(section.contains(&Event::Text("Alpha".into())));
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use directories::ProjectDirs;
+use image::{
+    codecs::{
+        avif::AvifEncoder,
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType as PngFilter, PngEncoder},
+    },
+    imageops::FilterType,
+    DynamicImage, ImageDecoder, ImageReader,
+};
+use sha2::{Digest, Sha256};
+
+use crate::trash::format_size;
+
+// GIFs can be animated and SVGs are not raster images, those are copied as they are
+const OPTIMIZED_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImageFormat {
+    /// Lossy WebP
+    Webp,
+    Avif,
+    /// Optimised JPEG without transparency
+    Jpeg,
+    /// Keep the format, only resize and strip metadata
+    Original,
+}
+
+#[derive(Clone)]
+pub struct ImageOptions {
+    pub format: ImageFormat,
+    /// Wider images are scaled down, keeping the aspect ratio
+    pub max_width: Option<u32>,
+    /// 1-100, used by the lossy formats
+    pub quality: u8,
+    /// Optimised images are stored here by the hash of their content and the options
+    pub cache_dir: PathBuf,
+}
+
+impl ImageOptions {
+    pub fn default_cache_dir() -> PathBuf {
+        match ProjectDirs::from("com.oliverfalvai.homecooked", "", "gardener") {
+            Some(project_dirs) => project_dirs.cache_dir().join("images"),
+            None => env::temp_dir().join("gardener-images"),
+        }
+    }
+
+    /// Extension of the optimised image, `None` if the file is not optimised
+    pub fn output_extension(&self, path: &Path) -> Option<String> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        if !OPTIMIZED_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+        Some(match self.format {
+            ImageFormat::Webp => "webp".to_string(),
+            ImageFormat::Avif => "avif".to_string(),
+            ImageFormat::Jpeg => "jpg".to_string(),
+            ImageFormat::Original => extension,
+        })
+    }
+
+    /// Path of the optimised version of `source`, processed only if it's not in the cache yet
+    pub fn optimize(&self, source: &Path) -> anyhow::Result<PathBuf> {
+        let extension = self
+            .output_extension(source)
+            .context(format!("{} is not a supported image", source.display()))?;
+        let content = fs::read(source).context(format!("Can't read {}", source.display()))?;
+        let cached = self
+            .cache_dir
+            .join(format!("{}.{extension}", self.cache_key(&content)));
+        if cached.exists() {
+            return Ok(cached);
+        }
+
+        let encoded = self
+            .encode(&content, &extension)
+            .context(format!("Failed to optimise {}", source.display()))?;
+        fs::create_dir_all(&self.cache_dir)?;
+        // Renamed into place so that an interrupted run doesn't leave a broken cache entry
+        let temp = cached.with_extension("tmp");
+        fs::write(&temp, &encoded).context(format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &cached)?;
+        println!(
+            "Optimised {}: {} -> {}",
+            source.display(),
+            format_size(content.len() as u64),
+            format_size(encoded.len() as u64)
+        );
+        Ok(cached)
+    }
+
+    fn cache_key(&self, content: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(content);
+        hasher.update(format!(
+            "{:?}/{:?}/{}",
+            self.format, self.max_width, self.quality
+        ));
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Decoding and encoding again drops all metadata, including the EXIF GPS position
+    fn encode(&self, content: &[u8], extension: &str) -> anyhow::Result<Vec<u8>> {
+        let mut decoder = ImageReader::new(Cursor::new(content))
+            .with_guessed_format()?
+            .into_decoder()?;
+        // Phone photos are often stored rotated, with the orientation only in the metadata
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+
+        if let Some(max_width) = self.max_width {
+            if image.width() > max_width {
+                image = image.resize(max_width, u32::MAX, FilterType::Lanczos3);
+            }
+        }
+
+        let mut output = vec![];
+        match extension {
+            "webp" => {
+                let image = DynamicImage::ImageRgba8(image.to_rgba8());
+                let encoder = webp::Encoder::from_image(&image)
+                    .map_err(|e| anyhow::anyhow!("Can't encode WebP: {e}"))?;
+                return Ok(encoder.encode(self.quality as f32).to_vec());
+            }
+            "avif" => {
+                let encoder = AvifEncoder::new_with_speed_quality(&mut output, 6, self.quality);
+                without_unsupported_channels(image).write_with_encoder(encoder)?;
+            }
+            "png" => {
+                let encoder = PngEncoder::new_with_quality(
+                    &mut output,
+                    CompressionType::Best,
+                    PngFilter::Adaptive,
+                );
+                image.write_with_encoder(encoder)?;
+            }
+            _ => {
+                // JPEG has no transparency
+                let image = DynamicImage::ImageRgb8(image.to_rgb8());
+                let encoder = JpegEncoder::new_with_quality(&mut output, self.quality);
+                image.write_with_encoder(encoder)?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// The AVIF encoder only takes 8-bit RGB(A) images
+fn without_unsupported_channels(image: DynamicImage) -> DynamicImage {
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn options(format: ImageFormat, cache_dir: PathBuf) -> ImageOptions {
+        ImageOptions {
+            format,
+            max_width: Some(40),
+            quality: 80,
+            cache_dir,
+        }
+    }
+
+    #[test]
+    fn only_raster_images_are_optimised() {
+        let webp = options(ImageFormat::Webp, PathBuf::new());
+        assert_eq!(
+            webp.output_extension(Path::new("a/Photo.JPG")),
+            Some("webp".to_string())
+        );
+        assert_eq!(webp.output_extension(Path::new("a/anim.gif")), None);
+        assert_eq!(webp.output_extension(Path::new("a/paper.pdf")), None);
+
+        let original = options(ImageFormat::Original, PathBuf::new());
+        assert_eq!(
+            original.output_extension(Path::new("a/Photo.JPG")),
+            Some("jpg".to_string())
+        );
+    }
+
+    #[test]
+    fn resized_and_cached() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("photo.png");
+        RgbImage::new(100, 50).save(&source).unwrap();
+
+        let jpeg = options(ImageFormat::Jpeg, dir.path().join("cache"));
+        let optimized = jpeg.optimize(&source).unwrap();
+        assert_eq!(optimized.extension().unwrap(), "jpg");
+        assert_eq!(image::image_dimensions(&optimized).unwrap(), (40, 20));
+
+        let modified = fs::metadata(&optimized).unwrap().modified().unwrap();
+        assert_eq!(jpeg.optimize(&source).unwrap(), optimized);
+        assert_eq!(
+            fs::metadata(&optimized).unwrap().modified().unwrap(),
+            modified
+        );
+    }
+}
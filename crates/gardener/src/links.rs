@@ -0,0 +1,183 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use obsidian_export::Context as ObsidianContext;
+use obsidian_export::{MarkdownEvents, PostprocessorResult};
+use pulldown_cmark::{CowStr, Event, Tag};
+
+use crate::vault::{is_markdown, resolve_link};
+
+pub struct DemotedLink {
+    pub text: String,
+    pub target: PathBuf,
+}
+
+/// Demoted links of each exported note, filled in by the postprocessor
+#[derive(Default)]
+pub struct LinkReport {
+    demoted: Mutex<BTreeMap<PathBuf, Vec<DemotedLink>>>,
+}
+
+impl LinkReport {
+    pub fn print(&self) {
+        let demoted = self.demoted.lock().unwrap();
+        if demoted.is_empty() {
+            return;
+        }
+
+        let count: usize = demoted.values().map(Vec::len).sum();
+        println!("Demoted {count} links to unpublished notes:");
+        for (note, links) in demoted.iter() {
+            println!("{}", note.display());
+            for link in links {
+                println!("  - \"{}\" -> {}", link.text, link.target.display());
+            }
+        }
+        println!();
+    }
+}
+
+/// Turns links pointing to notes outside of the published set into plain text, or into
+/// `placeholder` if given. Needs to run after the note itself passed the publish filter.
+pub fn create_link_demoter<'a>(
+    root: &'a Path,
+    published: &'a HashSet<PathBuf>,
+    placeholder: Option<&'a str>,
+    report: &'a LinkReport,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult + 'a {
+    move |context, events| {
+        let current_file = context.current_file().to_owned();
+        let note_path = current_file.strip_prefix(root).unwrap_or(&current_file);
+        let note_dir = note_path.parent().unwrap_or(Path::new(""));
+
+        let demoted = demote_links(events, note_dir, published, placeholder);
+        if !demoted.is_empty() {
+            report
+                .demoted
+                .lock()
+                .unwrap()
+                .insert(note_path.to_path_buf(), demoted);
+        }
+        PostprocessorResult::Continue
+    }
+}
+
+fn demote_links(
+    events: &mut MarkdownEvents,
+    note_dir: &Path,
+    published: &HashSet<PathBuf>,
+    placeholder: Option<&str>,
+) -> Vec<DemotedLink> {
+    let mut demoted = vec![];
+    let mut output = Vec::with_capacity(events.len());
+    let mut current: Option<(PathBuf, String)> = None;
+
+    for event in events.drain(..) {
+        match event {
+            Event::Start(Tag::Link(_, ref dest, _)) if current.is_none() => {
+                match link_target(dest, note_dir) {
+                    Some(target) if !published.contains(&target) => {
+                        current = Some((target, String::new()));
+                        if let Some(placeholder) = placeholder {
+                            output.push(Event::Text(CowStr::from(placeholder.to_string())));
+                        }
+                    }
+                    _ => output.push(event),
+                }
+            }
+            Event::End(Tag::Link(..)) if current.is_some() => {
+                let (target, text) = current.take().unwrap();
+                demoted.push(DemotedLink { text, target });
+            }
+            event => {
+                if let Some((_, text)) = current.as_mut() {
+                    if let Event::Text(t) | Event::Code(t) = &event {
+                        text.push_str(t);
+                    }
+                    if placeholder.is_some() {
+                        continue;
+                    }
+                }
+                output.push(event);
+            }
+        }
+    }
+
+    *events = output;
+    demoted
+}
+
+/// Vault-relative path of the note a link points to, `None` for anything else
+fn link_target(dest: &str, note_dir: &Path) -> Option<PathBuf> {
+    resolve_link(dest, note_dir)
+        .map(|(target, _)| target)
+        .filter(|target| is_markdown(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::LinkType;
+
+    use super::*;
+
+    fn link_events(dest: &'static str, text: &'static str) -> MarkdownEvents<'static> {
+        vec![
+            Event::Text("See ".into()),
+            Event::Start(Tag::Link(LinkType::Inline, dest.into(), "".into())),
+            Event::Text(text.into()),
+            Event::End(Tag::Link(LinkType::Inline, dest.into(), "".into())),
+        ]
+    }
+
+    #[test]
+    fn link_target_resolves_relative_paths() {
+        let dir = Path::new("Blog/2024");
+        assert_eq!(
+            link_target("../Private%20note.md#Heading", dir),
+            Some(PathBuf::from("Blog/Private note.md"))
+        );
+        assert_eq!(link_target("image.png", dir), None);
+        assert_eq!(link_target("https://example.com/a.md", dir), None);
+        assert_eq!(link_target("#heading", dir), None);
+    }
+
+    #[test]
+    fn unpublished_links_become_text() {
+        let published = HashSet::from([PathBuf::from("public.md")]);
+        let mut events = link_events("private.md", "Private");
+        let demoted = demote_links(&mut events, Path::new(""), &published, None);
+
+        assert_eq!(
+            events,
+            vec![Event::Text("See ".into()), Event::Text("Private".into())]
+        );
+        assert_eq!(demoted.len(), 1);
+        assert_eq!(demoted[0].text, "Private");
+        assert_eq!(demoted[0].target, PathBuf::from("private.md"));
+    }
+
+    #[test]
+    fn unpublished_links_use_placeholder() {
+        let published = HashSet::new();
+        let mut events = link_events("private.md", "Private");
+        demote_links(&mut events, Path::new(""), &published, Some("[private]"));
+
+        assert_eq!(
+            events,
+            vec![Event::Text("See ".into()), Event::Text("[private]".into())]
+        );
+    }
+
+    #[test]
+    fn published_links_are_kept() {
+        let published = HashSet::from([PathBuf::from("public.md")]);
+        let mut events = link_events("public.md", "Public");
+        let demoted = demote_links(&mut events, Path::new(""), &published, None);
+
+        assert_eq!(events, link_events("public.md", "Public"));
+        assert!(demoted.is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use obsidian_export::serde_yaml;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    refactor::{relative_files, rename_files},
+    trash::{format_size, move_to_trash, new_run_id, VAULT_TRASH_DIR},
+    vault::{code_ranges, is_markdown, split_frontmatter, wikilinks, VaultIndex},
+};
+
+// Break wikilinks, URLs or file systems of other platforms
+const UNSAFE_CHARS: &[char] = &[
+    '#', '^', '[', ']', '|', '%', '?', '*', ':', '"', '<', '>', '\\',
+];
+
+const DEFAULT_MAX_ATTACHMENT_SIZE_KB: u64 = 5 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    BrokenWikilink,
+    DuplicateTitle,
+    MissingFrontmatter,
+    InvalidYaml,
+    EmptyNote,
+    UnsafeFilename,
+    OversizedAttachment,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl Rule {
+    fn default_severity(self) -> Severity {
+        match self {
+            Rule::BrokenWikilink | Rule::InvalidYaml => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+
+    fn fixable(self) -> bool {
+        matches!(self, Rule::EmptyNote | Rule::UnsafeFilename)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LintConfig {
+    /// Overrides of the default severities
+    rules: HashMap<Rule, Severity>,
+    required_frontmatter: Vec<String>,
+    max_attachment_size_kb: u64,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            rules: HashMap::new(),
+            required_frontmatter: vec![],
+            max_attachment_size_kb: DEFAULT_MAX_ATTACHMENT_SIZE_KB,
+        }
+    }
+}
+
+impl LintConfig {
+    fn load(path: Option<&str>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(LintConfig::default());
+        };
+        let content = fs::read_to_string(path).context(format!("Can't read {path}"))?;
+        serde_yaml::from_str(&content).context("Invalid lint config")
+    }
+
+    fn severity(&self, rule: Rule) -> Severity {
+        self.rules
+            .get(&rule)
+            .copied()
+            .unwrap_or(rule.default_severity())
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub severity: Severity,
+    /// Relative to the vault root
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    pub message: String,
+    pub fixed: bool,
+}
+
+/// Returns whether the vault passed, i.e. there are no unfixed findings of error severity
+pub fn run_lint(
+    root: &str,
+    config_path: Option<&str>,
+    fix: bool,
+    format: OutputFormat,
+) -> anyhow::Result<bool> {
+    let root = Path::new(root).canonicalize()?;
+    let config = LintConfig::load(config_path)?;
+
+    let mut findings: Vec<Finding> = check_vault(&root, &config)?
+        .into_iter()
+        .filter_map(|(rule, path, line, message)| {
+            let severity = config.severity(rule);
+            (severity != Severity::Off).then_some(Finding {
+                rule,
+                severity,
+                path,
+                line,
+                message,
+                fixed: false,
+            })
+        })
+        .collect();
+    findings.sort_by(|a, b| (&a.path, a.line, a.rule).cmp(&(&b.path, b.line, b.rule)));
+
+    if fix {
+        apply_fixes(&root, &mut findings)?;
+    }
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&findings)?),
+        OutputFormat::Text => print_findings(&findings),
+    }
+
+    Ok(!findings
+        .iter()
+        .any(|f| f.severity == Severity::Error && !f.fixed))
+}
+
+type RawFinding = (Rule, PathBuf, Option<usize>, String);
+
+fn check_vault(root: &Path, config: &LintConfig) -> anyhow::Result<Vec<RawFinding>> {
+    let files = relative_files(root);
+    let index = VaultIndex::from_paths(files.iter().cloned());
+    let mut findings = vec![];
+
+    let mut by_name: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+    for path in &files {
+        if let Some(message) = unsafe_filename(path) {
+            findings.push((Rule::UnsafeFilename, path.to_owned(), None, message));
+        }
+
+        if is_markdown(path) {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            by_name.entry(stem.to_lowercase()).or_default().push(path);
+            let content = fs::read_to_string(root.join(path))
+                .context(format!("Can't read {}", path.display()))?;
+            findings.extend(check_note(path, &content, &index, config));
+        } else if path.extension().is_some_and(|ext| ext != "canvas") {
+            let size = fs::metadata(root.join(path))?.len();
+            if size > config.max_attachment_size_kb * 1024 {
+                let message = format!("Attachment is {}", format_size(size));
+                findings.push((Rule::OversizedAttachment, path.to_owned(), None, message));
+            }
+        }
+    }
+
+    for paths in by_name.values().filter(|paths| paths.len() > 1) {
+        for path in paths {
+            let others: Vec<String> = paths
+                .iter()
+                .filter(|other| *other != path)
+                .map(|other| other.display().to_string())
+                .collect();
+            let message = format!(
+                "Same name as {}, links by name are ambiguous",
+                others.join(", ")
+            );
+            findings.push((Rule::DuplicateTitle, path.to_path_buf(), None, message));
+        }
+    }
+
+    Ok(findings)
+}
+
+fn check_note(
+    path: &Path,
+    content: &str,
+    index: &VaultIndex,
+    config: &LintConfig,
+) -> Vec<RawFinding> {
+    let mut findings = vec![];
+    let finding = |rule, line, message| (rule, path.to_path_buf(), line, message);
+
+    let body = match split_frontmatter(content) {
+        Ok((frontmatter, body)) => {
+            for key in &config.required_frontmatter {
+                if frontmatter.get(key.as_str()).is_none() {
+                    let message = format!("Missing frontmatter key {key}");
+                    findings.push(finding(Rule::MissingFrontmatter, Some(1), message));
+                }
+            }
+            body
+        }
+        Err(e) => {
+            findings.push(finding(Rule::InvalidYaml, Some(1), e.to_string()));
+            content
+        }
+    };
+
+    if body.trim().is_empty() {
+        findings.push(finding(Rule::EmptyNote, None, "Note is empty".to_string()));
+    }
+
+    // The body is a suffix of the content
+    let body_offset = content.len() - body.len();
+    let code = code_ranges(body);
+    for link in wikilinks(body) {
+        if link.target.is_empty()
+            || code.iter().any(|range| range.contains(&link.range.start))
+            || index.resolve(link.target).is_some()
+        {
+            continue;
+        }
+        let line = line_number(content, body_offset + link.range.start);
+        let message = format!("Link to missing note or file {}", &body[link.range]);
+        findings.push(finding(Rule::BrokenWikilink, Some(line), message));
+    }
+
+    findings
+}
+
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+fn unsafe_filename(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    let mut found: Vec<char> = name
+        .chars()
+        .filter(|c| UNSAFE_CHARS.contains(c) || c.is_control())
+        .collect();
+    found.sort();
+    found.dedup();
+    if !found.is_empty() {
+        let chars: String = found.into_iter().collect();
+        return Some(format!("File name contains unsafe characters: {chars}"));
+    }
+    if name.trim() != name {
+        return Some("File name starts or ends with whitespace".to_string());
+    }
+    None
+}
+
+fn safe_filename(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| {
+            if UNSAFE_CHARS.contains(&c) || c.is_control() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    replaced.trim().to_string()
+}
+
+fn apply_fixes(root: &Path, findings: &mut [Finding]) -> anyhow::Result<()> {
+    let mut empty_notes = vec![];
+    let mut unsafe_files = vec![];
+    for finding in findings.iter().filter(|f| f.rule.fixable()) {
+        match finding.rule {
+            Rule::EmptyNote => empty_notes.push(finding.path.to_owned()),
+            Rule::UnsafeFilename => unsafe_files.push(finding.path.to_owned()),
+            _ => {}
+        }
+    }
+    // Empty notes go to the trash under their original name
+    unsafe_files.retain(|path| !empty_notes.contains(path));
+
+    let renames = safe_renames(root, &unsafe_files);
+    if !renames.is_empty() {
+        let files = relative_files(root);
+        let index = VaultIndex::from_paths(files.iter().cloned());
+        rename_files(root, &files, &index, &renames, false)?;
+        for finding in findings
+            .iter_mut()
+            .filter(|f| f.rule == Rule::UnsafeFilename && renames.contains_key(&f.path))
+        {
+            finding.fixed = true;
+        }
+        eprintln!("Renamed {} files with unsafe names", renames.len());
+    }
+
+    if !empty_notes.is_empty() {
+        let trash_dir = root.join(VAULT_TRASH_DIR);
+        let run_id = new_run_id(&trash_dir);
+        move_to_trash(root, &trash_dir, &run_id, &empty_notes)?;
+        for finding in findings.iter_mut().filter(|f| f.rule == Rule::EmptyNote) {
+            finding.fixed = true;
+        }
+        eprintln!(
+            "Moved {} empty notes to the trash, undo with: gardener clean --root {} --restore {run_id}",
+            empty_notes.len(),
+            root.display()
+        );
+    }
+    Ok(())
+}
+
+/// Safe names for the files, skipping the ones whose new name is already taken
+fn safe_renames(root: &Path, paths: &[PathBuf]) -> BTreeMap<PathBuf, PathBuf> {
+    let mut targets = HashSet::new();
+    let mut renames = BTreeMap::new();
+    for path in paths {
+        let name = safe_filename(&path.file_name().unwrap_or_default().to_string_lossy());
+        let new_path = path.with_file_name(&name);
+        if name.is_empty() || root.join(&new_path).exists() || !targets.insert(new_path.clone()) {
+            continue;
+        }
+        renames.insert(path.to_owned(), new_path);
+    }
+    renames
+}
+
+fn print_findings(findings: &[Finding]) {
+    for finding in findings {
+        let location = match finding.line {
+            Some(line) => format!("{}:{line}", finding.path.display()),
+            None => finding.path.display().to_string(),
+        };
+        let severity = match finding.severity {
+            Severity::Error => "error",
+            _ => "warning",
+        };
+        let rule = serde_json::to_value(finding.rule).unwrap_or_default();
+        let fixed = if finding.fixed { " (fixed)" } else { "" };
+        println!(
+            "{severity}[{}] {location}: {}{fixed}",
+            rule.as_str().unwrap_or_default(),
+            finding.message
+        );
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    println!();
+    println!(
+        "{} problems ({errors} errors, {} warnings)",
+        findings.len(),
+        findings.len() - errors
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn rules(findings: &[RawFinding]) -> Vec<(Rule, Option<usize>)> {
+        findings.iter().map(|f| (f.0, f.2)).collect()
+    }
+
+    #[test]
+    fn note_checks() {
+        let index = VaultIndex::from_paths([PathBuf::from("Existing.md")]);
+        let config = LintConfig {
+            required_frontmatter: vec!["title".to_string()],
+            ..Default::default()
+        };
+        let content = "---\ndate: 2024-01-01\n---\n\n[[Existing]] and\n[[Missing]] [[#Heading]]";
+        let findings = check_note(Path::new("a.md"), content, &index, &config);
+        assert_eq!(
+            rules(&findings),
+            vec![
+                (Rule::MissingFrontmatter, Some(1)),
+                (Rule::BrokenWikilink, Some(6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_in_code_are_ignored() {
+        let index = VaultIndex::from_paths([]);
+        let config = LintConfig::default();
+        let content = "Inline `[[Missing]]` code\n\n```\n[[Missing]]\n```\n\n[[Missing]]";
+        let findings = check_note(Path::new("a.md"), content, &index, &config);
+        assert_eq!(rules(&findings), vec![(Rule::BrokenWikilink, Some(7))]);
+    }
+
+    #[test]
+    fn notes_excluded_from_exports_are_checked() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Private")).unwrap();
+        fs::write(root.join(".export-ignore"), "Private/\n").unwrap();
+        fs::write(root.join("Private/Journal.md"), "[[Missing]]").unwrap();
+
+        let findings = check_vault(root, &LintConfig::default()).unwrap();
+        assert!(findings
+            .iter()
+            .any(|f| f.0 == Rule::BrokenWikilink && f.1 == Path::new("Private/Journal.md")));
+    }
+
+    #[test]
+    fn fix_renames_unsafe_files_and_updates_links() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("100% done.md"), "Done").unwrap();
+        fs::write(root.join("50% done.md"), "Halfway").unwrap();
+        fs::write(
+            root.join("Index.md"),
+            "[[100% done]] and [half](50%25%20done.md)",
+        )
+        .unwrap();
+
+        let mut findings: Vec<Finding> = ["100% done.md", "50% done.md"]
+            .into_iter()
+            .map(|path| Finding {
+                rule: Rule::UnsafeFilename,
+                severity: Severity::Warning,
+                path: PathBuf::from(path),
+                line: None,
+                message: String::new(),
+                fixed: false,
+            })
+            .collect();
+        apply_fixes(root, &mut findings).unwrap();
+        assert!(findings.iter().all(|f| f.fixed));
+        assert!(root.join("100- done.md").exists());
+        assert!(root.join("50- done.md").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("Index.md")).unwrap(),
+            "[[100- done]] and [half](50-%20done.md)"
+        );
+    }
+
+    #[test]
+    fn invalid_yaml_and_empty_notes() {
+        let index = VaultIndex::from_paths([]);
+        let config = LintConfig::default();
+        let findings = check_note(Path::new("a.md"), "---\na: [\n---\n", &index, &config);
+        assert_eq!(rules(&findings), vec![(Rule::InvalidYaml, Some(1))]);
+
+        let findings = check_note(Path::new("a.md"), "---\na: b\n---\n\n  \n", &index, &config);
+        assert_eq!(rules(&findings), vec![(Rule::EmptyNote, None)]);
+    }
+
+    #[test]
+    fn unsafe_filenames() {
+        assert!(unsafe_filename(Path::new("Notes/C# basics.md")).is_some());
+        assert!(unsafe_filename(Path::new("Notes/What? Why.md")).is_some());
+        assert!(unsafe_filename(Path::new("Notes/Plain name.md")).is_none());
+        assert_eq!(
+            safe_filename("C# basics: part 1?.md"),
+            "C- basics- part 1-.md"
+        );
+    }
+
+    #[test]
+    fn severity_overrides() {
+        let config: LintConfig =
+            serde_yaml::from_str("rules:\n  empty-note: error\n  broken-wikilink: off").unwrap();
+        assert_eq!(config.severity(Rule::EmptyNote), Severity::Error);
+        assert_eq!(config.severity(Rule::BrokenWikilink), Severity::Off);
+        assert_eq!(config.severity(Rule::DuplicateTitle), Severity::Warning);
+    }
+}
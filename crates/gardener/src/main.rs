@@ -1,9 +1,36 @@
+use std::path::PathBuf;
+
+use chrono::{Local, NaiveDate};
 use clap::{Parser, Subcommand};
-use clean::run_clean;
-use export::run_export;
+use clean::{run_clean, run_restore, CleanOptions};
+use daily::{run_daily, run_rollup, DailyNotes, DailyOptions, Period, RollupOptions};
+use export::{run_export, ExportOptions};
+use guard::GuardMode;
+use images::{ImageFormat, ImageOptions};
+use lint::{run_lint, OutputFormat};
+use refactor::{run_move, run_rename, run_retag};
+use rules::normalize_tag;
+use target::Target;
+use tasks::{
+    run_complete, run_tasks, Priority, SortKey, Status, TaskFilter, TaskFormat, TasksOptions,
+};
 
 mod clean;
+mod daily;
 mod export;
+mod graph;
+mod guard;
+mod images;
+mod links;
+mod lint;
+mod manifest;
+mod refactor;
+mod rules;
+mod syntax;
+mod target;
+mod tasks;
+mod trash;
+mod vault;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
@@ -22,15 +49,315 @@ enum Commands {
 
         #[arg(short, long, default_value_t = String::from("./output"), value_name = "PATH")]
         destination: String,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "YAML file with publish rules, defaults to exporting notes tagged public"
+        )]
+        rules: Option<String>,
+
+        #[arg(long, default_value_t = String::from(rules::DEFAULT_PROFILE), requires = "rules")]
+        profile: String,
+
+        #[arg(
+            long,
+            value_name = "TEXT",
+            help = "Replace links to unpublished notes with this text instead of the link text"
+        )]
+        unpublished_link_text: Option<String>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Target::Plain,
+            help = "Frontmatter, link format and folder layout of the output"
+        )]
+        target: Target,
+
+        #[arg(long, help = "Show which files would be written and removed")]
+        dry_run: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = GuardMode::Fail,
+            help = "What to do when published notes embed private notes, attachments or secrets"
+        )]
+        guard: GuardMode,
+
+        #[arg(
+            long,
+            help = "Resize and re-encode exported images, dropping their metadata"
+        )]
+        optimize_images: bool,
+
+        #[arg(long, value_enum, default_value_t = ImageFormat::Webp, requires = "optimize_images")]
+        image_format: ImageFormat,
+
+        #[arg(
+            long,
+            value_name = "PX",
+            requires = "optimize_images",
+            help = "Scale down wider images"
+        )]
+        max_image_width: Option<u32>,
+
+        #[arg(
+            long,
+            default_value_t = 80,
+            value_parser = clap::value_parser!(u8).range(1..=100),
+            requires = "optimize_images"
+        )]
+        image_quality: u8,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "optimize_images",
+            help = "Cache of the optimised images, defaults to the user cache folder"
+        )]
+        image_cache: Option<String>,
+
+        #[arg(long, help = "Append a list of the notes linking to each note")]
+        backlinks: bool,
+
+        #[arg(
+            long,
+            help = "Write the notes and the links between them to graph.json, for backlinks and graph views on the site"
+        )]
+        graph: bool,
     },
 
-    #[command(about = "Clean unreferenced attachments")]
+    #[command(about = "Move unreferenced attachments to the trash")]
     Clean {
         #[arg(short, long, value_name = "PATH")]
         root: String,
 
         #[arg(long)]
         dry_run: bool,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Folder for the trashed files and undo logs, defaults to the .trash folder of the vault"
+        )]
+        trash: Option<String>,
+
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        yes: bool,
+
+        #[arg(
+            long,
+            value_name = "RUN_ID",
+            conflicts_with_all = ["dry_run", "yes"],
+            help = "Move the files of a previous run back from the trash"
+        )]
+        restore: Option<String>,
+
+        #[arg(
+            long = "frontmatter-field",
+            value_name = "KEY",
+            default_values = clean::ATTACHMENT_FIELDS,
+            help = "Frontmatter field referencing attachments, can be repeated"
+        )]
+        frontmatter_fields: Vec<String>,
+    },
+
+    #[command(about = "Check the vault for broken links and other problems")]
+    Lint {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "YAML file with rule severities and settings"
+        )]
+        config: Option<String>,
+
+        #[arg(long, help = "Fix what can be fixed mechanically")]
+        fix: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    #[command(about = "Create today's daily note from a template")]
+    Daily {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(long, default_value_t = String::from(daily::DEFAULT_FOLDER), value_name = "PATH")]
+        folder: String,
+
+        #[arg(
+            long,
+            default_value_t = String::from(daily::DEFAULT_FORMAT),
+            help = "strftime format of the note names"
+        )]
+        format: String,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Template relative to the root, with {{date}}, {{title}}, {{weekday}}, {{yesterday}}, {{tomorrow}}, {{time}} and {{tasks}} variables"
+        )]
+        template: Option<String>,
+
+        #[arg(long, value_name = "YYYY-MM-DD", help = "Defaults to today")]
+        date: Option<NaiveDate>,
+
+        #[arg(
+            long,
+            help = "Don't copy the unchecked tasks of the previous daily note"
+        )]
+        no_carry_over: bool,
+    },
+
+    #[command(about = "Summarize the daily notes of a week or month")]
+    #[command(group(clap::ArgGroup::new("period").required(true).args(["week", "month"])))]
+    Rollup {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(long, default_value_t = String::from(daily::DEFAULT_FOLDER), value_name = "PATH")]
+        folder: String,
+
+        #[arg(
+            long,
+            default_value_t = String::from(daily::DEFAULT_FORMAT),
+            help = "strftime format of the note names"
+        )]
+        format: String,
+
+        #[arg(long)]
+        week: bool,
+
+        #[arg(long)]
+        month: bool,
+
+        #[arg(
+            long,
+            value_name = "YYYY-MM-DD",
+            help = "Any day of the period, defaults to today"
+        )]
+        date: Option<NaiveDate>,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Folder of the summary note, defaults to the daily notes folder"
+        )]
+        output_folder: Option<String>,
+
+        #[arg(long, help = "Overwrite an existing summary note")]
+        force: bool,
+    },
+
+    #[command(about = "List the tasks of the vault, or mark one done")]
+    Tasks {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(
+            long,
+            value_enum,
+            default_values_t = [Status::Todo, Status::InProgress],
+            help = "Can be repeated"
+        )]
+        status: Vec<Status>,
+
+        #[arg(long, help = "Tasks with any of these tags, can be repeated")]
+        tag: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "YYYY-MM-DD",
+            help = "Tasks due on or before this day"
+        )]
+        due_before: Option<NaiveDate>,
+
+        #[arg(
+            long,
+            value_name = "YYYY-MM-DD",
+            help = "Tasks due on or after this day"
+        )]
+        due_after: Option<NaiveDate>,
+
+        #[arg(long, value_enum, help = "Tasks with this priority or higher")]
+        min_priority: Option<Priority>,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Tasks of notes matching the glob relative to the root, can be repeated"
+        )]
+        path: Vec<String>,
+
+        #[arg(long, value_enum, default_value_t = SortKey::Due)]
+        sort: SortKey,
+
+        #[arg(long, value_enum, default_value_t = TaskFormat::Table)]
+        format: TaskFormat,
+
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Write to a file instead of stdout"
+        )]
+        output: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "ID",
+            help = "Check the task with this ID in its note and add the done date"
+        )]
+        done: Option<String>,
+    },
+
+    #[command(about = "Rename a note or attachment and update the links pointing to it")]
+    Rename {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(help = "Path or name of the file to rename")]
+        old: String,
+
+        #[arg(help = "New name, or a path relative to the root")]
+        new: String,
+
+        #[arg(long, help = "Show the changes without modifying any files")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Move matching files to a folder and update the links pointing to them")]
+    Move {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        #[arg(help = "Glob matching paths relative to the root")]
+        pattern: String,
+
+        #[arg(help = "Destination folder relative to the root")]
+        folder: String,
+
+        #[arg(long, help = "Show the changes without modifying any files")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Rename a tag and its nested tags in every note")]
+    Retag {
+        #[arg(short, long, value_name = "PATH")]
+        root: String,
+
+        old: String,
+
+        new: String,
+
+        #[arg(long, help = "Show the changes without modifying any files")]
+        dry_run: bool,
     },
 }
 
@@ -38,13 +365,204 @@ fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::Export { root, destination } => match run_export(root, destination) {
-            Ok(()) => println!("Success!"),
+        Commands::Export {
+            root,
+            destination,
+            rules,
+            profile,
+            unpublished_link_text,
+            target,
+            dry_run,
+            guard,
+            optimize_images,
+            image_format,
+            max_image_width,
+            image_quality,
+            image_cache,
+            backlinks,
+            graph,
+        } => {
+            let images = optimize_images.then(|| ImageOptions {
+                format: *image_format,
+                max_width: *max_image_width,
+                quality: *image_quality,
+                cache_dir: image_cache
+                    .as_ref()
+                    .map_or_else(ImageOptions::default_cache_dir, PathBuf::from),
+            });
+            let options = ExportOptions {
+                rules: rules.as_deref(),
+                profile,
+                unpublished_link_text: unpublished_link_text.as_deref(),
+                target: *target,
+                dry_run: *dry_run,
+                guard: *guard,
+                images,
+                backlinks: *backlinks,
+                graph: *graph,
+            };
+            match run_export(root, destination, &options) {
+                Ok(()) => println!("Success!"),
+                // Including a blocked export, so that the guard can fail a CI job
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Clean {
+            root,
+            dry_run,
+            trash,
+            yes,
+            restore,
+            frontmatter_fields,
+        } => {
+            let result = match restore {
+                Some(run_id) => run_restore(root, trash.as_deref(), run_id),
+                None => {
+                    let options = CleanOptions {
+                        dry_run: *dry_run,
+                        fields: frontmatter_fields,
+                        trash: trash.as_deref(),
+                        yes: *yes,
+                    };
+                    run_clean(root, &options)
+                }
+            };
+            match result {
+                Ok(()) => println!("Success"),
+                Err(e) => println!("{e}"),
+            }
+        }
+        Commands::Lint {
+            root,
+            config,
+            fix,
+            format,
+        } => match run_lint(root, config.as_deref(), *fix, *format) {
+            Ok(true) => {}
+            // Errors fail the process so lint can run in CI
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                println!("{e}");
+                std::process::exit(1);
+            }
+        },
+        Commands::Tasks {
+            root,
+            status,
+            tag,
+            due_before,
+            due_after,
+            min_priority,
+            path,
+            sort,
+            format,
+            output,
+            done,
+        } => {
+            let result = match done {
+                Some(id) => run_complete(root, id),
+                None => {
+                    let paths = (!path.is_empty())
+                        .then(|| rules::glob_set(path))
+                        .transpose();
+                    paths.and_then(|paths| {
+                        let options = TasksOptions {
+                            filter: TaskFilter {
+                                statuses: status.to_owned(),
+                                tags: tag.iter().map(|t| normalize_tag(t)).collect(),
+                                due_before: *due_before,
+                                due_after: *due_after,
+                                min_priority: *min_priority,
+                                paths,
+                            },
+                            sort: *sort,
+                            format: *format,
+                            output: output.as_deref(),
+                        };
+                        run_tasks(root, &options)
+                    })
+                }
+            };
+            if let Err(e) = result {
+                println!("{e}");
+            }
+        }
+        Commands::Rename {
+            root,
+            old,
+            new,
+            dry_run,
+        } => match run_rename(root, old, new, *dry_run) {
+            Ok(()) => println!("Success"),
+            Err(e) => println!("{e}"),
+        },
+        Commands::Move {
+            root,
+            pattern,
+            folder,
+            dry_run,
+        } => match run_move(root, pattern, folder, *dry_run) {
+            Ok(()) => println!("Success"),
             Err(e) => println!("{e}"),
         },
-        Commands::Clean { root, dry_run } => match run_clean(root, *dry_run) {
+        Commands::Retag {
+            root,
+            old,
+            new,
+            dry_run,
+        } => match run_retag(root, old, new, *dry_run) {
             Ok(()) => println!("Success"),
             Err(e) => println!("{e}"),
         },
+        Commands::Daily {
+            root,
+            folder,
+            format,
+            template,
+            date,
+            no_carry_over,
+        } => {
+            let notes = DailyNotes {
+                root: PathBuf::from(root),
+                folder: PathBuf::from(folder),
+                format: format.to_owned(),
+            };
+            let options = DailyOptions {
+                date: date.unwrap_or_else(|| Local::now().date_naive()),
+                template: template.as_deref(),
+                carry_over: !no_carry_over,
+            };
+            if let Err(e) = run_daily(&notes, &options) {
+                println!("{e}");
+            }
+        }
+        Commands::Rollup {
+            root,
+            folder,
+            format,
+            week: _,
+            month,
+            date,
+            output_folder,
+            force,
+        } => {
+            let notes = DailyNotes {
+                root: PathBuf::from(root),
+                folder: PathBuf::from(folder),
+                format: format.to_owned(),
+            };
+            let options = RollupOptions {
+                period: if *month { Period::Month } else { Period::Week },
+                date: date.unwrap_or_else(|| Local::now().date_naive()),
+                output_folder: output_folder.as_deref(),
+                force: *force,
+            };
+            if let Err(e) = run_rollup(&notes, &options) {
+                println!("{e}");
+            }
+        }
     }
 }
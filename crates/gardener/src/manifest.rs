@@ -0,0 +1,235 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const MANIFEST_FILENAME: &str = ".gardener-manifest.json";
+
+/// A file of the export, destined to `output` inside the destination dir
+pub struct OutputFile {
+    /// Note or attachment in the vault, relative to the vault root
+    pub source: PathBuf,
+    /// File with the final content
+    pub from: PathBuf,
+    /// Relative to the destination dir
+    pub output: PathBuf,
+    /// Don't overwrite a file that exists but was not created by a previous export
+    pub keep_existing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ManifestEntry {
+    source: PathBuf,
+    hash: String,
+}
+
+/// Files written by the previous export, keyed by output path
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    fn load(destination: &Path) -> anyhow::Result<Self> {
+        let path = destination.join(MANIFEST_FILENAME);
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .context(format!("Invalid export manifest {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e).context(format!("Can't read {}", path.display())),
+        }
+    }
+
+    fn save(&self, destination: &Path) -> anyhow::Result<()> {
+        let path = destination.join(MANIFEST_FILENAME);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write {}", path.display()))
+    }
+}
+
+#[derive(Default)]
+pub struct SyncSummary {
+    pub written: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub unchanged: usize,
+}
+
+impl SyncSummary {
+    pub fn print(&self, dry_run: bool) {
+        let (written, removed) = if dry_run {
+            ("Would write", "Would remove")
+        } else {
+            ("Written", "Removed")
+        };
+        for path in &self.written {
+            println!("{written}: {}", path.display());
+        }
+        for path in &self.removed {
+            println!("{removed}: {}", path.display());
+        }
+        println!(
+            "{} written, {} removed, {} unchanged",
+            self.written.len(),
+            self.removed.len(),
+            self.unchanged
+        );
+    }
+}
+
+/// Writes the changed files to the destination and removes outputs of the previous export
+/// that are not part of this one. Files not recorded in the manifest are never removed.
+pub fn sync(
+    destination: &Path,
+    files: &[OutputFile],
+    dry_run: bool,
+) -> anyhow::Result<SyncSummary> {
+    let previous = Manifest::load(destination)?;
+    let mut manifest = Manifest::default();
+    let mut summary = SyncSummary::default();
+
+    for file in files {
+        let output = destination.join(&file.output);
+        let previous_entry = previous.files.get(&file.output);
+        if file.keep_existing && previous_entry.is_none() && output.exists() {
+            continue;
+        }
+
+        let hash = hash_file(&file.from)?;
+        let unchanged = previous_entry.is_some_and(|entry| entry.hash == hash) && output.exists();
+        if unchanged {
+            summary.unchanged += 1;
+        } else {
+            if !dry_run {
+                copy_file(&file.from, &output)?;
+            }
+            summary.written.push(file.output.to_owned());
+        }
+        manifest.files.insert(
+            file.output.to_owned(),
+            ManifestEntry {
+                source: file.source.to_owned(),
+                hash,
+            },
+        );
+    }
+
+    for output in previous.files.keys() {
+        if manifest.files.contains_key(output) {
+            continue;
+        }
+        if !dry_run {
+            remove_output(destination, output)?;
+        }
+        summary.removed.push(output.to_owned());
+    }
+
+    if !dry_run {
+        manifest.save(destination)?;
+    }
+    Ok(summary)
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let content = fs::read(path).context(format!("Can't read {}", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(content)))
+}
+
+fn copy_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to).context(format!("Failed to copy {}", from.display()))?;
+    Ok(())
+}
+
+/// Removes the file and the folders left empty after it
+fn remove_output(destination: &Path, output: &Path) -> anyhow::Result<()> {
+    let path = destination.join(output);
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(format!("Failed to remove {}", path.display())),
+    }
+    for dir in output.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() {
+            break;
+        }
+        // Fails if the folder is not empty
+        if fs::remove_dir(destination.join(dir)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn output_file(source_dir: &Path, name: &str, content: &str) -> OutputFile {
+        let from = source_dir.join(name);
+        fs::write(&from, content).unwrap();
+        OutputFile {
+            source: PathBuf::from(name),
+            from,
+            output: PathBuf::from("content").join(name),
+            keep_existing: false,
+        }
+    }
+
+    #[test]
+    fn sync_writes_only_changes_and_removes_orphans() {
+        let source_dir = TempDir::new().unwrap();
+        let destination_dir = TempDir::new().unwrap();
+        let (source, destination) = (source_dir.path(), destination_dir.path());
+
+        let files = vec![
+            output_file(source, "a.md", "A"),
+            output_file(source, "b.md", "B"),
+        ];
+        let summary = sync(destination, &files, false).unwrap();
+        assert_eq!(summary.written.len(), 2);
+
+        let files = vec![output_file(source, "a.md", "A changed")];
+        let summary = sync(destination, &files, true).unwrap();
+        assert_eq!(summary.written, vec![PathBuf::from("content/a.md")]);
+        assert_eq!(summary.removed, vec![PathBuf::from("content/b.md")]);
+        assert!(destination.join("content/b.md").exists());
+
+        sync(destination, &files, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("content/a.md")).unwrap(),
+            "A changed"
+        );
+        assert!(!destination.join("content/b.md").exists());
+
+        let summary = sync(destination, &files, false).unwrap();
+        assert!(summary.written.is_empty());
+        assert_eq!(summary.unchanged, 1);
+    }
+
+    #[test]
+    fn sync_keeps_untracked_files() {
+        let source_dir = TempDir::new().unwrap();
+        let destination_dir = TempDir::new().unwrap();
+        let (source, destination) = (source_dir.path(), destination_dir.path());
+        fs::create_dir_all(destination.join("content")).unwrap();
+        fs::write(destination.join("content/_index.md"), "Custom").unwrap();
+
+        let mut file = output_file(source, "_index.md", "Generated");
+        file.keep_existing = true;
+        let summary = sync(destination, &[file], false).unwrap();
+        assert!(summary.written.is_empty());
+        assert_eq!(
+            fs::read_to_string(destination.join("content/_index.md")).unwrap(),
+            "Custom"
+        );
+    }
+}
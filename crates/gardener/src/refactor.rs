@@ -0,0 +1,422 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::{
+    rules::{glob_set, normalize_tag},
+    vault::{
+        all_vault_files, code_ranges, is_markdown, link_destination, link_target_for,
+        replace_wikilinks, resolve_link, VaultIndex,
+    },
+};
+
+lazy_static! {
+    // Inline links and images with a destination without spaces, optionally with a title
+    static ref MARKDOWN_LINK: Regex =
+        Regex::new(r#"(!?\[[^\]\n]*\]\()([^)\s]+)((?:\s+"[^"\n]*")?\))"#).unwrap();
+    static ref TAG_TOKEN: Regex = Regex::new(r#"(^|[\s\[,'"])(#?)([\w/-]+)"#).unwrap();
+    static ref INLINE_TAG: Regex = Regex::new(r"(^|\s)#([\w/-]+)").unwrap();
+}
+
+/// Renames a note or attachment and updates the links pointing to it
+pub fn run_rename(root: &str, old: &str, new: &str, dry_run: bool) -> anyhow::Result<()> {
+    let root = Path::new(root).canonicalize()?;
+    let files = relative_files(&root);
+    let index = VaultIndex::from_paths(files.iter().cloned());
+
+    let old_path = match index.resolve(old) {
+        Some(path) => path.to_path_buf(),
+        None => anyhow::bail!("{old} not found in the vault"),
+    };
+    let mut new_path = if new.contains('/') {
+        PathBuf::from(new)
+    } else {
+        old_path.with_file_name(new)
+    };
+    if new_path.extension().is_none() {
+        if let Some(extension) = old_path.extension() {
+            new_path.set_extension(extension);
+        }
+    }
+
+    let renames = BTreeMap::from([(old_path, new_path)]);
+    apply_renames(&root, &files, &index, &renames, dry_run)
+}
+
+/// Moves every file matching `pattern` into `folder` and updates the links pointing to them
+pub fn run_move(root: &str, pattern: &str, folder: &str, dry_run: bool) -> anyhow::Result<()> {
+    let root = Path::new(root).canonicalize()?;
+    let files = relative_files(&root);
+    let index = VaultIndex::from_paths(files.iter().cloned());
+
+    let globs = glob_set(&[pattern.to_string()])?;
+    let folder = Path::new(folder);
+    let renames: BTreeMap<PathBuf, PathBuf> = files
+        .iter()
+        .filter(|path| globs.is_match(path))
+        .filter_map(|path| Some((path.to_owned(), folder.join(path.file_name()?))))
+        .filter(|(old, new)| old != new)
+        .collect();
+    if renames.is_empty() {
+        anyhow::bail!("No files matching {pattern} to move");
+    }
+    apply_renames(&root, &files, &index, &renames, dry_run)
+}
+
+/// Replaces a tag (and its nested tags) in frontmatter and inline tags of every note
+pub fn run_retag(root: &str, old: &str, new: &str, dry_run: bool) -> anyhow::Result<()> {
+    let root = Path::new(root).canonicalize()?;
+    let old = normalize_tag(old);
+    let new = new.trim().trim_start_matches('#');
+    if old.is_empty() || new.is_empty() {
+        anyhow::bail!("Tags can't be empty");
+    }
+
+    let mut changed = 0;
+    for note in relative_files(&root).iter().filter(|p| is_markdown(p)) {
+        let abs_path = root.join(note);
+        let content = fs::read_to_string(&abs_path)?;
+        let updated = retag(&content, &old, new);
+        if updated != content {
+            changed += 1;
+            write_note(&abs_path, note, &content, &updated, dry_run)?;
+        }
+    }
+    print_summary(changed, dry_run);
+    Ok(())
+}
+
+/// Every file of the vault relative to `root`, including the ones excluded from exports
+pub fn relative_files(root: &Path) -> Vec<PathBuf> {
+    all_vault_files(root)
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+fn apply_renames(
+    root: &Path,
+    files: &[PathBuf],
+    index: &VaultIndex,
+    renames: &BTreeMap<PathBuf, PathBuf>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let changed = rename_files(root, files, index, renames, dry_run)?;
+    for (old, new) in renames {
+        println!("Rename {} -> {}", old.display(), new.display());
+    }
+    print_summary(changed, dry_run);
+    Ok(())
+}
+
+/// Updates the links pointing to the renamed files in every note, then renames the files.
+/// Returns the number of updated notes.
+pub fn rename_files(
+    root: &Path,
+    files: &[PathBuf],
+    index: &VaultIndex,
+    renames: &BTreeMap<PathBuf, PathBuf>,
+    dry_run: bool,
+) -> anyhow::Result<usize> {
+    let mut targets = HashSet::new();
+    for new in renames.values() {
+        // Files missing from the index (hidden or gitignored ones) would be overwritten too
+        if (index.contains(new) || root.join(new).exists()) && !renames.contains_key(new) {
+            anyhow::bail!("{} already exists", new.display());
+        }
+        if !targets.insert(new) {
+            anyhow::bail!("Multiple files would be moved to {}", new.display());
+        }
+    }
+
+    let mut changed = 0;
+    for note in files.iter().filter(|p| is_markdown(p)) {
+        let abs_path = root.join(note);
+        let content = fs::read_to_string(&abs_path)?;
+        let updated = update_links(&content, note, renames, index);
+        if updated != content {
+            changed += 1;
+            write_note(&abs_path, note, &content, &updated, dry_run)?;
+        }
+    }
+
+    if !dry_run {
+        for (old, new) in renames {
+            let new_abs = root.join(new);
+            if let Some(parent) = new_abs.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(root.join(old), &new_abs)
+                .context(format!("Failed to rename {}", old.display()))?;
+        }
+    }
+    Ok(changed)
+}
+
+/// Points wikilinks and Markdown links of the note at `note` to the renamed files. Relative
+/// Markdown links of a moved note are rebased to its new folder.
+fn update_links(
+    content: &str,
+    note: &Path,
+    renames: &BTreeMap<PathBuf, PathBuf>,
+    index: &VaultIndex,
+) -> String {
+    let content = replace_wikilinks(content, |link| {
+        let target = index.resolve(link.target)?;
+        let new = renames.get(target)?;
+        Some(link.with_target(&link_target_for(link.target, new)))
+    })
+    .unwrap_or_else(|| content.to_string());
+
+    let old_dir = note.parent().unwrap_or(Path::new(""));
+    let new_note = renames.get(note).map_or(note, PathBuf::as_path);
+    let new_dir = new_note.parent().unwrap_or(Path::new(""));
+    let note_moved = old_dir != new_dir;
+
+    MARKDOWN_LINK
+        .replace_all(&content, |cap: &Captures| {
+            let dest = &cap[2];
+            let new_dest = resolve_link(dest, old_dir)
+                .filter(|(target, _)| index.contains(target))
+                .and_then(|(target, anchor)| {
+                    let renamed = renames.get(&target);
+                    if renamed.is_none() && !note_moved {
+                        return None;
+                    }
+                    let new_target = renamed.unwrap_or(&target);
+                    let mut new_dest = link_destination(new_dir, new_target);
+                    if let Some(anchor) = anchor {
+                        new_dest.push('#');
+                        new_dest.push_str(anchor);
+                    }
+                    Some(new_dest)
+                });
+            format!(
+                "{}{}{}",
+                &cap[1],
+                new_dest.as_deref().unwrap_or(dest),
+                &cap[3]
+            )
+        })
+        .into_owned()
+}
+
+fn retag(content: &str, old: &str, new: &str) -> String {
+    let frontmatter_end = content
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.find("\n---"))
+        .map(|end| end + "---\n".len());
+
+    let (frontmatter, body) = match frontmatter_end {
+        Some(end) => content.split_at(end),
+        None => ("", content),
+    };
+    let mut result = retag_frontmatter(frontmatter, old, new);
+    let code = code_ranges(body);
+    result.push_str(&INLINE_TAG.replace_all(body, |cap: &Captures| {
+        let tag = cap.get(2).unwrap();
+        if code.iter().any(|range| range.contains(&tag.start())) {
+            return cap[0].to_string();
+        }
+        format!("{}#{}", &cap[1], replace_tag(tag.as_str(), old, new))
+    }));
+    result
+}
+
+/// Replaces the tag in the `tags` field, either a list or a string, keeping the formatting
+fn retag_frontmatter(frontmatter: &str, old: &str, new: &str) -> String {
+    let mut in_tags = false;
+    let mut result = String::with_capacity(frontmatter.len());
+    for line in frontmatter.split_inclusive('\n') {
+        let value = if let Some(value) = line.strip_prefix("tags:") {
+            in_tags = true;
+            result.push_str("tags:");
+            value
+        } else if in_tags && (line.starts_with(' ') || line.starts_with('-')) {
+            line
+        } else {
+            in_tags = false;
+            result.push_str(line);
+            continue;
+        };
+        result.push_str(&TAG_TOKEN.replace_all(value, |cap: &Captures| {
+            format!("{}{}{}", &cap[1], &cap[2], replace_tag(&cap[3], old, new))
+        }));
+    }
+    result
+}
+
+/// `old` is normalized, nested tags (`old/child`) are renamed too
+fn replace_tag(tag: &str, old: &str, new: &str) -> String {
+    let normalized = normalize_tag(tag);
+    if normalized == old {
+        new.to_string()
+    } else if normalized.starts_with(&format!("{old}/")) {
+        // Lowercasing can change the length, so the nested part is found by its segments
+        let depth = old.split('/').count();
+        let nested = tag.splitn(depth + 1, '/').nth(depth).unwrap_or_default();
+        format!("{new}/{nested}")
+    } else {
+        tag.to_string()
+    }
+}
+
+fn write_note(
+    abs_path: &Path,
+    note: &Path,
+    before: &str,
+    after: &str,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    if dry_run {
+        print_diff(note, before, after);
+        return Ok(());
+    }
+    fs::write(abs_path, after).context(format!("Failed to update {}", note.display()))
+}
+
+/// Replacements never span lines, so the changed lines are compared pairwise
+fn print_diff(note: &Path, before: &str, after: &str) {
+    println!("--- {}", note.display());
+    println!("+++ {}", note.display());
+    let before_lines: Vec<&str> = before.lines().collect();
+    let after_lines: Vec<&str> = after.lines().collect();
+    for i in 0..before_lines.len().max(after_lines.len()) {
+        let (old, new) = (before_lines.get(i), after_lines.get(i));
+        if old == new {
+            continue;
+        }
+        println!("@@ line {} @@", i + 1);
+        if let Some(old) = old {
+            println!("-{old}");
+        }
+        if let Some(new) = new {
+            println!("+{new}");
+        }
+    }
+    println!();
+}
+
+fn print_summary(changed: usize, dry_run: bool) {
+    if dry_run {
+        println!("{changed} notes would be updated");
+    } else {
+        println!("Updated {changed} notes");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn index() -> VaultIndex {
+        VaultIndex::from_paths(
+            ["Notes/Old Name.md", "Notes/Other.md", "Assets/img.png"]
+                .into_iter()
+                .map(PathBuf::from),
+        )
+    }
+
+    #[test]
+    fn rename_updates_wikilinks_and_markdown_links() {
+        let renames = BTreeMap::from([(
+            PathBuf::from("Notes/Old Name.md"),
+            PathBuf::from("Archive/New Name.md"),
+        )]);
+        let content = "[[Old Name#Heading|alias]] [[Other]]\n[text](Old%20Name.md#heading) ![](../Assets/img.png)";
+        assert_eq!(
+            update_links(content, Path::new("Notes/Other.md"), &renames, &index()),
+            "[[New Name#Heading|alias]] [[Other]]\n[text](../Archive/New%20Name.md#heading) ![](../Assets/img.png)"
+        );
+    }
+
+    #[test]
+    fn moved_note_rebases_relative_links() {
+        let renames = BTreeMap::from([(
+            PathBuf::from("Notes/Other.md"),
+            PathBuf::from("Notes/Sub/Other.md"),
+        )]);
+        let content = "![](../Assets/img.png \"Title\") [[Old Name]] [web](https://example.com)";
+        assert_eq!(
+            update_links(content, Path::new("Notes/Other.md"), &renames, &index()),
+            "![](../../Assets/img.png \"Title\") [[Old Name]] [web](https://example.com)"
+        );
+    }
+
+    #[test]
+    fn retag_frontmatter_and_inline_tags() {
+        let content = "---\ntitle: Rust #1\ntags:\n  - rust\n  - Rust/async\n  - rusty\n---\n\nText #rust and #rust/tokio, #rustacean";
+        assert_eq!(
+            retag(content, "rust", "lang/rust"),
+            "---\ntitle: Rust #1\ntags:\n  - lang/rust\n  - lang/rust/async\n  - rusty\n---\n\nText #lang/rust and #lang/rust/tokio, #rustacean"
+        );
+    }
+
+    #[test]
+    fn retag_skips_code() {
+        let content = "#rust and `#rust`\n\n```\n#rust\n```\n";
+        assert_eq!(
+            retag(content, "rust", "lang"),
+            "#lang and `#rust`\n\n```\n#rust\n```\n"
+        );
+    }
+
+    #[test]
+    fn retag_keeps_nested_tags_after_case_changes() {
+        assert_eq!(
+            replace_tag("İstanbul/Old", &normalize_tag("İstanbul"), "city"),
+            "city/Old"
+        );
+    }
+
+    #[test]
+    fn rename_refuses_to_overwrite_files_missing_from_the_index() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.md"), "A").unwrap();
+        fs::write(root.join(".b.md"), "Hidden").unwrap();
+
+        let files = relative_files(root);
+        let index = VaultIndex::from_paths(files.iter().cloned());
+        let renames = BTreeMap::from([(PathBuf::from("a.md"), PathBuf::from(".b.md"))]);
+        let error = rename_files(root, &files, &index, &renames, false).unwrap_err();
+        assert!(error.to_string().contains("already exists"));
+        assert_eq!(fs::read_to_string(root.join(".b.md")).unwrap(), "Hidden");
+    }
+
+    #[test]
+    fn rename_updates_links_in_notes_excluded_from_exports() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Private")).unwrap();
+        fs::write(root.join(".export-ignore"), "Private/\n").unwrap();
+        fs::write(root.join("Old.md"), "Note").unwrap();
+        fs::write(root.join("Private/Journal.md"), "[[Old]]").unwrap();
+
+        let files = relative_files(root);
+        let index = VaultIndex::from_paths(files.iter().cloned());
+        let renames = BTreeMap::from([(PathBuf::from("Old.md"), PathBuf::from("New.md"))]);
+        rename_files(root, &files, &index, &renames, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Private/Journal.md")).unwrap(),
+            "[[New]]"
+        );
+    }
+
+    #[test]
+    fn retag_inline_list() {
+        let content = "---\ntags: [rust, \"#cli\"]\n---\n";
+        assert_eq!(
+            retag(content, "cli", "tools"),
+            "---\ntags: [rust, \"#tools\"]\n---\n"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use obsidian_export::serde_yaml::{self, Mapping, Value};
+use serde::Deserialize;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+const FRONTMATTER_KEY_TAGS: &str = "tags";
+const FRONTMATTER_TAG_INCLUDE: &str = "public";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Profile {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+    frontmatter: FrontmatterRules,
+    guard: GuardRules,
+    syntax: SyntaxRules,
+}
+
+/// All criteria of a rule have to match. Empty criteria match every note.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Rule {
+    /// Note has at least one of these tags
+    tags: Vec<String>,
+    /// Note path relative to the vault root matches at least one of these globs
+    folders: Vec<String>,
+    /// Note frontmatter satisfies all of these conditions
+    frontmatter: Vec<Condition>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrontmatterRules {
+    /// When not empty, only these keys are kept (after renaming)
+    pub keep: Vec<String>,
+    pub strip: Vec<String>,
+    pub rename: HashMap<String, String>,
+}
+
+/// Checks of the privacy guard before exporting
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuardRules {
+    /// Text matching any of these must not be published, checked on top of the built-in patterns
+    pub secrets: Vec<SecretPattern>,
+    /// Set to `false` to only check `secrets`
+    pub builtin_secrets: bool,
+    /// Globs relative to the vault root, attachments in these folders must not be published
+    pub private_folders: Vec<String>,
+}
+
+/// Conversion of Obsidian-only syntax, everything is converted by default
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyntaxRules {
+    /// `> [!note]` callouts to HTML or shortcodes of the target
+    pub callouts: bool,
+    /// `==text==` to `<mark>`
+    pub highlights: bool,
+    /// Remove `%% comments %%`
+    pub comments: bool,
+    /// Remove `^block-id` markers
+    pub block_ids: bool,
+    /// Keep `$inline$` and `$$block$$` math as written
+    pub math: bool,
+}
+
+impl Default for SyntaxRules {
+    fn default() -> Self {
+        SyntaxRules {
+            callouts: true,
+            highlights: true,
+            comments: true,
+            block_ids: true,
+            math: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SecretPattern {
+    pub name: String,
+    pub pattern: String,
+}
+
+impl Default for GuardRules {
+    fn default() -> Self {
+        GuardRules {
+            secrets: vec![],
+            builtin_secrets: true,
+            private_folders: vec![],
+        }
+    }
+}
+
+impl GuardRules {
+    /// The built-in patterns (unless turned off) followed by the configured ones
+    pub fn secret_patterns(&self) -> Vec<SecretPattern> {
+        let secret = |name: &str, pattern: &str| SecretPattern {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        };
+        let mut patterns = vec![];
+        if self.builtin_secrets {
+            patterns.extend([
+                secret("OpenAI API key", r"\bsk-[A-Za-z0-9_-]{20,}"),
+                secret("AWS access key", r"\bAKIA[0-9A-Z]{16}\b"),
+                secret("GitHub token", r"\bgh[pousr]_[A-Za-z0-9]{36,}"),
+                secret("private key", r"-----BEGIN [A-Z ]*PRIVATE KEY-----"),
+                secret("email address", r"[\w.+-]+@[\w-]+\.[\w.-]+"),
+                secret(
+                    "phone number",
+                    r"\+\d{1,3}[ -]?\(?\d{1,4}\)?(?:[ -]?\d{2,4}){2,4}",
+                ),
+            ]);
+        }
+        patterns.extend(self.secrets.iter().cloned());
+        patterns
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(try_from = "String")]
+enum Condition {
+    Exists(String),
+    Missing(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some((key, expected)) = value.split_once("!=") {
+            return Ok(Condition::NotEquals(
+                key.trim().to_string(),
+                expected.trim().to_string(),
+            ));
+        }
+        if let Some((key, expected)) = value.split_once("==") {
+            return Ok(Condition::Equals(
+                key.trim().to_string(),
+                expected.trim().to_string(),
+            ));
+        }
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Empty frontmatter condition".to_string());
+        }
+        match value.strip_prefix('!') {
+            Some(key) => Ok(Condition::Missing(key.trim().to_string())),
+            None => Ok(Condition::Exists(value.to_string())),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, frontmatter: &Mapping) -> bool {
+        match self {
+            Condition::Exists(key) => frontmatter.get(key.as_str()).is_some(),
+            Condition::Missing(key) => frontmatter.get(key.as_str()).is_none(),
+            Condition::Equals(key, expected) => frontmatter
+                .get(key.as_str())
+                .is_some_and(|v| value_matches(v, expected)),
+            Condition::NotEquals(key, expected) => !frontmatter
+                .get(key.as_str())
+                .is_some_and(|v| value_matches(v, expected)),
+        }
+    }
+}
+
+/// Compares a frontmatter value to its textual form, lists match if any item matches
+fn value_matches(value: &Value, expected: &str) -> bool {
+    match value {
+        Value::String(s) => s == expected,
+        Value::Bool(b) => b.to_string() == expected,
+        Value::Number(n) => n.to_string() == expected,
+        Value::Null => expected == "null" || expected.is_empty(),
+        Value::Sequence(items) => items.iter().any(|item| value_matches(item, expected)),
+        _ => false,
+    }
+}
+
+struct CompiledRule {
+    tags: HashSet<String>,
+    folders: Option<GlobSet>,
+    frontmatter: Vec<Condition>,
+}
+
+impl CompiledRule {
+    fn compile(rule: Rule) -> anyhow::Result<Self> {
+        let folders = if rule.folders.is_empty() {
+            None
+        } else {
+            Some(glob_set(&rule.folders)?)
+        };
+        Ok(CompiledRule {
+            tags: rule.tags.iter().map(|t| normalize_tag(t)).collect(),
+            folders,
+            frontmatter: rule.frontmatter,
+        })
+    }
+
+    fn matches(&self, note: &NoteInfo) -> bool {
+        let tags_match = self.tags.is_empty() || !self.tags.is_disjoint(&note.tags);
+        let folders_match = self
+            .folders
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(note.path));
+        let frontmatter_match = self.frontmatter.iter().all(|c| c.matches(note.frontmatter));
+        tags_match && folders_match && frontmatter_match
+    }
+}
+
+pub struct NoteInfo<'a> {
+    /// Relative to the vault root
+    pub path: &'a Path,
+    pub tags: HashSet<String>,
+    pub frontmatter: &'a Mapping,
+}
+
+/// Decides which notes get published and how their frontmatter is transformed
+pub struct PublishFilter {
+    include: Vec<CompiledRule>,
+    exclude: Vec<CompiledRule>,
+    pub frontmatter: FrontmatterRules,
+    pub guard: GuardRules,
+    pub syntax: SyntaxRules,
+}
+
+impl PublishFilter {
+    /// Only notes tagged `public` are published, without any tags
+    pub fn public_tag() -> Self {
+        PublishFilter {
+            include: vec![CompiledRule {
+                tags: HashSet::from([FRONTMATTER_TAG_INCLUDE.to_string()]),
+                folders: None,
+                frontmatter: vec![],
+            }],
+            exclude: vec![],
+            frontmatter: FrontmatterRules {
+                strip: vec![FRONTMATTER_KEY_TAGS.to_string()],
+                ..Default::default()
+            },
+            guard: GuardRules::default(),
+            syntax: SyntaxRules::default(),
+        }
+    }
+
+    pub fn load(rules_path: &Path, profile_name: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(rules_path)
+            .context(format!("Can't read rules file {}", rules_path.display()))?;
+        let mut rules: RulesFile = serde_yaml::from_str(&content).context("Invalid rules file")?;
+        let profile = rules.profiles.remove(profile_name).context(format!(
+            "Profile {profile_name} not found in {}",
+            rules_path.display()
+        ))?;
+        if profile.include.is_empty() {
+            anyhow::bail!("Profile {profile_name} has no include rules, nothing would be exported");
+        }
+
+        Ok(PublishFilter {
+            include: compile_rules(profile.include)?,
+            exclude: compile_rules(profile.exclude)?,
+            frontmatter: profile.frontmatter,
+            guard: profile.guard,
+            syntax: profile.syntax,
+        })
+    }
+
+    pub fn is_published(&self, note: &NoteInfo) -> bool {
+        self.include.iter().any(|rule| rule.matches(note))
+            && !self.exclude.iter().any(|rule| rule.matches(note))
+    }
+
+    pub fn transform_frontmatter(&self, frontmatter: &mut Mapping) {
+        let rules = &self.frontmatter;
+        for (from, to) in &rules.rename {
+            if let Some(value) = frontmatter.remove(from.as_str()) {
+                frontmatter.insert(Value::String(to.to_owned()), value);
+            }
+        }
+        if !rules.keep.is_empty() {
+            frontmatter.retain(|key, _| {
+                key.as_str()
+                    .is_some_and(|key| rules.keep.iter().any(|k| k == key))
+            });
+        }
+        for key in &rules.strip {
+            frontmatter.remove(key.as_str());
+        }
+    }
+}
+
+pub fn glob_set(globs: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).context(format!("Invalid glob {glob}"))?);
+    }
+    Ok(builder.build()?)
+}
+
+fn compile_rules(rules: Vec<Rule>) -> anyhow::Result<Vec<CompiledRule>> {
+    rules.into_iter().map(CompiledRule::compile).collect()
+}
+
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().trim_start_matches('#').to_lowercase()
+}
+
+/// Tags from the `tags` frontmatter field, either a list or a comma/space separated string
+pub fn frontmatter_tags(frontmatter: &Mapping) -> HashSet<String> {
+    match frontmatter.get(FRONTMATTER_KEY_TAGS) {
+        Some(Value::Sequence(tags)) => tags
+            .iter()
+            .filter_map(|t| t.as_str())
+            .map(normalize_tag)
+            .collect(),
+        Some(Value::String(tags)) => tags
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty())
+            .map(normalize_tag)
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frontmatter(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn filter(yaml: &str) -> PublishFilter {
+        let profile: Profile = serde_yaml::from_str(yaml).unwrap();
+        PublishFilter {
+            include: compile_rules(profile.include).unwrap(),
+            exclude: compile_rules(profile.exclude).unwrap(),
+            frontmatter: profile.frontmatter,
+            guard: profile.guard,
+            syntax: profile.syntax,
+        }
+    }
+
+    fn is_published(filter: &PublishFilter, path: &str, yaml: &str) -> bool {
+        let frontmatter = frontmatter(yaml);
+        filter.is_published(&NoteInfo {
+            path: Path::new(path),
+            tags: frontmatter_tags(&frontmatter),
+            frontmatter: &frontmatter,
+        })
+    }
+
+    #[test]
+    fn condition_parsing() {
+        assert_eq!(
+            Condition::try_from("publish == true".to_string()),
+            Ok(Condition::Equals("publish".into(), "true".into()))
+        );
+        assert_eq!(
+            Condition::try_from("status != draft".to_string()),
+            Ok(Condition::NotEquals("status".into(), "draft".into()))
+        );
+        assert_eq!(
+            Condition::try_from("!private".to_string()),
+            Ok(Condition::Missing("private".into()))
+        );
+        assert_eq!(
+            Condition::try_from("slug".to_string()),
+            Ok(Condition::Exists("slug".into()))
+        );
+    }
+
+    #[test]
+    fn default_filter_requires_public_tag() {
+        let filter = PublishFilter::public_tag();
+        assert!(is_published(&filter, "a.md", "tags: [public, rust]"));
+        assert!(!is_published(&filter, "a.md", "tags: [rust]"));
+        assert!(!is_published(&filter, "a.md", "title: A"));
+    }
+
+    #[test]
+    fn include_and_exclude_rules() {
+        let filter = filter(
+            r#"
+include:
+  - frontmatter: ["publish == true"]
+  - folders: ["Blog/**"]
+exclude:
+  - frontmatter: ["status == draft"]
+  - tags: [private]
+"#,
+        );
+        assert!(is_published(&filter, "Notes/a.md", "publish: true"));
+        assert!(is_published(&filter, "Blog/2024/post.md", "title: Post"));
+        assert!(!is_published(&filter, "Notes/a.md", "publish: false"));
+        assert!(!is_published(
+            &filter,
+            "Blog/post.md",
+            "status: draft\npublish: true"
+        ));
+        assert!(!is_published(&filter, "Blog/post.md", "tags: '#private'"));
+    }
+
+    #[test]
+    fn rule_criteria_are_combined() {
+        let filter = filter(
+            r#"
+include:
+  - tags: [public]
+    folders: ["Wiki/**"]
+    frontmatter: ["status != draft"]
+"#,
+        );
+        assert!(is_published(&filter, "Wiki/a.md", "tags: [public]"));
+        assert!(!is_published(&filter, "Blog/a.md", "tags: [public]"));
+        assert!(!is_published(
+            &filter,
+            "Wiki/a.md",
+            "tags: [public]\nstatus: draft"
+        ));
+    }
+
+    #[test]
+    fn frontmatter_transform() {
+        let filter = filter(
+            r#"
+include: [{}]
+frontmatter:
+  rename: {created: date}
+  keep: [date, title, tags]
+  strip: [tags]
+"#,
+        );
+        let mut fm = frontmatter("created: 2024-01-01\ntitle: A\ntags: [x]\nsecret: y");
+        filter.transform_frontmatter(&mut fm);
+        assert_eq!(fm, frontmatter("date: 2024-01-01\ntitle: A"));
+    }
+}
@@ -0,0 +1,498 @@
+use std::{collections::HashMap, fs, mem};
+
+use lazy_static::lazy_static;
+use obsidian_export::Context as ObsidianContext;
+use obsidian_export::{MarkdownEvents, PostprocessorResult};
+use pulldown_cmark::{Event, Tag};
+use regex::Regex;
+
+use crate::target::Target;
+
+const COMMENT_DELIMITER: &str = "%%";
+
+lazy_static! {
+    static ref CALLOUT: Regex = Regex::new(r"^\[!([\w-]+)\]([+-]?)[ \t]*(.*)$").unwrap();
+    static ref HIGHLIGHT: Regex = Regex::new(r"==([^\s=](?:[^=\n]*[^\s=])?)==").unwrap();
+    static ref BLOCK_ID: Regex = Regex::new(r"(?:^|[ \t]+)\^[A-Za-z0-9-]+[ \t]*$").unwrap();
+    // Obsidian only treats `$...$` as math without whitespace inside the dollar signs
+    static ref MATH: Regex =
+        Regex::new(r"(?s)\$\$.+?\$\$|\$[^\s$](?:[^$\n]*[^\s$])?\$").unwrap();
+}
+
+/// Removes `%% comments %%`, both inline and spanning multiple lines
+pub fn create_comment_stripper(
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
+    |_context, events| {
+        merge_text(events);
+        strip_comments(events);
+        PostprocessorResult::Continue
+    }
+}
+
+/// Keeps math as it is written in the note. The Markdown parser would drop the backslashes
+/// escaping punctuation (`\\`, `\{`) and the output would escape other characters.
+pub fn create_math_preserver(
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
+    |context, events| {
+        let source = fs::read_to_string(context.current_file()).unwrap_or_default();
+        merge_text(events);
+        preserve_math(events, &source);
+        PostprocessorResult::Continue
+    }
+}
+
+/// Turns `==highlights==` into `<mark>` elements
+pub fn create_highlight_converter(
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
+    |_context, events| {
+        merge_text(events);
+        convert_highlights(events);
+        PostprocessorResult::Continue
+    }
+}
+
+/// Removes the `^block-id` markers at the end of paragraphs and list items
+pub fn create_block_id_stripper(
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
+    |_context, events| {
+        merge_text(events);
+        strip_block_ids(events);
+        PostprocessorResult::Continue
+    }
+}
+
+/// Turns `> [!type] Title` callouts into HTML, or shortcodes for Hugo and Zola
+pub fn create_callout_converter(
+    target: Target,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult {
+    move |_context, events| {
+        merge_text(events);
+        convert_callouts(events, target);
+        PostprocessorResult::Continue
+    }
+}
+
+/// The parser splits text at characters that might start inline syntax
+fn merge_text(events: &mut Vec<Event>) {
+    let input = mem::take(events);
+    for event in input {
+        if let Event::Text(text) = &event {
+            if let Some(Event::Text(previous)) = events.last_mut() {
+                *previous = format!("{previous}{text}").into();
+                continue;
+            }
+        }
+        events.push(event);
+    }
+}
+
+fn is_inline(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+fn strip_comments(events: &mut Vec<Event>) {
+    let input = mem::take(events);
+    let mut in_comment = false;
+    let mut in_code = false;
+    for event in input {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            Event::Text(text) if !in_code => {
+                let mut kept = String::new();
+                for (i, part) in text.split(COMMENT_DELIMITER).enumerate() {
+                    if i > 0 {
+                        in_comment = !in_comment;
+                    }
+                    if !in_comment {
+                        kept.push_str(part);
+                    }
+                }
+                if !kept.is_empty() {
+                    events.push(Event::Text(kept.into()));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        // Block structure is kept even inside comments, only the content is removed
+        let keep = match &event {
+            Event::Start(tag) | Event::End(tag) => !(in_comment && is_inline(tag)),
+            _ => !in_comment,
+        };
+        if keep {
+            events.push(event);
+        }
+    }
+}
+
+fn preserve_math(events: &mut Vec<Event>, source: &str) {
+    // Math as written in the note, by its parsed form
+    let written: HashMap<String, &str> = MATH
+        .find_iter(source)
+        .map(|found| (math_key(found.as_str()), found.as_str()))
+        .collect();
+
+    let input = mem::take(events);
+    let mut in_code = false;
+    // Consecutive text and line breaks, display math spans multiple lines
+    let mut run: Vec<Event> = vec![];
+    for event in input {
+        match event {
+            Event::Text(_) | Event::SoftBreak if !in_code => {
+                run.push(event);
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            _ => {}
+        }
+        flush_math_run(events, mem::take(&mut run), &written);
+        events.push(event);
+    }
+    flush_math_run(events, run, &written);
+}
+
+fn flush_math_run<'a>(
+    events: &mut Vec<Event<'a>>,
+    run: Vec<Event<'a>>,
+    written: &HashMap<String, &str>,
+) {
+    let text: String = run
+        .iter()
+        .map(|event| match event {
+            Event::Text(text) => &**text,
+            _ => "\n",
+        })
+        .collect();
+    if !MATH.is_match(&text) {
+        events.extend(run);
+        return;
+    }
+
+    let mut last = 0;
+    for found in MATH.find_iter(&text) {
+        push_text_lines(events, &text[last..found.start()]);
+        let math = found.as_str();
+        let math = written.get(&math_key(math)).copied().unwrap_or(math);
+        events.push(Event::Html(math.to_string().into()));
+        last = found.end();
+    }
+    push_text_lines(events, &text[last..]);
+}
+
+fn push_text_lines(events: &mut Vec<Event>, text: &str) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            events.push(Event::SoftBreak);
+        }
+        if !line.is_empty() {
+            events.push(Event::Text(line.to_string().into()));
+        }
+    }
+}
+
+/// Math text without escaping backslashes and with collapsed whitespace, the same for the
+/// source and the parsed text
+fn math_key(math: &str) -> String {
+    let mut key = String::with_capacity(math.len());
+    let mut chars = math.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek().is_some_and(char::is_ascii_punctuation) {
+            if let Some(escaped) = chars.next() {
+                key.push(escaped);
+            }
+        } else if c.is_whitespace() {
+            if !key.ends_with(' ') {
+                key.push(' ');
+            }
+        } else {
+            key.push(c);
+        }
+    }
+    key
+}
+
+fn convert_highlights(events: &mut Vec<Event>) {
+    let input = mem::take(events);
+    let mut in_code = false;
+    for event in input {
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            Event::Text(text) if !in_code && HIGHLIGHT.is_match(text) => {
+                let mut last = 0;
+                for cap in HIGHLIGHT.captures_iter(text) {
+                    let found = cap.get(0).unwrap();
+                    if found.start() > last {
+                        events.push(Event::Text(text[last..found.start()].to_string().into()));
+                    }
+                    events.push(Event::Html("<mark>".into()));
+                    events.push(Event::Text(cap[1].to_string().into()));
+                    events.push(Event::Html("</mark>".into()));
+                    last = found.end();
+                }
+                if last < text.len() {
+                    events.push(Event::Text(text[last..].to_string().into()));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        events.push(event);
+    }
+}
+
+fn strip_block_ids(events: &mut [Event]) {
+    let mut in_code = false;
+    for i in 0..events.len() {
+        let stripped = match &events[i] {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code = true;
+                None
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                in_code = false;
+                None
+            }
+            Event::Text(text) if !in_code => {
+                let at_line_end = matches!(
+                    events.get(i + 1),
+                    None | Some(Event::SoftBreak | Event::HardBreak | Event::End(_))
+                );
+                BLOCK_ID
+                    .find(text)
+                    .filter(|_| at_line_end)
+                    .map(|found| text[..found.start()].to_string())
+            }
+            _ => None,
+        };
+        if let Some(text) = stripped {
+            events[i] = Event::Text(text.into());
+        }
+    }
+}
+
+fn convert_callouts(events: &mut Vec<Event>, target: Target) {
+    let input = mem::take(events);
+    // Closing markup of the open block quotes, `None` for regular quotes
+    let mut quotes: Vec<Option<String>> = vec![];
+    let mut i = 0;
+    while i < input.len() {
+        match &input[i] {
+            Event::Start(Tag::BlockQuote) => {
+                let header = match (input.get(i + 1), input.get(i + 2)) {
+                    (Some(Event::Start(Tag::Paragraph)), Some(Event::Text(text))) => {
+                        CALLOUT.captures(text)
+                    }
+                    _ => None,
+                };
+                let Some(header) = header else {
+                    quotes.push(None);
+                    events.push(input[i].clone());
+                    i += 1;
+                    continue;
+                };
+
+                // The title is the rest of the first line
+                let mut title = header[3].to_string();
+                let mut j = i + 3;
+                let mut paragraph_closed = false;
+                while let Some(event) = input.get(j) {
+                    j += 1;
+                    match event {
+                        Event::SoftBreak | Event::HardBreak => break,
+                        Event::End(Tag::Paragraph) => {
+                            paragraph_closed = true;
+                            break;
+                        }
+                        Event::Text(text) | Event::Code(text) => title.push_str(text),
+                        _ => {}
+                    }
+                }
+
+                let (opening, closing) = callout_markup(target, &header[1], &header[2], &title);
+                events.push(Event::Html(opening.into()));
+                if !paragraph_closed {
+                    events.push(Event::Start(Tag::Paragraph));
+                }
+                quotes.push(Some(closing));
+                i = j;
+                continue;
+            }
+            Event::End(Tag::BlockQuote) => match quotes.pop() {
+                Some(Some(closing)) => events.push(Event::Html(closing.into())),
+                _ => events.push(input[i].clone()),
+            },
+            event => events.push(event.clone()),
+        }
+        i += 1;
+    }
+}
+
+/// Opening and closing markup of a callout, `fold` is `-` (collapsed), `+` (expanded) or empty
+fn callout_markup(target: Target, kind: &str, fold: &str, title: &str) -> (String, String) {
+    let kind = kind.to_lowercase();
+    let title = match title.trim() {
+        "" => capitalize(&kind),
+        title => escape_html(title),
+    };
+    match target {
+        Target::Hugo => {
+            let folded = if fold == "-" { " folded=\"true\"" } else { "" };
+            (
+                format!("{{{{% callout type=\"{kind}\" title=\"{title}\"{folded} %}}}}\n\n"),
+                "\n{{% /callout %}}\n\n".to_string(),
+            )
+        }
+        Target::Zola => {
+            let folded = if fold == "-" { ", folded=true" } else { "" };
+            (
+                format!("{{% callout(type=\"{kind}\", title=\"{title}\"{folded}) %}}\n\n"),
+                "\n{% end %}\n\n".to_string(),
+            )
+        }
+        Target::Plain | Target::Astro if fold.is_empty() => (
+            format!(
+                "<div class=\"callout\" data-callout=\"{kind}\">\n<p class=\"callout-title\">{title}</p>\n\n"
+            ),
+            "\n</div>\n\n".to_string(),
+        ),
+        Target::Plain | Target::Astro => {
+            let open = if fold == "+" { " open" } else { "" };
+            (
+                format!(
+                    "<details class=\"callout\" data-callout=\"{kind}\"{open}>\n<summary class=\"callout-title\">{title}</summary>\n\n"
+                ),
+                "\n</details>\n\n".to_string(),
+            )
+        }
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::Parser;
+
+    use super::*;
+
+    fn parse(markdown: &str) -> Vec<Event<'_>> {
+        let mut events = Parser::new(markdown).collect();
+        merge_text(&mut events);
+        events
+    }
+
+    fn texts(events: &[Event]) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Html(text) => Some(text.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn comments_are_removed() {
+        let mut events = parse("Visible %%hidden%% text\n\n%%\nBlock [link](a.md)\n\n%%\nAfter");
+        strip_comments(&mut events);
+        assert_eq!(texts(&events), vec!["Visible  text", "After"]);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, Event::Start(Tag::Link(..)))));
+    }
+
+    #[test]
+    fn comments_in_code_are_kept() {
+        let mut events = parse("```\n%% not a comment %%\n```");
+        strip_comments(&mut events);
+        assert_eq!(texts(&events), vec!["%% not a comment %%\n"]);
+    }
+
+    #[test]
+    fn highlights_become_mark() {
+        let mut events = parse("Some ==important== text, a == b");
+        convert_highlights(&mut events);
+        assert_eq!(
+            texts(&events),
+            vec!["Some ", "<mark>", "important", "</mark>", " text, a == b"]
+        );
+    }
+
+    #[test]
+    fn block_ids_are_removed() {
+        let mut events = parse("A paragraph ^abc-123\n\n- item ^id1\n- not^id");
+        strip_block_ids(&mut events);
+        assert_eq!(texts(&events), vec!["A paragraph", "item", "not^id"]);
+    }
+
+    #[test]
+    fn math_is_kept_as_written() {
+        let source = "Inline $\\{x\\}$ and\n$$\na \\\\ b\n$$\nCost: $5 or $10";
+        let mut events = parse(source);
+        preserve_math(&mut events, source);
+        assert_eq!(
+            texts(&events),
+            vec![
+                "Inline ",
+                "$\\{x\\}$",
+                " and",
+                "$$\na \\\\ b\n$$",
+                "Cost: $5 or $10"
+            ]
+        );
+    }
+
+    #[test]
+    fn callouts_become_html() {
+        let mut events = parse("> [!tip]- Read *this*\n> Body text\n\n> Plain quote");
+        convert_callouts(&mut events, Target::Plain);
+        let html: Vec<String> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Html(html) => Some(html.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            html,
+            vec![
+                "<details class=\"callout\" data-callout=\"tip\">\n<summary class=\"callout-title\">Read this</summary>\n\n",
+                "\n</details>\n\n"
+            ]
+        );
+        assert!(events.contains(&Event::Text("Body text".into())));
+        assert!(events.contains(&Event::Start(Tag::BlockQuote)));
+    }
+
+    #[test]
+    fn callouts_become_shortcodes() {
+        let (opening, closing) = callout_markup(Target::Zola, "NOTE", "", "");
+        assert_eq!(opening, "{% callout(type=\"note\", title=\"Note\") %}\n\n");
+        assert_eq!(closing, "\n{% end %}\n\n");
+
+        let (opening, _) = callout_markup(Target::Hugo, "warning", "-", "Careful");
+        assert_eq!(
+            opening,
+            "{{% callout type=\"warning\" title=\"Careful\" folded=\"true\" %}}\n\n"
+        );
+    }
+}
@@ -0,0 +1,570 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use obsidian_export::serde_yaml::{Mapping, Value};
+use obsidian_export::Context as ObsidianContext;
+use obsidian_export::{MarkdownEvents, PostprocessorResult};
+use pulldown_cmark::{Event, Tag};
+
+use crate::{
+    images::ImageOptions,
+    manifest::OutputFile,
+    rules::frontmatter_tags,
+    vault::{is_markdown, resolve_link, Note},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    /// Same folder structure as the vault, relative links
+    Plain,
+    /// content/ and static/ folders, site-relative links
+    Hugo,
+    /// content/ and static/ folders with section indexes, site-relative links
+    Zola,
+    /// src/content/notes/ and public/ folders, site-relative links
+    Astro,
+}
+
+// Zola rejects unknown frontmatter keys, the rest is moved under `extra`
+const ZOLA_KEYS: &[&str] = &[
+    "title",
+    "description",
+    "date",
+    "updated",
+    "weight",
+    "draft",
+    "slug",
+    "path",
+    "aliases",
+    "authors",
+    "in_search_index",
+    "template",
+    "taxonomies",
+    "extra",
+];
+
+const SECTION_INDEX: &str = "_index.md";
+// Inside the staging dir, generated section indexes are written here first
+const SECTION_INDEX_DIR: &str = ".sections";
+
+impl Target {
+    fn content_dir(self) -> &'static Path {
+        Path::new(match self {
+            Target::Plain => "",
+            Target::Hugo | Target::Zola => "content",
+            Target::Astro => "src/content/notes",
+        })
+    }
+
+    fn static_dir(self) -> &'static Path {
+        Path::new(match self {
+            Target::Plain => "",
+            Target::Hugo | Target::Zola => "static",
+            Target::Astro => "public",
+        })
+    }
+}
+
+struct Page {
+    slug: String,
+    /// Relative to the content dir of the target
+    output: PathBuf,
+    url: String,
+}
+
+/// Output location of every published note and the attachments they reference
+pub struct Site {
+    target: Target,
+    pages: HashMap<PathBuf, Page>,
+    attachments: Mutex<BTreeSet<PathBuf>>,
+    images: Option<ImageOptions>,
+}
+
+impl Site {
+    pub fn new(
+        root: &Path,
+        target: Target,
+        published: &HashSet<PathBuf>,
+        images: Option<ImageOptions>,
+    ) -> anyhow::Result<Self> {
+        let mut pages = HashMap::new();
+        for path in published {
+            let note = Note::read(root, &root.join(path))?;
+            pages.insert(path.to_owned(), page(target, path, &note.frontmatter));
+        }
+        check_collisions(&pages)?;
+        Ok(Site {
+            target,
+            pages,
+            attachments: Mutex::new(BTreeSet::new()),
+            images,
+        })
+    }
+
+    /// Final location of the notes exported to `staging` and the referenced attachments
+    pub fn outputs(&self, root: &Path, staging: &Path) -> anyhow::Result<Vec<OutputFile>> {
+        let content_dir = self.target.content_dir();
+        let mut outputs = vec![];
+        for (source, page) in &self.pages {
+            let staged = staging.join(source);
+            // Skipped by a postprocessor
+            if !staged.exists() {
+                continue;
+            }
+            outputs.push(OutputFile {
+                source: source.to_owned(),
+                from: staged,
+                output: content_dir.join(&page.output),
+                keep_existing: false,
+            });
+        }
+
+        let static_dir = self.target.static_dir();
+        for attachment in self.attachments.lock().unwrap().iter() {
+            let from = match &self.images {
+                Some(images) if images.output_extension(attachment).is_some() => {
+                    images.optimize(&root.join(attachment))?
+                }
+                _ => root.join(attachment),
+            };
+            outputs.push(OutputFile {
+                source: attachment.to_owned(),
+                from,
+                output: static_dir.join(self.attachment_output(attachment)),
+                keep_existing: false,
+            });
+        }
+
+        if self.target == Target::Zola {
+            outputs.extend(self.section_indexes(staging)?);
+        }
+        Ok(outputs)
+    }
+
+    /// Zola only renders pages inside sections, so every folder needs an `_index.md`
+    fn section_indexes(&self, staging: &Path) -> anyhow::Result<Vec<OutputFile>> {
+        let sections: BTreeSet<&Path> = self
+            .pages
+            .values()
+            .filter_map(|page| page.output.parent())
+            .flat_map(Path::ancestors)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+
+        let mut outputs = vec![];
+        for section in sections {
+            let output = section.join(SECTION_INDEX);
+            if self.pages.values().any(|page| page.output == output) {
+                continue;
+            }
+            let from = staging.join(SECTION_INDEX_DIR).join(&output);
+            fs::create_dir_all(from.parent().unwrap())?;
+            let title = section.file_name().unwrap_or_default().to_string_lossy();
+            fs::write(&from, format!("---\ntitle: \"{title}\"\n---\n"))
+                .context(format!("Failed to write {}", from.display()))?;
+            outputs.push(OutputFile {
+                source: section.to_path_buf(),
+                from,
+                output: self.target.content_dir().join(output),
+                // Hand-written section pages are left alone
+                keep_existing: true,
+            });
+        }
+        Ok(outputs)
+    }
+
+    /// New destination of a link, `None` if it should stay as it is
+    fn rewrite_link(&self, root: &Path, dest: &str, note_dir: &Path) -> Option<String> {
+        let (target, anchor) = resolve_link(dest, note_dir)?;
+        if is_markdown(&target) {
+            if self.target == Target::Plain {
+                return None;
+            }
+            let mut url = self.pages.get(&target)?.url.to_owned();
+            if let Some(anchor) = anchor {
+                url.push('#');
+                url.push_str(anchor);
+            }
+            return Some(url);
+        }
+
+        if !root.join(&target).is_file() {
+            return None;
+        }
+        let output = self.attachment_output(&target);
+        let renamed = output.extension() != target.extension();
+        self.attachments.lock().unwrap().insert(target);
+        match self.target {
+            // Optimised images can get a new extension
+            Target::Plain if renamed => {
+                let extension = output.extension().unwrap_or_default();
+                Some(
+                    Path::new(dest)
+                        .with_extension(extension)
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            }
+            Target::Plain => None,
+            _ => Some(path_url(&output)),
+        }
+    }
+
+    /// Site URL of a published note, `None` for plain exports
+    pub fn note_url(&self, path: &Path) -> Option<String> {
+        match self.target {
+            Target::Plain => None,
+            _ => self.pages.get(path).map(|page| page.url.to_owned()),
+        }
+    }
+
+    /// Location of a generated file in the static folder of the site
+    pub fn static_output(&self, path: &Path) -> PathBuf {
+        self.target.static_dir().join(path)
+    }
+
+    fn attachment_output(&self, path: &Path) -> PathBuf {
+        let mut output = attachment_output(self.target, path);
+        if let Some(extension) = self.images.as_ref().and_then(|i| i.output_extension(path)) {
+            output.set_extension(extension);
+        }
+        output
+    }
+}
+
+fn page(target: Target, path: &Path, frontmatter: &Mapping) -> Page {
+    // Slugified too, so that `/` and `..` can't point outside of the content folder
+    let slug = frontmatter
+        .get("slug")
+        .and_then(Value::as_str)
+        .map(slugify)
+        .filter(|slug| !slug.is_empty())
+        .unwrap_or_else(|| slugify(&path.file_stem().unwrap_or_default().to_string_lossy()));
+    if target == Target::Plain {
+        return Page {
+            slug,
+            output: path.to_path_buf(),
+            url: String::new(),
+        };
+    }
+
+    let dir = slugify_dirs(path.parent());
+    let url = format!("{}{slug}/", dir_url(&dir));
+    Page {
+        output: dir.join(format!("{slug}.md")),
+        slug,
+        url,
+    }
+}
+
+/// Notes whose slugified paths are the same would overwrite each other in the output
+fn check_collisions(pages: &HashMap<PathBuf, Page>) -> anyhow::Result<()> {
+    let mut sources: BTreeMap<&Path, BTreeSet<&Path>> = BTreeMap::new();
+    for (source, page) in pages {
+        sources.entry(&page.output).or_default().insert(source);
+    }
+    let collisions: Vec<String> = sources
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(output, sources)| {
+            let sources: Vec<String> = sources.iter().map(|s| s.display().to_string()).collect();
+            format!("{}: {}", output.display(), sources.join(", "))
+        })
+        .collect();
+    if !collisions.is_empty() {
+        anyhow::bail!(
+            "Notes with the same slug, set a different `slug` in their frontmatter:\n{}",
+            collisions.join("\n")
+        );
+    }
+    Ok(())
+}
+
+fn attachment_output(target: Target, path: &Path) -> PathBuf {
+    if target == Target::Plain {
+        return path.to_path_buf();
+    }
+    let stem = slugify(&path.file_stem().unwrap_or_default().to_string_lossy());
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}.{}", ext.to_string_lossy().to_lowercase()),
+        None => stem,
+    };
+    slugify_dirs(path.parent()).join(file_name)
+}
+
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn slugify_dirs(dir: Option<&Path>) -> PathBuf {
+    dir.map(|dir| {
+        dir.iter()
+            .map(|component| slugify(&component.to_string_lossy()))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn dir_url(dir: &Path) -> String {
+    let mut url = String::from("/");
+    for component in dir {
+        url.push_str(&component.to_string_lossy());
+        url.push('/');
+    }
+    url
+}
+
+fn path_url(path: &Path) -> String {
+    let mut url = dir_url(path);
+    url.pop();
+    url
+}
+
+/// Adds the slug, date, aliases and draft flag expected by the static site generator
+pub fn create_site_frontmatter<'a>(
+    root: &'a Path,
+    site: &'a Site,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult + 'a {
+    move |context, _events| {
+        if site.target == Target::Plain {
+            return PostprocessorResult::Continue;
+        }
+        let current_file = context.current_file().to_owned();
+        let path = current_file.strip_prefix(root).unwrap_or(&current_file);
+        if let Some(page) = site.pages.get(path) {
+            let modified = fs::metadata(&current_file).and_then(|m| m.modified()).ok();
+            site_frontmatter(site.target, page, &mut context.frontmatter, modified);
+        }
+        PostprocessorResult::Continue
+    }
+}
+
+/// Points links to the final location of notes and attachments and collects the attachments
+pub fn create_site_links<'a>(
+    root: &'a Path,
+    site: &'a Site,
+) -> impl Fn(&mut ObsidianContext, &mut MarkdownEvents) -> PostprocessorResult + 'a {
+    move |context, events| {
+        let current_file = context.current_file().to_owned();
+        let note_path = current_file.strip_prefix(root).unwrap_or(&current_file);
+        let note_dir = note_path.parent().unwrap_or(Path::new(""));
+
+        for event in events.iter_mut() {
+            if let Event::Start(Tag::Link(_, dest, _) | Tag::Image(_, dest, _))
+            | Event::End(Tag::Link(_, dest, _) | Tag::Image(_, dest, _)) = event
+            {
+                if let Some(url) = site.rewrite_link(root, dest, note_dir) {
+                    *dest = url.into();
+                }
+            }
+        }
+        PostprocessorResult::Continue
+    }
+}
+
+fn site_frontmatter(
+    target: Target,
+    page: &Page,
+    frontmatter: &mut Mapping,
+    modified: Option<SystemTime>,
+) {
+    frontmatter.insert("slug".into(), page.slug.as_str().into());
+
+    if !frontmatter.contains_key("date") {
+        let date = frontmatter.get("created").cloned().or_else(|| {
+            modified.map(|t| {
+                let date = DateTime::<Local>::from(t).format("%Y-%m-%d");
+                Value::from(date.to_string())
+            })
+        });
+        if let Some(date) = date {
+            frontmatter.insert("date".into(), date);
+        }
+    }
+
+    let draft = match frontmatter.get("draft") {
+        Some(Value::Bool(draft)) => *draft,
+        _ => frontmatter.get("status").and_then(Value::as_str) == Some("draft"),
+    };
+    frontmatter.insert("draft".into(), Value::Bool(draft));
+
+    let aliases: Vec<String> = match frontmatter.get("aliases") {
+        Some(Value::Sequence(aliases)) => aliases
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::String(alias)) => vec![alias.to_owned()],
+        _ => vec![],
+    };
+    if !aliases.is_empty() {
+        // Hugo and Zola use aliases for redirects from old URLs
+        let dir = dir_url(page.output.parent().unwrap_or(Path::new("")));
+        let aliases = match target {
+            Target::Hugo | Target::Zola => aliases
+                .iter()
+                .map(|alias| format!("{dir}{}/", slugify(alias)))
+                .collect(),
+            _ => aliases,
+        };
+        frontmatter.insert(
+            "aliases".into(),
+            Value::Sequence(aliases.into_iter().map(Value::from).collect()),
+        );
+    }
+
+    if target == Target::Zola {
+        zola_frontmatter(frontmatter);
+    }
+}
+
+fn zola_frontmatter(frontmatter: &mut Mapping) {
+    let mut taxonomies = match frontmatter.remove("taxonomies") {
+        Some(Value::Mapping(taxonomies)) => taxonomies,
+        _ => Mapping::new(),
+    };
+    let tags: BTreeSet<String> = frontmatter_tags(frontmatter).into_iter().collect();
+    if frontmatter.remove("tags").is_some() && !tags.is_empty() {
+        let tags = tags.into_iter().map(Value::from).collect();
+        taxonomies.insert("tags".into(), Value::Sequence(tags));
+    }
+
+    let mut extra = match frontmatter.remove("extra") {
+        Some(Value::Mapping(extra)) => extra,
+        _ => Mapping::new(),
+    };
+    let unknown_keys: Vec<Value> = frontmatter
+        .keys()
+        .filter(|key| !key.as_str().is_some_and(|key| ZOLA_KEYS.contains(&key)))
+        .cloned()
+        .collect();
+    for key in unknown_keys {
+        if let Some(value) = frontmatter.remove(&key) {
+            extra.insert(key, value);
+        }
+    }
+
+    if !taxonomies.is_empty() {
+        frontmatter.insert("taxonomies".into(), Value::Mapping(taxonomies));
+    }
+    if !extra.is_empty() {
+        frontmatter.insert("extra".into(), Value::Mapping(extra));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use obsidian_export::serde_yaml;
+
+    use super::*;
+
+    fn frontmatter(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn slugify_text() {
+        assert_eq!(slugify("My Note: Part 2!"), "my-note-part-2");
+        assert_eq!(slugify("  Árvíztűrő  "), "árvíztűrő");
+    }
+
+    #[test]
+    fn hugo_page_location() {
+        let page = page(
+            Target::Hugo,
+            Path::new("Blog Posts/Hello World.md"),
+            &Mapping::new(),
+        );
+        assert_eq!(page.slug, "hello-world");
+        assert_eq!(page.output, PathBuf::from("blog-posts/hello-world.md"));
+        assert_eq!(page.url, "/blog-posts/hello-world/");
+    }
+
+    #[test]
+    fn frontmatter_slug_wins() {
+        let page = page(
+            Target::Zola,
+            Path::new("A.md"),
+            &frontmatter("slug: custom"),
+        );
+        assert_eq!(page.url, "/custom/");
+    }
+
+    #[test]
+    fn frontmatter_slug_stays_in_its_folder() {
+        let page = page(
+            Target::Hugo,
+            Path::new("Blog/A.md"),
+            &frontmatter("slug: ../../x/y"),
+        );
+        assert_eq!(page.output, PathBuf::from("blog/x-y.md"));
+        assert_eq!(page.url, "/blog/x-y/");
+    }
+
+    #[test]
+    fn slug_collisions_are_rejected() {
+        let pages: HashMap<PathBuf, Page> = ["Blog/My Note.md", "Blog/my-note.md", "Other.md"]
+            .into_iter()
+            .map(|path| {
+                let path = PathBuf::from(path);
+                let page = page(Target::Hugo, &path, &Mapping::new());
+                (path, page)
+            })
+            .collect();
+        let error = check_collisions(&pages).unwrap_err().to_string();
+        assert!(error.contains("blog/my-note.md: Blog/My Note.md, Blog/my-note.md"));
+        assert!(!error.contains("Other.md"));
+    }
+
+    #[test]
+    fn plain_keeps_paths() {
+        let path = Path::new("Blog/Image 1.PNG");
+        assert_eq!(attachment_output(Target::Plain, path), path);
+        assert_eq!(
+            attachment_output(Target::Hugo, path),
+            PathBuf::from("blog/image-1.png")
+        );
+    }
+
+    #[test]
+    fn hugo_frontmatter() {
+        let page = page(Target::Hugo, Path::new("Blog/Post.md"), &Mapping::new());
+        let mut fm = frontmatter("created: 2024-01-02\nstatus: draft\naliases: [Old Name]");
+        site_frontmatter(Target::Hugo, &page, &mut fm, None);
+        assert_eq!(
+            fm,
+            frontmatter(
+                "created: 2024-01-02\nstatus: draft\naliases: [/blog/old-name/]\nslug: post\ndate: 2024-01-02\ndraft: true"
+            )
+        );
+    }
+
+    #[test]
+    fn zola_moves_unknown_keys() {
+        let page = page(Target::Zola, Path::new("Post.md"), &Mapping::new());
+        let mut fm = frontmatter("title: Post\ndate: 2024-01-02\ntags: [Rust]\nmood: great");
+        site_frontmatter(Target::Zola, &page, &mut fm, None);
+        assert_eq!(
+            fm,
+            frontmatter(
+                "title: Post\ndate: 2024-01-02\nslug: post\ndraft: false\ntaxonomies: {tags: [rust]}\nextra: {mood: great}"
+            )
+        );
+    }
+}
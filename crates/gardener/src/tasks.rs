@@ -0,0 +1,553 @@
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use globset::GlobSet;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::vault::{all_vault_files, inline_tags, is_markdown};
+
+const ID_LENGTH: usize = 6;
+
+lazy_static! {
+    static ref TASK: Regex = Regex::new(r"^(\s*[-*+] \[)(.)(\] )(.*)$").unwrap();
+    static ref DATE_FIELD: Regex =
+        Regex::new(r"(📅|📆|🗓\x{FE0F}?|⏳|🛫|✅|➕|❌)\s*(\d{4}-\d{2}-\d{2})").unwrap();
+    static ref PRIORITY: Regex = Regex::new(r"🔺|⏫|🔼|🔽|⏬").unwrap();
+    static ref RECURRENCE: Regex = Regex::new(r"🔁\x{FE0F}?\s*([a-zA-Z0-9 ,!]+)").unwrap();
+    static ref TASK_ID: Regex = Regex::new(r"🆔\s*([\w-]+)").unwrap();
+    static ref DEPENDS_ON: Regex = Regex::new(r"⛔\s*[\w,-]+").unwrap();
+    static ref BLOCK_ID: Regex = Regex::new(r"\s+\^[A-Za-z0-9-]+\s*$").unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Todo,
+    InProgress,
+    Done,
+    Cancelled,
+}
+
+/// Obsidian Tasks priorities, from the highest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Highest,
+    High,
+    Medium,
+    Normal,
+    Low,
+    Lowest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    Due,
+    Priority,
+    Path,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TaskFormat {
+    Table,
+    Json,
+    /// Calendar of the tasks with a due or scheduled date
+    Ics,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Task {
+    /// The Tasks plugin ID if there is one, otherwise derived from the path and description
+    pub id: String,
+    /// Relative to the vault root
+    pub path: PathBuf,
+    pub line: usize,
+    pub status: Status,
+    pub description: String,
+    pub priority: Priority,
+    pub due: Option<NaiveDate>,
+    pub scheduled: Option<NaiveDate>,
+    pub start: Option<NaiveDate>,
+    pub created: Option<NaiveDate>,
+    pub done: Option<NaiveDate>,
+    pub recurrence: Option<String>,
+    pub tags: Vec<String>,
+}
+
+pub struct TaskFilter {
+    pub statuses: Vec<Status>,
+    /// Tasks with any of these tags
+    pub tags: Vec<String>,
+    /// Inclusive
+    pub due_before: Option<NaiveDate>,
+    /// Inclusive
+    pub due_after: Option<NaiveDate>,
+    /// Tasks with this priority or higher
+    pub min_priority: Option<Priority>,
+    /// Globs of the note paths relative to the root
+    pub paths: Option<GlobSet>,
+}
+
+impl TaskFilter {
+    fn matches(&self, task: &Task) -> bool {
+        let status_match = self.statuses.contains(&task.status);
+        let tags_match = self.tags.is_empty() || task.tags.iter().any(|t| self.tags.contains(t));
+        let due_match = match (self.due_before, self.due_after) {
+            (None, None) => true,
+            (before, after) => task.due.is_some_and(|due| {
+                before.is_none_or(|before| due <= before) && after.is_none_or(|after| due >= after)
+            }),
+        };
+        let priority_match = self.min_priority.is_none_or(|min| task.priority <= min);
+        let path_match = self
+            .paths
+            .as_ref()
+            .is_none_or(|paths| paths.is_match(&task.path));
+        status_match && tags_match && due_match && priority_match && path_match
+    }
+}
+
+pub struct TasksOptions<'a> {
+    pub filter: TaskFilter,
+    pub sort: SortKey,
+    pub format: TaskFormat,
+    /// Prints to stdout if not set
+    pub output: Option<&'a str>,
+}
+
+pub fn run_tasks(root: &str, options: &TasksOptions) -> anyhow::Result<()> {
+    let root = Path::new(root);
+    let mut tasks: Vec<Task> = collect_tasks(root)?
+        .into_iter()
+        .filter(|task| options.filter.matches(task))
+        .collect();
+    sort_tasks(&mut tasks, options.sort);
+
+    let rendered = match options.format {
+        TaskFormat::Table => format_table(&tasks),
+        TaskFormat::Json => serde_json::to_string_pretty(&tasks)? + "\n",
+        TaskFormat::Ics => to_ics(&tasks),
+    };
+    match options.output {
+        Some(output) => {
+            fs::write(output, rendered).context(format!("Failed to write {output}"))?;
+            println!("Wrote {} tasks to {output}", tasks.len());
+        }
+        None => print!("{rendered}"),
+    }
+    Ok(())
+}
+
+/// Checks the task in its note and adds the done date
+pub fn run_complete(root: &str, id: &str) -> anyhow::Result<()> {
+    let root = Path::new(root);
+    let tasks: Vec<Task> = collect_tasks(root)?
+        .into_iter()
+        .filter(|task| task.id == id)
+        .collect();
+    let task = match tasks.as_slice() {
+        [task] => task,
+        [] => anyhow::bail!("No task with ID {id}"),
+        _ => anyhow::bail!("Multiple tasks have the ID {id}, give them a 🆔 in Obsidian"),
+    };
+    if task.status == Status::Done {
+        println!("Already done: {}", task.description);
+        return Ok(());
+    }
+
+    let path = root.join(&task.path);
+    let content = fs::read_to_string(&path).context(format!("Can't read {}", path.display()))?;
+    let today = Local::now().date_naive();
+    let updated: Vec<String> = content
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            if i + 1 == task.line {
+                complete_line(line, today)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    fs::write(&path, updated.join("\n")).context(format!("Failed to write {}", path.display()))?;
+    println!(
+        "Done: {} ({}:{})",
+        task.description,
+        task.path.display(),
+        task.line
+    );
+    Ok(())
+}
+
+fn collect_tasks(root: &Path) -> anyhow::Result<Vec<Task>> {
+    let mut tasks = vec![];
+    // Tasks of notes excluded from exports are still tasks
+    for abs_path in all_vault_files(root).into_iter().filter(|p| is_markdown(p)) {
+        let content =
+            fs::read_to_string(&abs_path).context(format!("Can't read {}", abs_path.display()))?;
+        let path = abs_path.strip_prefix(root).unwrap_or(&abs_path);
+        tasks.extend(parse_tasks(path, &content));
+    }
+    Ok(tasks)
+}
+
+fn parse_tasks(path: &Path, content: &str) -> Vec<Task> {
+    let mut tasks = vec![];
+    let mut in_code = false;
+    for (i, line) in content.split('\n').enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        if let Some(task) = parse_task(path, i + 1, line) {
+            tasks.push(task);
+        }
+    }
+    tasks
+}
+
+fn parse_task(path: &Path, line_number: usize, line: &str) -> Option<Task> {
+    let cap = TASK.captures(line.trim_end_matches('\r'))?;
+    let status = match &cap[2] {
+        "x" | "X" => Status::Done,
+        "-" => Status::Cancelled,
+        "/" => Status::InProgress,
+        _ => Status::Todo,
+    };
+    let text = &cap[4];
+
+    let mut task = Task {
+        id: String::new(),
+        path: path.to_path_buf(),
+        line: line_number,
+        status,
+        description: String::new(),
+        priority: Priority::Normal,
+        due: None,
+        scheduled: None,
+        start: None,
+        created: None,
+        done: None,
+        recurrence: None,
+        tags: inline_tags(text).collect(),
+    };
+    for field in DATE_FIELD.captures_iter(text) {
+        let date = NaiveDate::parse_from_str(&field[2], "%Y-%m-%d").ok();
+        match field[1].trim_end_matches('\u{FE0F}') {
+            "⏳" => task.scheduled = date,
+            "🛫" => task.start = date,
+            "✅" => task.done = date,
+            "➕" => task.created = date,
+            // Cancelled date
+            "❌" => {}
+            _ => task.due = date,
+        }
+    }
+    if let Some(priority) = PRIORITY.find(text) {
+        task.priority = match priority.as_str() {
+            "🔺" => Priority::Highest,
+            "⏫" => Priority::High,
+            "🔼" => Priority::Medium,
+            "🔽" => Priority::Low,
+            _ => Priority::Lowest,
+        };
+    }
+    task.recurrence = RECURRENCE
+        .captures(text)
+        .map(|cap| cap[1].trim().to_string());
+
+    let mut description = text.to_string();
+    for regex in [
+        &*DATE_FIELD,
+        &*PRIORITY,
+        &*RECURRENCE,
+        &*TASK_ID,
+        &*DEPENDS_ON,
+        &*BLOCK_ID,
+    ] {
+        description = regex.replace_all(&description, "").into_owned();
+    }
+    task.description = description.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    task.id = match TASK_ID.captures(text) {
+        Some(id) => id[1].to_string(),
+        None => {
+            let hash = Sha256::digest(format!("{}\n{}", path.display(), task.description));
+            format!("{hash:x}")[..ID_LENGTH].to_string()
+        }
+    };
+    Some(task)
+}
+
+/// Checks the box and adds the done date before the block ID, like the Tasks plugin does
+fn complete_line(line: &str, today: NaiveDate) -> String {
+    let (line, line_ending) = match line.strip_suffix('\r') {
+        Some(line) => (line, "\r"),
+        None => (line, ""),
+    };
+    let Some(cap) = TASK.captures(line) else {
+        return format!("{line}{line_ending}");
+    };
+    let text = &cap[4];
+    let (text, block_id) = match BLOCK_ID.find(text) {
+        Some(found) => (&text[..found.start()], found.as_str()),
+        None => (text, ""),
+    };
+    format!(
+        "{}x{}{} ✅ {}{block_id}{line_ending}",
+        &cap[1],
+        &cap[3],
+        text.trim_end(),
+        today.format("%Y-%m-%d")
+    )
+}
+
+fn sort_tasks(tasks: &mut [Task], sort: SortKey) {
+    // Tasks without a due date go last
+    let by_due = |a: &Task, b: &Task| match (a.due, b.due) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    let by_path = |a: &Task, b: &Task| a.path.cmp(&b.path).then(a.line.cmp(&b.line));
+    tasks.sort_by(|a, b| match sort {
+        SortKey::Due => by_due(a, b)
+            .then(a.priority.cmp(&b.priority))
+            .then(by_path(a, b)),
+        SortKey::Priority => a
+            .priority
+            .cmp(&b.priority)
+            .then(by_due(a, b))
+            .then(by_path(a, b)),
+        SortKey::Path => by_path(a, b),
+    });
+}
+
+fn format_table(tasks: &[Task]) -> String {
+    let rows: Vec<[String; 5]> = tasks
+        .iter()
+        .map(|task| {
+            [
+                task.id.to_owned(),
+                variant_name(task.status),
+                task.due.map(|d| d.to_string()).unwrap_or_default(),
+                variant_name(task.priority),
+                format!("{}:{}", task.path.display(), task.line),
+            ]
+        })
+        .collect();
+    let header = ["ID", "STATUS", "DUE", "PRIORITY", "LOCATION"].map(String::from);
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |row: &[String; 5], description: &str| {
+        let [id, status, due, priority, location] = row;
+        format!(
+            "{id:w0$}  {status:w1$}  {due:w2$}  {priority:w3$}  {location:w4$}  {description}\n",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        )
+    };
+    let mut table = format_row(&header, "TASK");
+    for (row, task) in rows.iter().zip(tasks) {
+        table.push_str(&format_row(row, &task.description));
+    }
+    table.push_str(&format!("\n{} tasks\n", tasks.len()));
+    table
+}
+
+/// Same name as in the JSON output
+fn variant_name(value: impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// All-day events on the due date, or the scheduled date of tasks without one
+fn to_ics(tasks: &[Task]) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//homecooked//gardener//EN".to_string(),
+    ];
+    for task in tasks {
+        let Some(date) = task.due.or(task.scheduled) else {
+            continue;
+        };
+        let status = match task.status {
+            Status::Done => "CONFIRMED",
+            Status::Cancelled => "CANCELLED",
+            _ => "TENTATIVE",
+        };
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@gardener", task.id),
+            format!("DTSTAMP:{stamp}"),
+            format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")),
+            format!("SUMMARY:{}", escape_ics(&task.description)),
+            format!(
+                "DESCRIPTION:{}",
+                escape_ics(&format!("{}:{}", task.path.display(), task.line))
+            ),
+            format!("STATUS:{status}"),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    // iCalendar requires CRLF line endings
+    let lines: Vec<String> = lines.iter().map(|line| fold_ics_line(line)).collect();
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Lines longer than 75 octets are split, continuation lines start with a space (RFC 5545)
+fn fold_ics_line(line: &str) -> String {
+    const MAX_OCTETS: usize = 75;
+    let mut folded = String::with_capacity(line.len());
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > MAX_OCTETS {
+            folded.push_str("\r\n ");
+            line_len = 1;
+        }
+        folded.push(c);
+        line_len += c.len_utf8();
+    }
+    folded
+}
+
+fn escape_ics(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+    }
+
+    fn task(line: &str) -> Task {
+        parse_task(Path::new("Notes/Todo.md"), 3, line).unwrap()
+    }
+
+    #[test]
+    fn parses_tasks_metadata() {
+        let task = task(
+            "  - [ ] Write report #work ⏫ 🔁 every week 📅 2024-03-01 ⏳ 2024-02-28 ➕ 2024-02-20 🆔 abc123 ^block-1",
+        );
+        assert_eq!(task.id, "abc123");
+        assert_eq!(task.status, Status::Todo);
+        assert_eq!(task.description, "Write report #work");
+        assert_eq!(task.priority, Priority::High);
+        assert_eq!(task.due, date("2024-03-01"));
+        assert_eq!(task.scheduled, date("2024-02-28"));
+        assert_eq!(task.created, date("2024-02-20"));
+        assert_eq!(task.recurrence.as_deref(), Some("every week"));
+        assert_eq!(task.tags, vec!["work"]);
+    }
+
+    #[test]
+    fn ids_are_stable_across_status_changes() {
+        let todo = task("- [ ] Call the bank");
+        let done = task("- [x] Call the bank ✅ 2024-03-02");
+        assert_eq!(todo.id.len(), ID_LENGTH);
+        assert_eq!(todo.id, done.id);
+        assert_eq!(done.status, Status::Done);
+        assert_eq!(done.done, date("2024-03-02"));
+    }
+
+    #[test]
+    fn tasks_in_code_blocks_are_ignored() {
+        let content = "- [ ] real\n```\n- [ ] example\n```\n- [/] started\nnot a task";
+        let tasks = parse_tasks(Path::new("a.md"), content);
+        let lines: Vec<(usize, Status)> = tasks.iter().map(|t| (t.line, t.status)).collect();
+        assert_eq!(lines, vec![(1, Status::Todo), (5, Status::InProgress)]);
+    }
+
+    #[test]
+    fn completing_adds_done_date() {
+        assert_eq!(
+            complete_line(
+                "- [ ] Pay rent 📅 2024-03-01 ^rent",
+                date("2024-03-02").unwrap()
+            ),
+            "- [x] Pay rent 📅 2024-03-01 ✅ 2024-03-02 ^rent"
+        );
+    }
+
+    #[test]
+    fn filter_and_sort() {
+        let mut tasks = vec![
+            task("- [ ] no date 🔺"),
+            task("- [ ] later 📅 2024-04-01"),
+            task("- [ ] sooner 🔽 📅 2024-03-01 #home"),
+            task("- [x] finished 📅 2024-03-01"),
+        ];
+        let filter = TaskFilter {
+            statuses: vec![Status::Todo],
+            tags: vec![],
+            due_before: None,
+            due_after: None,
+            min_priority: None,
+            paths: None,
+        };
+        tasks.retain(|task| filter.matches(task));
+        sort_tasks(&mut tasks, SortKey::Due);
+        let descriptions: Vec<&str> = tasks.iter().map(|t| t.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["sooner #home", "later", "no date"]);
+
+        let filter = TaskFilter {
+            due_before: date("2024-03-15"),
+            ..filter
+        };
+        assert_eq!(tasks.iter().filter(|task| filter.matches(task)).count(), 1);
+    }
+
+    #[test]
+    fn ics_events_for_dated_tasks() {
+        let ics = to_ics(&[
+            task("- [ ] Pay rent, now 📅 2024-03-01"),
+            task("- [ ] Someday"),
+        ]);
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240301\r\n"));
+        assert!(ics.contains("SUMMARY:Pay rent\\, now\r\n"));
+    }
+
+    #[test]
+    fn long_ics_lines_are_folded() {
+        let line = format!("SUMMARY:{}", "é".repeat(50));
+        let folded = fold_ics_line(&line);
+        assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+        assert_eq!(folded.split("\r\n").count(), 2);
+        assert_eq!(folded.replace("\r\n ", ""), line);
+        assert_eq!(fold_ics_line("SUMMARY:short"), "SUMMARY:short");
+    }
+}
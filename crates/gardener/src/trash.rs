@@ -0,0 +1,188 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+const UNDO_LOG_FILENAME: &str = "undo.json";
+
+/// Obsidian's own trash folder inside the vault
+pub const VAULT_TRASH_DIR: &str = ".trash";
+
+/// Record of a clean run, stored next to the trashed files
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct UndoLog {
+    pub run_id: String,
+    pub files: Vec<TrashedFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TrashedFile {
+    /// Relative to the vault root
+    pub original: PathBuf,
+    /// Relative to the run folder in the trash
+    pub trashed: PathBuf,
+    pub size: u64,
+}
+
+impl UndoLog {
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// Timestamp with milliseconds, followed by a counter if a run with the same ID is already in
+/// `trash_dir`
+pub fn new_run_id(trash_dir: &Path) -> String {
+    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S-%3f").to_string();
+    let mut run_id = timestamp.clone();
+    let mut counter = 1;
+    while trash_dir.join(&run_id).exists() {
+        counter += 1;
+        run_id = format!("{timestamp}-{counter}");
+    }
+    run_id
+}
+
+/// Moves `files` (relative to `root`) into a folder of the run inside `trash_dir`, keeping
+/// their folder structure, and writes the undo log
+pub fn move_to_trash(
+    root: &Path,
+    trash_dir: &Path,
+    run_id: &str,
+    files: &[PathBuf],
+) -> anyhow::Result<UndoLog> {
+    let run_dir = trash_dir.join(run_id);
+    let mut log = UndoLog {
+        run_id: run_id.to_string(),
+        files: vec![],
+    };
+
+    for file in files {
+        let from = root.join(file);
+        let size = fs::metadata(&from)
+            .context(format!("Can't read {}", from.display()))?
+            .len();
+        move_file(&from, &run_dir.join(file))?;
+        log.files.push(TrashedFile {
+            original: file.to_owned(),
+            trashed: file.to_owned(),
+            size,
+        });
+        // Written after every file so that an interrupted run can be restored too
+        write_log(&run_dir, &log)?;
+    }
+    Ok(log)
+}
+
+/// Moves the files of a previous run back to their original location
+pub fn restore(root: &Path, trash_dir: &Path, run_id: &str) -> anyhow::Result<UndoLog> {
+    let run_dir = trash_dir.join(run_id);
+    let log_path = run_dir.join(UNDO_LOG_FILENAME);
+    let log: UndoLog = serde_json::from_str(
+        &fs::read_to_string(&log_path)
+            .context(format!("No undo log found at {}", log_path.display()))?,
+    )
+    .context("Invalid undo log")?;
+
+    let mut skipped = 0;
+    for file in &log.files {
+        let to = root.join(&file.original);
+        if to.exists() {
+            println!(
+                "Skipping {}, a file already exists at the original location",
+                file.original.display()
+            );
+            skipped += 1;
+            continue;
+        }
+        move_file(&run_dir.join(&file.trashed), &to)?;
+    }
+
+    if skipped == 0 {
+        fs::remove_dir_all(&run_dir).context(format!("Failed to remove {}", run_dir.display()))?;
+    }
+    Ok(log)
+}
+
+fn write_log(run_dir: &Path, log: &UndoLog) -> anyhow::Result<()> {
+    let path = run_dir.join(UNDO_LOG_FILENAME);
+    fs::write(&path, serde_json::to_string_pretty(log)?)
+        .context(format!("Failed to write {}", path.display()))
+}
+
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // The trash can be on a different filesystem
+    fs::copy(from, to).context(format!("Failed to move {}", from.display()))?;
+    fs::remove_file(from).context(format!("Failed to remove {}", from.display()))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn trash_and_restore_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Attachments")).unwrap();
+        fs::write(root.join("Attachments/a.png"), "image").unwrap();
+        let trash_dir = root.join(VAULT_TRASH_DIR);
+
+        let files = vec![PathBuf::from("Attachments/a.png")];
+        let log = move_to_trash(root, &trash_dir, "run", &files).unwrap();
+        assert_eq!(log.total_size(), 5);
+        assert!(!root.join("Attachments/a.png").exists());
+        assert!(trash_dir.join("run/Attachments/a.png").exists());
+
+        let restored = restore(root, &trash_dir, "run").unwrap();
+        assert_eq!(restored, log);
+        assert_eq!(
+            fs::read_to_string(root.join("Attachments/a.png")).unwrap(),
+            "image"
+        );
+        assert!(!trash_dir.join("run").exists());
+    }
+
+    #[test]
+    fn run_ids_are_unique() {
+        let trash_dir = TempDir::new().unwrap();
+        let first = new_run_id(trash_dir.path());
+        fs::create_dir_all(trash_dir.path().join(&first)).unwrap();
+        let second = new_run_id(trash_dir.path());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+use ignore::WalkBuilder;
+use lazy_static::lazy_static;
+use obsidian_export::serde_yaml::{self, Mapping};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+
+use crate::rules::{frontmatter_tags, normalize_tag};
+
+// Same ignore file as obsidian-export uses
+const EXPORT_IGNORE_FILENAME: &str = ".export-ignore";
+
+// Characters of a path that need escaping in a Markdown link destination
+const LINK_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'#')
+    .add(b'%')
+    .add(b'(')
+    .add(b')')
+    .add(b'<')
+    .add(b'>');
+
+lazy_static! {
+    static ref INLINE_TAG: Regex = Regex::new(r"(?:^|\s)#([\w/-]*[^\W\d][\w/-]*)").unwrap();
+    static ref WIKILINK: Regex =
+        Regex::new(r"(!?)\[\[([^\[\]|#^]*)([#^][^\[\]|]*)?(?:\|([^\[\]]*))?\]\]").unwrap();
+}
+
+pub struct Note {
+    /// Relative to the vault root
+    pub path: PathBuf,
+    pub frontmatter: Mapping,
+    pub body: String,
+}
+
+impl Note {
+    pub fn read(root: &Path, path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).context(format!("Can't read {}", path.display()))?;
+        let (frontmatter, body) = split_frontmatter(&content)
+            .context(format!("Invalid frontmatter in {}", path.display()))?;
+        Ok(Note {
+            path: path.strip_prefix(root).unwrap_or(path).to_path_buf(),
+            frontmatter,
+            body: body.to_string(),
+        })
+    }
+
+    /// Frontmatter tags and inline tags of the text (not code) of the note
+    pub fn tags(&self) -> HashSet<String> {
+        let mut tags = frontmatter_tags(&self.frontmatter);
+        for event in Parser::new(&self.body) {
+            if let Event::Text(text) = event {
+                tags.extend(inline_tags(&text));
+            }
+        }
+        tags
+    }
+}
+
+/// Files of the vault, walked the same way as obsidian-export does
+pub fn vault_files(root: &Path) -> Vec<PathBuf> {
+    walk_files(
+        WalkBuilder::new(root)
+            .add_custom_ignore_filename(EXPORT_IGNORE_FILENAME)
+            .build(),
+    )
+}
+
+/// All files of the vault, including the ones excluded from exports by `.export-ignore`
+pub fn all_vault_files(root: &Path) -> Vec<PathBuf> {
+    walk_files(WalkBuilder::new(root).build())
+}
+
+fn walk_files(walk: ignore::Walk) -> Vec<PathBuf> {
+    walk.filter_map(|result| result.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+pub fn markdown_files(root: &Path) -> Vec<PathBuf> {
+    vault_files(root)
+        .into_iter()
+        .filter(|path| is_markdown(path))
+        .collect()
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("md"))
+}
+
+/// Byte ranges of code blocks and inline code, where links and tags are just text
+pub fn code_ranges(text: &str) -> Vec<Range<usize>> {
+    Parser::new(text)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Splits a note into its parsed YAML frontmatter and the rest of the content
+pub fn split_frontmatter(content: &str) -> anyhow::Result<(Mapping, &str)> {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return Ok((Mapping::new(), content));
+    };
+    let Some(end) = rest.find("\n---") else {
+        return Ok((Mapping::new(), content));
+    };
+
+    let yaml = &rest[..end];
+    let body = rest[end + 4..].trim_start_matches(['\r', '\n']);
+    let frontmatter = if yaml.trim().is_empty() {
+        Mapping::new()
+    } else {
+        serde_yaml::from_str(yaml)?
+    };
+    Ok((frontmatter, body))
+}
+
+/// Inline `#tags` of a Markdown text, normalized like frontmatter tags
+pub fn inline_tags(text: &str) -> impl Iterator<Item = String> + '_ {
+    INLINE_TAG
+        .captures_iter(text)
+        .map(|cap| normalize_tag(&cap[1]))
+}
+
+/// Resolves `.` and `..` components without touching the filesystem
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WikiLink<'a> {
+    /// `![[...]]`, the target is transcluded
+    pub embed: bool,
+    pub target: &'a str,
+    /// Heading (`#Heading`) or block reference (`^id`) including the prefix
+    pub section: Option<&'a str>,
+    pub label: Option<&'a str>,
+    /// Byte range of the whole link in the text
+    pub range: std::ops::Range<usize>,
+}
+
+pub fn wikilinks(text: &str) -> Vec<WikiLink<'_>> {
+    WIKILINK
+        .captures_iter(text)
+        .map(|cap| WikiLink {
+            embed: !cap[1].is_empty(),
+            target: cap.get(2).unwrap().as_str().trim(),
+            section: cap.get(3).map(|m| m.as_str()),
+            label: cap.get(4).map(|m| m.as_str()),
+            range: cap.get(0).unwrap().range(),
+        })
+        .collect()
+}
+
+impl WikiLink<'_> {
+    /// The same link pointing to another target
+    pub fn with_target(&self, target: &str) -> String {
+        let mut link = String::new();
+        if self.embed {
+            link.push('!');
+        }
+        link.push_str("[[");
+        link.push_str(target);
+        if let Some(section) = self.section {
+            link.push_str(section);
+        }
+        if let Some(label) = self.label {
+            link.push('|');
+            link.push_str(label);
+        }
+        link.push_str("]]");
+        link
+    }
+}
+
+/// Replaces the wikilinks for which `replace` returns a new link, `None` if nothing changed
+pub fn replace_wikilinks(
+    text: &str,
+    mut replace: impl FnMut(&WikiLink) -> Option<String>,
+) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for link in wikilinks(text) {
+        if let Some(new_link) = replace(&link) {
+            result.push_str(&text[last..link.range.start]);
+            result.push_str(&new_link);
+            last = link.range.end;
+        }
+    }
+    if last == 0 {
+        return None;
+    }
+    result.push_str(&text[last..]);
+    Some(result)
+}
+
+/// Wikilink target pointing to `path` in the style of the `original` target: the full path
+/// if it had folders, only the file name otherwise, and `.md` only if it was there
+pub fn link_target_for(original: &str, path: &Path) -> String {
+    let path = if is_markdown(path) && !original.to_lowercase().ends_with(".md") {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    if original.contains('/') {
+        path.to_string_lossy().replace('\\', "/")
+    } else {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Resolves wikilink targets like Obsidian: by path from the vault root or by file name
+/// anywhere in the vault, case-insensitively and with `.md` being optional
+pub struct VaultIndex {
+    by_path: HashMap<String, PathBuf>,
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl VaultIndex {
+    pub fn build(root: &Path) -> Self {
+        let paths = vault_files(root)
+            .into_iter()
+            .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf));
+        Self::from_paths(paths)
+    }
+
+    /// `paths` are relative to the vault root
+    pub fn from_paths(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut by_path = HashMap::new();
+        let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            by_name
+                .entry(name.to_string_lossy().to_lowercase())
+                .or_default()
+                .push(path.to_owned());
+            by_path.insert(index_key(&path), path);
+        }
+        VaultIndex { by_path, by_name }
+    }
+
+    /// Vault-relative path of the file a wikilink target points to
+    pub fn resolve(&self, target: &str) -> Option<&Path> {
+        let target = target.trim().trim_start_matches('/');
+        if target.is_empty() {
+            return None;
+        }
+        self.resolve_exact(&format!("{target}.md"))
+            .or_else(|| self.resolve_exact(target))
+    }
+
+    fn resolve_exact(&self, target: &str) -> Option<&Path> {
+        let key = target.to_lowercase();
+        if let Some(path) = self.by_path.get(&key) {
+            return Some(path);
+        }
+        let name = key.rsplit('/').next()?;
+        let suffix = format!("/{key}");
+        // When there are multiple matches, Obsidian prefers the one closest to the root
+        self.by_name
+            .get(name)?
+            .iter()
+            .filter(|path| !key.contains('/') || index_key(path).ends_with(&suffix))
+            .min_by_key(|path| (path.components().count(), path.as_os_str().len()))
+            .map(PathBuf::as_path)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.by_path.contains_key(&index_key(path))
+    }
+}
+
+fn index_key(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").to_lowercase()
+}
+
+/// Vault-relative path and anchor of a local link found in a note inside `note_dir`.
+/// Returns `None` for external links and links to a heading of the same note.
+pub fn resolve_link<'a>(dest: &'a str, note_dir: &Path) -> Option<(PathBuf, Option<&'a str>)> {
+    if dest.contains("://") || dest.starts_with("mailto:") {
+        return None;
+    }
+    let (path, anchor) = match dest.split_once('#') {
+        Some((path, anchor)) => (path, Some(anchor)),
+        None => (dest, None),
+    };
+    if path.is_empty() {
+        return None;
+    }
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    Some((normalize_path(&note_dir.join(decoded.as_ref())), anchor))
+}
+
+/// Markdown link destination pointing to the vault-relative `target` from a note in `note_dir`
+pub fn link_destination(note_dir: &Path, target: &Path) -> String {
+    let path = relative_path(note_dir, target);
+    let path = path.to_string_lossy().replace('\\', "/");
+    utf8_percent_encode(&path, LINK_ENCODE_SET).to_string()
+}
+
+fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_frontmatter_parses_yaml() {
+        let (frontmatter, body) = split_frontmatter("---\ntitle: A\n---\n\nBody").unwrap();
+        assert_eq!(frontmatter.get("title").and_then(|v| v.as_str()), Some("A"));
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn split_frontmatter_without_frontmatter() {
+        let (frontmatter, body) = split_frontmatter("# Heading\n---\n").unwrap();
+        assert!(frontmatter.is_empty());
+        assert_eq!(body, "# Heading\n---\n");
+    }
+
+    #[test]
+    fn inline_tags_skip_headings_and_numbers() {
+        let tags: Vec<String> =
+            inline_tags("# Heading\nText #Rust and #project/a, issue #123").collect();
+        assert_eq!(tags, vec!["rust", "project/a"]);
+    }
+
+    #[test]
+    fn wikilinks_are_parsed() {
+        let links = wikilinks("See [[Note#Heading|label]] and ![[img.png]], not [link](a.md)");
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].target, "Note");
+        assert_eq!(links[0].section, Some("#Heading"));
+        assert_eq!(links[0].label, Some("label"));
+        assert!(!links[0].embed);
+        assert_eq!(links[1].target, "img.png");
+        assert!(links[1].embed);
+        assert_eq!(links[1].range, 31..43);
+    }
+
+    #[test]
+    fn wikilinks_are_replaced() {
+        let text = "A [[Old]] B ![[Folder/Old#Heading|label]] C [[Other]]";
+        let new_path = Path::new("New Folder/New.md");
+        let replaced = replace_wikilinks(text, |link| {
+            link.target
+                .ends_with("Old")
+                .then(|| link.with_target(&link_target_for(link.target, new_path)))
+        });
+        assert_eq!(
+            replaced.as_deref(),
+            Some("A [[New]] B ![[New Folder/New#Heading|label]] C [[Other]]")
+        );
+        assert_eq!(replace_wikilinks(text, |_| None), None);
+    }
+
+    #[test]
+    fn vault_index_resolves_like_obsidian() {
+        let index = VaultIndex::from_paths(
+            [
+                "Notes/Rust.md",
+                "Archive/Old/Rust.md",
+                "Assets/img.png",
+                "v1.2.md",
+            ]
+            .into_iter()
+            .map(PathBuf::from),
+        );
+        assert_eq!(index.resolve("rust"), Some(Path::new("Notes/Rust.md")));
+        assert_eq!(
+            index.resolve("Old/Rust"),
+            Some(Path::new("Archive/Old/Rust.md"))
+        );
+        assert_eq!(index.resolve("img.png"), Some(Path::new("Assets/img.png")));
+        assert_eq!(index.resolve("v1.2"), Some(Path::new("v1.2.md")));
+        assert_eq!(index.resolve("Missing"), None);
+    }
+
+    #[test]
+    fn normalize_path_resolves_parent_dirs() {
+        assert_eq!(
+            normalize_path(Path::new("a/b/../c/./d.md")),
+            PathBuf::from("a/c/d.md")
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            relative_path(Path::new("a/b"), Path::new("a/c/d.md")),
+            PathBuf::from("../c/d.md")
+        );
+        assert_eq!(
+            relative_path(Path::new(""), Path::new("a/d.md")),
+            PathBuf::from("a/d.md")
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::{Client, Url};
+
+use super::{resolve, DirectionMeasurement, Measurement, MeasurementBackend, Ping, Server};
+
+pub const DEFAULT_UPLOAD_SIZE: usize = 10 * 1000 * 1000;
+
+const PING_COUNT: usize = 5;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Of each request, so that a stalled transfer doesn't hang the schedule
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Downloads a file and uploads generated data to a server of your choice
+pub struct HttpBackend {
+    pub client: Client,
+    /// Should point to a large file, bigger files give more accurate results
+    pub download_url: Url,
+    /// Should accept a POST request with a large body and ignore it
+    pub upload_url: Url,
+    /// Bytes
+    pub upload_size: usize,
+}
+
+pub fn client() -> anyhow::Result<Client> {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .context("Failed to create the HTTP client")
+}
+
+#[async_trait]
+impl MeasurementBackend for HttpBackend {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn measure(&self) -> anyhow::Result<Measurement> {
+        let timestamp = Utc::now();
+        for url in [&self.download_url, &self.upload_url] {
+            let host = url.host_str().context("URL has no host")?;
+            resolve(host, url.port_or_known_default().unwrap_or(80)).await?;
+        }
+        let ping = self.ping().await?;
+        let download = self.download().await?;
+        let upload = self.upload().await?;
+
+        Ok(Measurement {
+            timestamp,
+            backend: self.name().to_string(),
+            ping: Some(ping),
+            download: DirectionMeasurement {
+                bandwidth: download,
+                latency: None,
+            },
+            upload: DirectionMeasurement {
+                bandwidth: upload,
+                latency: None,
+            },
+            packet_loss: None,
+            isp: None,
+            interface: None,
+            server: Server {
+                host: self.download_url.host_str().unwrap_or_default().to_string(),
+                location: None,
+            },
+            result_url: None,
+        })
+    }
+}
+
+impl HttpBackend {
+    /// Round trip times of HEAD requests over an already open connection
+    async fn ping(&self) -> anyhow::Result<Ping> {
+        // Opens the connection, so that DNS and TLS handshakes are not measured
+        self.head().await?;
+        let mut samples = vec![];
+        for _ in 0..PING_COUNT {
+            samples.push(self.head().await?.as_secs_f32() * 1000.0);
+        }
+
+        Ok(ping_stats(&samples))
+    }
+
+    async fn head(&self) -> anyhow::Result<Duration> {
+        let start = Instant::now();
+        self.client
+            .head(self.download_url.clone())
+            .send()
+            .await
+            .context("HTTP ping request failed")?
+            .error_for_status()?;
+        Ok(start.elapsed())
+    }
+
+    /// Bytes per second
+    async fn download(&self) -> anyhow::Result<u64> {
+        let start = Instant::now();
+        let mut response = self
+            .client
+            .get(self.download_url.clone())
+            .send()
+            .await
+            .context("HTTP download failed")?
+            .error_for_status()?;
+        let mut bytes = 0;
+        while let Some(chunk) = response.chunk().await.context("HTTP download failed")? {
+            bytes += chunk.len();
+        }
+        Ok(bytes_per_second(bytes, start.elapsed()))
+    }
+
+    /// Bytes per second
+    async fn upload(&self) -> anyhow::Result<u64> {
+        let body = vec![0u8; self.upload_size];
+        let start = Instant::now();
+        self.client
+            .post(self.upload_url.clone())
+            .body(body)
+            .send()
+            .await
+            .context("HTTP upload failed")?
+            .error_for_status()?;
+        Ok(bytes_per_second(self.upload_size, start.elapsed()))
+    }
+}
+
+/// Mean, extremes and mean difference of consecutive samples, in milliseconds
+fn ping_stats(samples: &[f32]) -> Ping {
+    let jitter =
+        samples.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f32>() / (samples.len() - 1) as f32;
+    Ping {
+        latency: samples.iter().sum::<f32>() / samples.len() as f32,
+        low: samples.iter().copied().fold(f32::INFINITY, f32::min),
+        high: samples.iter().copied().fold(0.0, f32::max),
+        jitter: Some(jitter),
+    }
+}
+
+fn bytes_per_second(bytes: usize, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_statistics() {
+        let ping = ping_stats(&[10.0, 14.0, 12.0, 12.0]);
+        assert_eq!(ping.latency, 12.0);
+        assert_eq!(ping.low, 10.0);
+        assert_eq!(ping.high, 14.0);
+        assert_eq!(ping.jitter, Some(2.0));
+    }
+
+    #[test]
+    fn bandwidth_in_bytes_per_second() {
+        assert_eq!(
+            bytes_per_second(10_000_000, Duration::from_millis(800)),
+            12_500_000
+        );
+    }
+}
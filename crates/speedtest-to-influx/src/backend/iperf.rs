@@ -0,0 +1,238 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use super::{
+    check_status, resolve, BackendError, DirectionMeasurement, FailureKind, Interface, Measurement,
+    MeasurementBackend, Ping, Server,
+};
+
+pub const DEFAULT_PORT: u16 = 5201;
+
+#[derive(Deserialize, Debug)]
+struct Output {
+    start: Option<Start>,
+    end: Option<End>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Start {
+    #[serde(default)]
+    connected: Vec<Connection>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Connection {
+    local_host: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct End {
+    #[serde(default)]
+    streams: Vec<Stream>,
+    // `end` is an empty object when iperf3 fails
+    sum_received: Option<Sum>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stream {
+    sender: Option<StreamSender>,
+}
+
+/// Round trip times in microseconds, only reported on Linux
+#[derive(Deserialize, Debug)]
+struct StreamSender {
+    min_rtt: Option<u64>,
+    max_rtt: Option<u64>,
+    mean_rtt: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sum {
+    bits_per_second: f64,
+}
+
+/// Runs `iperf3` twice against a server, in reverse mode for the download
+pub struct IperfBackend {
+    pub server: String,
+    pub port: u16,
+    /// Seconds per direction
+    pub duration: u32,
+}
+
+#[async_trait]
+impl MeasurementBackend for IperfBackend {
+    fn name(&self) -> &'static str {
+        "iperf3"
+    }
+
+    async fn measure(&self) -> anyhow::Result<Measurement> {
+        let timestamp = Utc::now();
+        resolve(&self.server, self.port).await?;
+        let upload = self.run(false).await?;
+        let download = self.run(true).await?;
+
+        Ok(Measurement {
+            timestamp,
+            backend: self.name().to_string(),
+            ping: upload.ping(),
+            download: DirectionMeasurement {
+                bandwidth: download.bandwidth()?,
+                latency: None,
+            },
+            upload: DirectionMeasurement {
+                bandwidth: upload.bandwidth()?,
+                latency: None,
+            },
+            packet_loss: None,
+            isp: None,
+            interface: upload.local_host().map(|internal_ip| Interface {
+                internal_ip,
+                mac_addr: None,
+            }),
+            server: Server {
+                host: format!("{}:{}", self.server, self.port),
+                location: None,
+            },
+            result_url: None,
+        })
+    }
+}
+
+impl IperfBackend {
+    async fn run(&self, reverse: bool) -> anyhow::Result<Output> {
+        let mut command = Command::new("iperf3");
+        command
+            .arg("--client")
+            .arg(&self.server)
+            .arg("--port")
+            .arg(self.port.to_string())
+            .arg("--time")
+            .arg(self.duration.to_string())
+            .arg("--json");
+        if reverse {
+            command.arg("--reverse");
+        }
+        let command_output = command
+            .output()
+            .await
+            .context("Failed to execute iperf3 command")?;
+        let raw_output = String::from_utf8_lossy(&command_output.stdout);
+        // iperf3 reports most errors in the JSON output, but not all of them
+        let output = serde_json::from_str::<Output>(&raw_output);
+        if let Ok(Output {
+            error: Some(error), ..
+        }) = &output
+        {
+            return Err(BackendError {
+                kind: error_kind(error),
+                message: format!("iperf3 failed: {error}"),
+            }
+            .into());
+        }
+        check_status("iperf3", &command_output)?;
+        output.with_context(|| format!("Failed to parse JSON output: {raw_output}"))
+    }
+}
+
+/// iperf3 reports these when the server is unreachable or already running a test
+fn error_kind(error: &str) -> FailureKind {
+    if error.starts_with("unable to connect to server") || error.starts_with("the server is busy") {
+        FailureKind::Server
+    } else {
+        FailureKind::Other
+    }
+}
+
+impl Output {
+    /// Bytes per second received at the other end
+    fn bandwidth(&self) -> anyhow::Result<u64> {
+        let sum = self
+            .end
+            .as_ref()
+            .and_then(|end| end.sum_received.as_ref())
+            .context("iperf3 output has no results")?;
+        Ok((sum.bits_per_second / 8.0) as u64)
+    }
+
+    /// TCP round trip times of the sending side
+    fn ping(&self) -> Option<Ping> {
+        let sender = self.end.as_ref()?.streams.first()?.sender.as_ref()?;
+        let ms = |micros: u64| micros as f32 / 1000.0;
+        Some(Ping {
+            latency: ms(sender.mean_rtt?),
+            low: ms(sender.min_rtt?),
+            high: ms(sender.max_rtt?),
+            jitter: None,
+        })
+    }
+
+    fn local_host(&self) -> Option<String> {
+        let connection = self.start.as_ref()?.connected.first()?;
+        Some(connection.local_host.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed output of `iperf3 --client 192.168.1.2 --json`
+    const OUTPUT: &str = r#"{
+        "start": {
+            "connected": [{"socket": 5, "local_host": "192.168.1.10", "local_port": 50312, "remote_host": "192.168.1.2", "remote_port": 5201}],
+            "version": "iperf 3.16"
+        },
+        "intervals": [],
+        "end": {
+            "streams": [{
+                "sender": {"socket": 5, "bytes": 125000000, "bits_per_second": 100000000.0, "max_rtt": 2510, "min_rtt": 480, "mean_rtt": 1220, "sender": true},
+                "receiver": {"socket": 5, "bytes": 118000000, "bits_per_second": 94400000.0, "sender": true}
+            }],
+            "sum_sent": {"bytes": 125000000, "bits_per_second": 100000000.0, "retransmits": 3, "sender": true},
+            "sum_received": {"bytes": 118000000, "bits_per_second": 94400000.0, "sender": true}
+        }
+    }"#;
+
+    fn output(json: &str) -> Output {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn bandwidth_is_what_the_receiver_got() {
+        // 94.4 Mbit/s received, not the 100 Mbit/s sent
+        assert_eq!(output(OUTPUT).bandwidth().unwrap(), 11_800_000);
+    }
+
+    #[test]
+    fn ping_from_sender_round_trip_times() {
+        let ping = output(OUTPUT).ping().unwrap();
+        assert_eq!(ping.latency, 1.22);
+        assert_eq!(ping.low, 0.48);
+        assert_eq!(ping.high, 2.51);
+        assert_eq!(ping.jitter, None);
+        assert_eq!(output(OUTPUT).local_host().as_deref(), Some("192.168.1.10"));
+    }
+
+    #[test]
+    fn missing_results() {
+        let failed = output(r#"{"start": {}, "end": {}, "error": "unable to connect to server"}"#);
+        assert_eq!(failed.error.as_deref(), Some("unable to connect to server"));
+        assert_eq!(
+            error_kind("unable to connect to server"),
+            FailureKind::Server
+        );
+        assert_eq!(error_kind("parameter error"), FailureKind::Other);
+        assert!(failed.bandwidth().is_err());
+        assert!(failed.ping().is_none());
+
+        // macOS doesn't report round trip times
+        let output =
+            output(&OUTPUT.replace(r#""max_rtt": 2510, "min_rtt": 480, "mean_rtt": 1220, "#, ""));
+        assert!(output.ping().is_none());
+        assert!(output.bandwidth().is_ok());
+    }
+}
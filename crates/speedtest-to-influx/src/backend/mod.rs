@@ -0,0 +1,220 @@
+use std::{fmt, io, process::Output};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+pub mod http;
+pub mod iperf;
+pub mod ookla;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Official Speedtest CLI
+    Ookla,
+    /// iperf3 client against an iperf3 server, usually on the local network
+    Iperf3,
+    /// Built-in HTTP download and upload
+    Http,
+}
+
+#[async_trait]
+pub trait MeasurementBackend: Send + Sync {
+    /// Stored as a tag next to the results
+    fn name(&self) -> &'static str;
+
+    async fn measure(&self) -> anyhow::Result<Measurement>;
+}
+
+/// Result of a speedtest, independent of the backend that measured it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Measurement {
+    pub timestamp: DateTime<Utc>,
+    pub backend: String,
+    pub ping: Option<Ping>, // not measured by every backend
+    pub download: DirectionMeasurement,
+    pub upload: DirectionMeasurement,
+    pub packet_loss: Option<f32>, // not always available
+    pub isp: Option<String>,
+    pub interface: Option<Interface>,
+    pub server: Server,
+    pub result_url: Option<String>,
+}
+
+/// Milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ping {
+    pub latency: f32,
+    pub low: f32,
+    pub high: f32,
+    pub jitter: Option<f32>,
+}
+
+/// Milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Latency {
+    pub iqm: f32,
+    pub low: f32,
+    pub high: f32,
+    pub jitter: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectionMeasurement {
+    pub bandwidth: u64,           // bytes per second
+    pub latency: Option<Latency>, // latency under load
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interface {
+    pub internal_ip: String,
+    pub mac_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Server {
+    pub host: String,
+    pub location: Option<String>,
+}
+
+/// Anything that gets stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Measurement(Box<Measurement>),
+    Failure(Failure),
+}
+
+/// A speedtest that couldn't complete, stored so that outages show up in the data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub timestamp: DateTime<Utc>,
+    pub backend: String,
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    NoNetwork,
+    Dns,
+    Server,
+    Other,
+}
+
+impl Failure {
+    pub fn new(backend: &str, error: &anyhow::Error) -> Self {
+        Failure {
+            timestamp: Utc::now(),
+            backend: backend.to_string(),
+            kind: FailureKind::of(error),
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+/// Failure with a known cause, created by the backend where it happened
+#[derive(Debug)]
+pub struct BackendError {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl FailureKind {
+    /// Cause of the first error in the chain that tells it: errors of the backends, reqwest
+    /// timeouts and status codes, or OS errors of failed connections
+    fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<BackendError>() {
+                return e.kind;
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_timeout() || e.status().is_some_and(|s| s.is_server_error()) {
+                    return FailureKind::Server;
+                }
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                match e.kind() {
+                    io::ErrorKind::NetworkUnreachable
+                    | io::ErrorKind::NetworkDown
+                    | io::ErrorKind::HostUnreachable => return FailureKind::NoNetwork,
+                    io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::TimedOut => return FailureKind::Server,
+                    _ => {}
+                }
+            }
+        }
+        FailureKind::Other
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::NoNetwork => "no_network",
+            FailureKind::Dns => "dns",
+            FailureKind::Server => "server",
+            FailureKind::Other => "other",
+        }
+    }
+}
+
+/// Resolves the host before measuring, so that DNS failures are told apart from the rest
+async fn resolve(host: &str, port: u16) -> anyhow::Result<()> {
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(BackendError {
+            kind: FailureKind::Dns,
+            message: format!("Couldn't resolve {host}: {e}"),
+        }
+        .into()),
+    }
+}
+
+/// Fails with the stderr of the command if it exited unsuccessfully
+fn check_status(command: &str, output: &Output) -> anyhow::Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!("{command} exited with {}: {}", output.status, stderr.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_kinds() {
+        let dns = anyhow::Error::new(BackendError {
+            kind: FailureKind::Dns,
+            message: "Couldn't resolve speedtest.example.com".to_string(),
+        })
+        .context("Speedtest failed");
+        let io = |kind| anyhow::Error::new(io::Error::from(kind)).context("Failed to connect");
+        let cases = [
+            (dns, FailureKind::Dns),
+            (
+                io(io::ErrorKind::NetworkUnreachable),
+                FailureKind::NoNetwork,
+            ),
+            (io(io::ErrorKind::ConnectionRefused), FailureKind::Server),
+            (io(io::ErrorKind::PermissionDenied), FailureKind::Other),
+            // Only the type of the error counts, not the message
+            (anyhow::anyhow!("server lookup failed"), FailureKind::Other),
+        ];
+        for (error, kind) in cases {
+            assert_eq!(FailureKind::of(&error), kind, "{error:#}");
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use super::{check_status, resolve, Measurement, MeasurementBackend};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Output {
+    pub timestamp: DateTime<Utc>,
+    pub ping: Ping,
+    pub download: DirectionMeasurement,
+    pub upload: DirectionMeasurement,
+    pub packet_loss: Option<f32>, // not always available
+    pub isp: String,
+    pub interface: Interface,
+    pub server: Server,
+    pub result: ResultMetadata,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Ping {
+    pub jitter: f32,
+    pub latency: f32,
+    pub low: f32,
+    pub high: f32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Latency {
+    pub iqm: f32,
+    pub low: f32,
+    pub high: f32,
+    pub jitter: f32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DirectionMeasurement {
+    pub bandwidth: u32, // bytes
+    pub latency: Latency,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Interface {
+    pub internal_ip: String,
+    pub mac_addr: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResultMetadata {
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Server {
+    pub host: String,
+    pub location: String,
+}
+
+const CONFIG_HOST: &str = "www.speedtest.net";
+
+/// Runs the proprietary `speedtest` binary
+pub struct OoklaBackend;
+
+#[async_trait]
+impl MeasurementBackend for OoklaBackend {
+    fn name(&self) -> &'static str {
+        "ookla"
+    }
+
+    async fn measure(&self) -> anyhow::Result<Measurement> {
+        // The CLI downloads its configuration and server list from here first
+        resolve(CONFIG_HOST, 443).await?;
+        let mut command = Command::new("speedtest");
+        command.arg("--format").arg("json-pretty");
+        let command_output = command
+            .output()
+            .await
+            .context("Failed to execute speedtest command")?;
+        check_status("speedtest", &command_output)?;
+        let raw_output = String::from_utf8_lossy(&command_output.stdout);
+        let output: Output = serde_json::from_str(&raw_output)
+            .with_context(|| format!("Failed to parse JSON output: {raw_output}"))?;
+
+        Ok(output.into_measurement(self.name()))
+    }
+}
+
+impl Output {
+    fn into_measurement(self, backend: &str) -> Measurement {
+        Measurement {
+            timestamp: self.timestamp,
+            backend: backend.to_string(),
+            ping: Some(super::Ping {
+                latency: self.ping.latency,
+                low: self.ping.low,
+                high: self.ping.high,
+                jitter: Some(self.ping.jitter),
+            }),
+            download: self.download.into(),
+            upload: self.upload.into(),
+            packet_loss: self.packet_loss,
+            isp: Some(self.isp),
+            interface: Some(super::Interface {
+                internal_ip: self.interface.internal_ip,
+                mac_addr: Some(self.interface.mac_addr),
+            }),
+            server: super::Server {
+                host: self.server.host,
+                location: Some(self.server.location),
+            },
+            result_url: Some(self.result.url),
+        }
+    }
+}
+
+impl From<DirectionMeasurement> for super::DirectionMeasurement {
+    fn from(direction: DirectionMeasurement) -> Self {
+        super::DirectionMeasurement {
+            bandwidth: direction.bandwidth as u64,
+            latency: Some(super::Latency {
+                iqm: direction.latency.iqm,
+                low: direction.latency.low,
+                high: direction.latency.high,
+                jitter: direction.latency.jitter,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed output of `speedtest --format json-pretty`
+    const OUTPUT: &str = r#"{
+        "type": "result",
+        "timestamp": "2024-03-01T08:00:00Z",
+        "ping": {"jitter": 0.4, "latency": 7.5, "low": 6.9, "high": 8.1},
+        "download": {"bandwidth": 11800000, "bytes": 120000000, "elapsed": 10000, "latency": {"iqm": 12.5, "low": 8.0, "high": 40.0, "jitter": 2.5}},
+        "upload": {"bandwidth": 2500000, "bytes": 25000000, "elapsed": 10000, "latency": {"iqm": 30.0, "low": 9.0, "high": 80.0, "jitter": 5.0}},
+        "packetLoss": 0.5,
+        "isp": "Example ISP",
+        "interface": {"internalIp": "192.168.1.10", "name": "eth0", "macAddr": "00:11:22:33:44:55", "isVpn": false, "externalIp": "203.0.113.5"},
+        "server": {"id": 1234, "host": "speedtest.example.com", "port": 8080, "name": "Example", "location": "Budapest", "country": "Hungary", "ip": "198.51.100.7"},
+        "result": {"id": "abc", "url": "https://www.speedtest.net/result/c/abc", "persisted": true}
+    }"#;
+
+    #[test]
+    fn maps_output_to_measurement() {
+        let output: Output = serde_json::from_str(OUTPUT).unwrap();
+        let measurement = output.into_measurement("ookla");
+        assert_eq!(measurement.backend, "ookla");
+        assert_eq!(
+            measurement.timestamp.to_rfc3339(),
+            "2024-03-01T08:00:00+00:00"
+        );
+        let ping = measurement.ping.unwrap();
+        assert_eq!((ping.latency, ping.jitter), (7.5, Some(0.4)));
+        assert_eq!(measurement.download.bandwidth, 11_800_000);
+        assert_eq!(measurement.download.latency.unwrap().iqm, 12.5);
+        assert_eq!(measurement.upload.bandwidth, 2_500_000);
+        assert_eq!(measurement.packet_loss, Some(0.5));
+        assert_eq!(measurement.isp.as_deref(), Some("Example ISP"));
+        let interface = measurement.interface.unwrap();
+        assert_eq!(interface.internal_ip, "192.168.1.10");
+        assert_eq!(interface.mac_addr.as_deref(), Some("00:11:22:33:44:55"));
+        assert_eq!(measurement.server.host, "speedtest.example.com");
+        assert_eq!(measurement.server.location.as_deref(), Some("Budapest"));
+        assert_eq!(
+            measurement.result_url.as_deref(),
+            Some("https://www.speedtest.net/result/c/abc")
+        );
+    }
+
+    #[test]
+    fn packet_loss_is_optional() {
+        let output: Output =
+            serde_json::from_str(&OUTPUT.replace(r#""packetLoss": 0.5,"#, "")).unwrap();
+        assert_eq!(output.packet_loss, None);
+    }
+
+    #[test]
+    fn missing_or_invalid_fields_are_errors() {
+        let missing = OUTPUT.replace(r#""isp": "Example ISP","#, "");
+        assert!(serde_json::from_str::<Output>(&missing).is_err());
+        let invalid = OUTPUT.replace(r#""bandwidth": 2500000"#, r#""bandwidth": -1"#);
+        assert!(serde_json::from_str::<Output>(&invalid).is_err());
+    }
+}
@@ -0,0 +1,654 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::{parser::ValueSource, ArgMatches, Args};
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::{backend::Backend, sink::influx::InfluxApi, sink::SinkKind};
+
+/// Every setting can come from the config file, a `SPEEDTEST_*` environment variable or a flag,
+/// in increasing precedence. Defaults are applied after merging these.
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "SPEEDTEST_BACKEND",
+        help = "Defaults to ookla"
+    )]
+    pub backend: Option<Backend>,
+
+    #[arg(
+        long = "sink",
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        env = "SPEEDTEST_SINKS",
+        help = "Where to store the results, can be repeated, defaults to influx"
+    )]
+    pub sinks: Vec<SinkKind>,
+
+    #[command(flatten)]
+    pub influx: InfluxSettings,
+
+    #[command(flatten)]
+    pub iperf: IperfSettings,
+
+    #[command(flatten)]
+    pub http: HttpSettings,
+
+    #[command(flatten)]
+    pub prometheus: PrometheusSettings,
+
+    #[command(flatten)]
+    pub sqlite: SqliteSettings,
+
+    #[command(flatten)]
+    pub mqtt: MqttSettings,
+}
+
+/// The `[influx]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxSettings {
+    #[arg(long, global = true, env = "SPEEDTEST_URL")]
+    pub url: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "SPEEDTEST_INFLUX_API",
+        help = "Defaults to v1"
+    )]
+    #[serde(rename = "api")]
+    pub influx_api: Option<InfluxApi>,
+
+    #[arg(long, global = true, env = "SPEEDTEST_USERNAME", help = "v1 API only")]
+    pub username: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_PASSWORD",
+        hide_env_values = true,
+        help = "v1 API only, prefer --password-file"
+    )]
+    pub password: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_PASSWORD_FILE",
+        value_name = "FILE",
+        help = "v1 API only, file containing the password"
+    )]
+    pub password_file: Option<PathBuf>,
+
+    #[arg(long, global = true, env = "SPEEDTEST_DATABASE", help = "v1 API only")]
+    pub database: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_ORG",
+        help = "v2 API only, not needed for InfluxDB 3.x"
+    )]
+    pub org: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_BUCKET",
+        help = "v2 API only, the database name for InfluxDB 3.x"
+    )]
+    pub bucket: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_TOKEN",
+        hide_env_values = true,
+        help = "v2 API only, prefer --token-file"
+    )]
+    pub token: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_TOKEN_FILE",
+        value_name = "FILE",
+        help = "v2 API only, file containing the token"
+    )]
+    pub token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_LINE_PROTOCOL",
+        value_name = "FILE",
+        help = "Append line protocol to a file (or stdout with -) instead of writing to InfluxDB"
+    )]
+    pub line_protocol: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MEASUREMENT_PREFIX",
+        help = "Defaults to speedtest"
+    )]
+    pub measurement_prefix: Option<String>,
+
+    #[arg(
+        long = "tag",
+        global = true,
+        value_delimiter = ',',
+        env = "SPEEDTEST_TAGS",
+        value_name = "KEY=VALUE",
+        help = "Extra tag for every point, can be repeated"
+    )]
+    pub tags: Vec<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_SPOOL",
+        value_name = "FILE",
+        help = "Results are saved here while InfluxDB is unreachable, defaults to a file per destination in the user data folder"
+    )]
+    pub spool: Option<PathBuf>,
+
+    #[arg(long, global = true, env = "SPEEDTEST_SPOOL_MAX_ENTRIES")]
+    pub spool_max_entries: Option<usize>,
+}
+
+/// The `[iperf]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IperfSettings {
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_IPERF_SERVER",
+        value_name = "HOST",
+        help = "Server running `iperf3 --server`"
+    )]
+    #[serde(rename = "server")]
+    pub iperf_server: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_IPERF_PORT",
+        help = "Defaults to 5201"
+    )]
+    #[serde(rename = "port")]
+    pub iperf_port: Option<u16>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_IPERF_DURATION",
+        value_name = "SECONDS",
+        help = "Duration of each direction, defaults to 10"
+    )]
+    #[serde(rename = "duration")]
+    pub iperf_duration: Option<u32>,
+}
+
+/// The `[http]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_HTTP_DOWNLOAD_URL",
+        value_name = "URL",
+        help = "Large file to download in the HTTP test"
+    )]
+    #[serde(rename = "download_url")]
+    pub http_download_url: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_HTTP_UPLOAD_URL",
+        value_name = "URL",
+        help = "Endpoint accepting POST requests in the HTTP test"
+    )]
+    #[serde(rename = "upload_url")]
+    pub http_upload_url: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_HTTP_UPLOAD_SIZE",
+        value_name = "BYTES",
+        help = "Defaults to 10 MB"
+    )]
+    #[serde(rename = "upload_size")]
+    pub http_upload_size: Option<usize>,
+}
+
+/// The `[prometheus]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrometheusSettings {
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_PROMETHEUS_LISTEN",
+        value_name = "ADDRESS",
+        help = "Defaults to 127.0.0.1:9516, use 0.0.0.0:9516 to accept remote scrapes"
+    )]
+    #[serde(rename = "listen")]
+    pub prometheus_listen: Option<SocketAddr>,
+}
+
+/// The `[sqlite]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteSettings {
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_SQLITE",
+        value_name = "FILE",
+        help = "History database, defaults to the user data folder"
+    )]
+    #[serde(rename = "path")]
+    pub sqlite: Option<PathBuf>,
+}
+
+/// The `[mqtt]` section
+#[derive(Args, Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttSettings {
+    #[arg(long, global = true, env = "SPEEDTEST_MQTT_HOST", value_name = "HOST")]
+    #[serde(rename = "host")]
+    pub mqtt_host: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_PORT",
+        help = "Defaults to 1883"
+    )]
+    #[serde(rename = "port")]
+    pub mqtt_port: Option<u16>,
+
+    #[arg(long, global = true, env = "SPEEDTEST_MQTT_USERNAME")]
+    #[serde(rename = "username")]
+    pub mqtt_username: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_PASSWORD",
+        hide_env_values = true,
+        help = "Prefer --mqtt-password-file"
+    )]
+    #[serde(rename = "password")]
+    pub mqtt_password: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_PASSWORD_FILE",
+        value_name = "FILE",
+        help = "File containing the MQTT password"
+    )]
+    #[serde(rename = "password_file")]
+    pub mqtt_password_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_TOPIC",
+        help = "Defaults to speedtest"
+    )]
+    #[serde(rename = "topic")]
+    pub mqtt_topic: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_DISCOVERY_PREFIX",
+        help = "Topic prefix of Home Assistant MQTT discovery, defaults to homeassistant"
+    )]
+    #[serde(rename = "discovery_prefix")]
+    pub mqtt_discovery_prefix: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_MQTT_DISCOVERY",
+        value_name = "BOOL",
+        help = "Announce the sensors to Home Assistant, defaults to true"
+    )]
+    #[serde(rename = "discovery")]
+    pub mqtt_discovery: Option<bool>,
+}
+
+impl Settings {
+    /// Secret files set by flags win over secrets in the environment, like other flags do
+    pub fn prefer_secret_file_flags(&mut self, matches: &ArgMatches) {
+        prefer_file_flag(
+            &mut self.influx.password,
+            matches,
+            "password",
+            "password_file",
+        );
+        prefer_file_flag(&mut self.influx.token, matches, "token", "token_file");
+        prefer_file_flag(
+            &mut self.mqtt.mqtt_password,
+            matches,
+            "mqtt_password",
+            "mqtt_password_file",
+        );
+    }
+
+    /// Values set in `other` win
+    pub fn merge(self, other: Settings) -> Settings {
+        let (password, password_file) = merge_secret(
+            (self.influx.password, self.influx.password_file),
+            (other.influx.password, other.influx.password_file),
+        );
+        let (token, token_file) = merge_secret(
+            (self.influx.token, self.influx.token_file),
+            (other.influx.token, other.influx.token_file),
+        );
+        let (mqtt_password, mqtt_password_file) = merge_secret(
+            (self.mqtt.mqtt_password, self.mqtt.mqtt_password_file),
+            (other.mqtt.mqtt_password, other.mqtt.mqtt_password_file),
+        );
+        Settings {
+            backend: other.backend.or(self.backend),
+            sinks: merge_list(self.sinks, other.sinks),
+            influx: InfluxSettings {
+                url: other.influx.url.or(self.influx.url),
+                influx_api: other.influx.influx_api.or(self.influx.influx_api),
+                username: other.influx.username.or(self.influx.username),
+                password,
+                password_file,
+                database: other.influx.database.or(self.influx.database),
+                org: other.influx.org.or(self.influx.org),
+                bucket: other.influx.bucket.or(self.influx.bucket),
+                token,
+                token_file,
+                line_protocol: other.influx.line_protocol.or(self.influx.line_protocol),
+                measurement_prefix: other
+                    .influx
+                    .measurement_prefix
+                    .or(self.influx.measurement_prefix),
+                tags: merge_list(self.influx.tags, other.influx.tags),
+                spool: other.influx.spool.or(self.influx.spool),
+                spool_max_entries: other
+                    .influx
+                    .spool_max_entries
+                    .or(self.influx.spool_max_entries),
+            },
+            iperf: IperfSettings {
+                iperf_server: other.iperf.iperf_server.or(self.iperf.iperf_server),
+                iperf_port: other.iperf.iperf_port.or(self.iperf.iperf_port),
+                iperf_duration: other.iperf.iperf_duration.or(self.iperf.iperf_duration),
+            },
+            http: HttpSettings {
+                http_download_url: other.http.http_download_url.or(self.http.http_download_url),
+                http_upload_url: other.http.http_upload_url.or(self.http.http_upload_url),
+                http_upload_size: other.http.http_upload_size.or(self.http.http_upload_size),
+            },
+            prometheus: PrometheusSettings {
+                prometheus_listen: other
+                    .prometheus
+                    .prometheus_listen
+                    .or(self.prometheus.prometheus_listen),
+            },
+            sqlite: SqliteSettings {
+                sqlite: other.sqlite.sqlite.or(self.sqlite.sqlite),
+            },
+            mqtt: MqttSettings {
+                mqtt_host: other.mqtt.mqtt_host.or(self.mqtt.mqtt_host),
+                mqtt_port: other.mqtt.mqtt_port.or(self.mqtt.mqtt_port),
+                mqtt_username: other.mqtt.mqtt_username.or(self.mqtt.mqtt_username),
+                mqtt_password,
+                mqtt_password_file,
+                mqtt_topic: other.mqtt.mqtt_topic.or(self.mqtt.mqtt_topic),
+                mqtt_discovery_prefix: other
+                    .mqtt
+                    .mqtt_discovery_prefix
+                    .or(self.mqtt.mqtt_discovery_prefix),
+                mqtt_discovery: other.mqtt.mqtt_discovery.or(self.mqtt.mqtt_discovery),
+            },
+        }
+    }
+}
+
+type Secret = (Option<String>, Option<PathBuf>);
+
+/// Drops a secret from the environment if its file is set by a flag. Environment variables are
+/// parsed like flags, so the secret would win over the file otherwise.
+fn prefer_file_flag(value: &mut Option<String>, matches: &ArgMatches, id: &str, file_id: &str) {
+    let from_flag = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if from_flag(file_id) && !from_flag(id) {
+        *value = None;
+    }
+}
+
+/// A secret and its file are replaced together, so that a file set by a flag wins over a value in
+/// the config file
+fn merge_secret(base: Secret, other: Secret) -> Secret {
+    if other.0.is_some() || other.1.is_some() {
+        other
+    } else {
+        base
+    }
+}
+
+/// Lists are replaced, not appended to
+fn merge_list<T>(base: Vec<T>, other: Vec<T>) -> Vec<T> {
+    if other.is_empty() {
+        base
+    } else {
+        other
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("com.oliverfalvai.homecooked", "", "speedtest-to-influx")
+        .map(|project_dirs| project_dirs.config_dir().join("config.toml"))
+}
+
+/// An explicit path must exist, the default one is optional
+pub fn load_settings(path: Option<&Path>) -> anyhow::Result<Settings> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Settings::default()),
+        },
+    };
+    let content = fs::read_to_string(&path)
+        .context(format!("Failed to load config file {}", path.display()))?;
+    toml::from_str(&content).context(format!("Invalid config file {}", path.display()))
+}
+
+/// The value itself wins over the file, trailing newlines of the file are ignored
+pub fn secret(value: &Option<String>, file: &Option<PathBuf>) -> anyhow::Result<Option<String>> {
+    if value.is_some() {
+        return Ok(value.clone());
+    }
+    match file {
+        Some(file) => {
+            let content = fs::read_to_string(file)
+                .context(format!("Can't read secret file {}", file.display()))?;
+            Ok(Some(content.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Explains every way to set a missing value. `field` is the flag name with underscores.
+pub fn required<T: Clone>(
+    value: &Option<T>,
+    field: &str,
+    section: &str,
+    key: &str,
+    reason: &str,
+) -> anyhow::Result<T> {
+    match value {
+        Some(value) => Ok(value.clone()),
+        None => bail!(
+            "Missing {key} {reason}: set --{}, SPEEDTEST_{} or `{key}` in the [{section}] section of the config file",
+            field.replace('_', "-"),
+            field.to_uppercase()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches, Parser};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        settings: Settings,
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let file: Settings = toml::from_str(
+            r#"
+            backend = "iperf3"
+            sinks = ["influx", "mqtt"]
+
+            [influx]
+            url = "http://influx:8086"
+            api = "v2"
+            bucket = "speedtest"
+            tags = ["site=home"]
+
+            [iperf]
+            server = "nas.local"
+            port = 5202
+
+            [mqtt]
+            host = "broker"
+            discovery = false
+            "#,
+        )
+        .unwrap();
+        let cli = TestCli::parse_from([
+            "speedtest",
+            "--url",
+            "http://other:8086",
+            "--sink",
+            "sqlite",
+            "--iperf-port",
+            "5203",
+        ]);
+
+        let settings = file.merge(cli.settings);
+        assert_eq!(settings.backend, Some(Backend::Iperf3));
+        assert_eq!(settings.sinks, vec![SinkKind::Sqlite]);
+        assert_eq!(settings.influx.url.as_deref(), Some("http://other:8086"));
+        assert_eq!(settings.influx.influx_api, Some(InfluxApi::V2));
+        assert_eq!(settings.influx.tags, vec!["site=home"]);
+        assert_eq!(settings.iperf.iperf_server.as_deref(), Some("nas.local"));
+        assert_eq!(settings.iperf.iperf_port, Some(5203));
+        assert_eq!(settings.mqtt.mqtt_discovery, Some(false));
+    }
+
+    #[test]
+    fn secret_file_flag_overrides_the_config_password() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "from-file\n").unwrap();
+        let file: Settings = toml::from_str(
+            r#"
+            [influx]
+            password = "from-config"
+            token = "config-token"
+            "#,
+        )
+        .unwrap();
+        let cli = TestCli::parse_from(["speedtest", "--password-file", path.to_str().unwrap()]);
+
+        let settings = file.merge(cli.settings);
+        assert_eq!(settings.influx.password, None);
+        assert_eq!(
+            secret(&settings.influx.password, &settings.influx.password_file)
+                .unwrap()
+                .as_deref(),
+            Some("from-file")
+        );
+        assert_eq!(settings.influx.token.as_deref(), Some("config-token"));
+    }
+
+    #[test]
+    fn secret_file_flag_overrides_the_environment() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "from-file\n").unwrap();
+        // A variable of its own, so that the other tests don't see it
+        std::env::set_var("SPEEDTEST_TEST_PASSWORD", "from-env");
+        let command =
+            TestCli::command().mut_arg("password", |arg| arg.env("SPEEDTEST_TEST_PASSWORD"));
+
+        let matches = command.clone().get_matches_from([
+            "speedtest",
+            "--password-file",
+            path.to_str().unwrap(),
+        ]);
+        let mut settings = TestCli::from_arg_matches(&matches).unwrap().settings;
+        settings.prefer_secret_file_flags(&matches);
+        assert_eq!(settings.influx.password, None);
+        assert_eq!(
+            secret(&settings.influx.password, &settings.influx.password_file)
+                .unwrap()
+                .as_deref(),
+            Some("from-file")
+        );
+
+        let matches = command.get_matches_from(["speedtest"]);
+        let mut settings = TestCli::from_arg_matches(&matches).unwrap().settings;
+        settings.prefer_secret_file_flags(&matches);
+        assert_eq!(settings.influx.password.as_deref(), Some("from-env"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let result = toml::from_str::<Settings>("[influx]\npasword = \"secret\"\n");
+        assert!(result.unwrap_err().to_string().contains("pasword"));
+    }
+
+    #[test]
+    fn secret_file_is_trimmed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "hunter2\n").unwrap();
+
+        assert_eq!(
+            secret(&None, &Some(path.clone())).unwrap().as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(
+            secret(&Some("explicit".to_string()), &Some(path.clone()))
+                .unwrap()
+                .as_deref(),
+            Some("explicit")
+        );
+    }
+}
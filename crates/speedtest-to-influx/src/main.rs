@@ -1,12 +1,31 @@
-use clap::{Parser, Subcommand};
+use std::{fmt::Display, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use backend::{
+    http::HttpBackend, iperf::IperfBackend, ookla::OoklaBackend, Backend, Failure, Measurement,
+    MeasurementBackend, Record,
+};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::{InfluxSettings, Settings};
 use duration_string::DurationString;
-use influx::ConnectionParams;
 use owo_colors::OwoColorize;
-use speedtest::Output;
+use reqwest::Url;
+use sink::{
+    influx::{self, ConnectionParams, Destination, InfluxApi, InfluxSink},
+    mqtt::{self, MqttConfig, MqttSink},
+    prometheus::{self, PrometheusSink},
+    sqlite::SqliteSink,
+    Sink, SinkKind,
+};
+use spool::Spool;
 use tokio::{task, time};
+use tracing::error;
+use tracing_subscriber::{filter::LevelFilter, EnvFilter};
 
-mod influx;
-mod speedtest;
+mod backend;
+mod config;
+mod sink;
+mod spool;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,22 +33,22 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(long, global = true, required = false)]
-    url: String,
-
-    #[arg(long, global = true, required = false)]
-    username: String,
+    #[arg(
+        long,
+        global = true,
+        env = "SPEEDTEST_CONFIG",
+        value_name = "FILE",
+        help = "TOML config file, defaults to config.toml in the user config folder"
+    )]
+    config: Option<PathBuf>,
 
-    #[arg(long, global = true, required = false)]
-    password: String,
-
-    #[arg(long, global = true, required = false)]
-    database: String,
+    #[command(flatten)]
+    settings: Settings,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    #[command(about = "Run speedtest and report results to DB")]
+    #[command(about = "Run speedtest and store the results")]
     Run,
 
     #[command(about = "Run speedtest on a schedule")]
@@ -37,60 +56,318 @@ enum Commands {
         #[arg(short, long)]
         interval: String,
     },
+
+    #[command(about = "Store the results saved while InfluxDB was unreachable")]
+    Flush,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.settings.prefer_secret_file_flags(&matches);
+    init_logging();
 
-    let params = ConnectionParams {
-        url: cli.url,
-        username: cli.username,
-        password: cli.password,
-        database: cli.database,
-    };
+    let settings = config::load_settings(cli.config.as_deref())?.merge(cli.settings);
+    let backend = create_backend(&settings)?;
+    let console = Console::new(&settings);
 
     match cli.command {
-        Commands::Run => run(params).await?,
-        Commands::Schedule { interval } => run_scheduled(params, interval).await?,
+        Commands::Run => {
+            let sinks = create_sinks(&settings).await?;
+            let result = run(backend.as_ref(), &sinks, console).await;
+            for sink in &sinks {
+                sink.shutdown().await;
+            }
+            result?;
+        }
+        Commands::Schedule { ref interval } => {
+            let sinks = create_sinks(&settings).await?;
+            run_scheduled(backend, sinks, interval.to_owned(), console).await?
+        }
+        Commands::Flush => {
+            let params = connection_params(&settings)?;
+            let count =
+                spool::flush(&params, &create_spool(&settings, &params.destination)).await?;
+            console.println(format_args!("Stored {count} spooled results"));
+        }
     }
 
     Ok(())
 }
 
-async fn run(connection_params: ConnectionParams) -> anyhow::Result<()> {
-    println!("{}", "Running speedtest...".cyan());
-    let output = speedtest::run_measurement()?;
+async fn create_sinks(settings: &Settings) -> anyhow::Result<Vec<Box<dyn Sink>>> {
+    let kinds = if settings.sinks.is_empty() {
+        vec![SinkKind::Influx]
+    } else {
+        settings.sinks.clone()
+    };
+
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    for kind in kinds {
+        let sink: Box<dyn Sink> = match kind {
+            SinkKind::Influx => {
+                let params = connection_params(settings)?;
+                let spool = create_spool(settings, &params.destination);
+                Box::new(InfluxSink { params, spool })
+            }
+            SinkKind::Prometheus => {
+                let address = match settings.prometheus.prometheus_listen {
+                    Some(address) => address,
+                    None => prometheus::DEFAULT_LISTEN.parse()?,
+                };
+                Box::new(PrometheusSink::serve(address).await?)
+            }
+            SinkKind::Sqlite => {
+                let path = settings
+                    .sqlite
+                    .sqlite
+                    .clone()
+                    .unwrap_or_else(SqliteSink::default_path);
+                Box::new(SqliteSink::open(&path)?)
+            }
+            SinkKind::Mqtt => {
+                let mqtt_settings = &settings.mqtt;
+                let mqtt_config = MqttConfig {
+                    host: config::required(
+                        &mqtt_settings.mqtt_host,
+                        "mqtt_host",
+                        "mqtt",
+                        "host",
+                        "for the MQTT sink",
+                    )?,
+                    port: mqtt_settings.mqtt_port.unwrap_or(mqtt::DEFAULT_PORT),
+                    username: mqtt_settings.mqtt_username.clone(),
+                    password: config::secret(
+                        &mqtt_settings.mqtt_password,
+                        &mqtt_settings.mqtt_password_file,
+                    )?,
+                    topic: mqtt_settings
+                        .mqtt_topic
+                        .clone()
+                        .unwrap_or_else(|| mqtt::DEFAULT_TOPIC.to_string()),
+                    discovery_prefix: mqtt_settings.mqtt_discovery.unwrap_or(true).then(|| {
+                        mqtt_settings
+                            .mqtt_discovery_prefix
+                            .clone()
+                            .unwrap_or_else(|| mqtt::DEFAULT_DISCOVERY_PREFIX.to_string())
+                    }),
+                };
+                Box::new(MqttSink::connect(&mqtt_config).await?)
+            }
+        };
+        sinks.push(sink);
+    }
+    Ok(sinks)
+}
+
+fn connection_params(settings: &Settings) -> anyhow::Result<ConnectionParams> {
+    let tags = settings
+        .influx
+        .tags
+        .iter()
+        .map(|tag| influx::parse_tag(tag).map_err(|e| anyhow!("Invalid tag: {e}")))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(ConnectionParams {
+        destination: destination(&settings.influx)?,
+        measurement_prefix: settings
+            .influx
+            .measurement_prefix
+            .clone()
+            .unwrap_or_else(|| influx::DEFAULT_MEASUREMENT_PREFIX.to_string()),
+        tags,
+    })
+}
+
+fn create_spool(settings: &Settings, destination: &Destination) -> Spool {
+    Spool {
+        path: settings
+            .influx
+            .spool
+            .clone()
+            .unwrap_or_else(|| Spool::default_path(destination)),
+        max_entries: settings
+            .influx
+            .spool_max_entries
+            .unwrap_or(spool::DEFAULT_MAX_ENTRIES),
+    }
+}
+
+fn destination(influx: &InfluxSettings) -> anyhow::Result<Destination> {
+    if let Some(path) = &influx.line_protocol {
+        return Ok(Destination::LineProtocol {
+            path: (path != "-").then(|| PathBuf::from(path)),
+        });
+    }
+
+    let url = config::required(&influx.url, "url", "influx", "url", "for InfluxDB")?;
+    let destination = match influx.influx_api.unwrap_or(InfluxApi::V1) {
+        InfluxApi::V1 => Destination::V1 {
+            url,
+            username: influx.username.clone().unwrap_or_default(),
+            password: config::secret(&influx.password, &influx.password_file)?.unwrap_or_default(),
+            database: config::required(
+                &influx.database,
+                "database",
+                "influx",
+                "database",
+                "for the v1 API",
+            )?,
+        },
+        InfluxApi::V2 => {
+            let token = config::secret(&influx.token, &influx.token_file)?;
+            Destination::V2 {
+                url,
+                org: influx.org.clone(),
+                bucket: config::required(
+                    &influx.bucket,
+                    "bucket",
+                    "influx",
+                    "bucket",
+                    "for the v2 API",
+                )?,
+                token: config::required(&token, "token", "influx", "token", "for the v2 API")?,
+            }
+        }
+    };
+    Ok(destination)
+}
+
+fn create_backend(settings: &Settings) -> anyhow::Result<Arc<dyn MeasurementBackend>> {
+    let backend: Arc<dyn MeasurementBackend> = match settings.backend.unwrap_or(Backend::Ookla) {
+        Backend::Ookla => Arc::new(OoklaBackend),
+        Backend::Iperf3 => {
+            let iperf = &settings.iperf;
+            Arc::new(IperfBackend {
+                server: config::required(
+                    &iperf.iperf_server,
+                    "iperf_server",
+                    "iperf",
+                    "server",
+                    "for the iperf3 backend",
+                )?,
+                port: iperf.iperf_port.unwrap_or(backend::iperf::DEFAULT_PORT),
+                duration: iperf.iperf_duration.unwrap_or(10),
+            })
+        }
+        Backend::Http => {
+            let http = &settings.http;
+            let download_url = config::required(
+                &http.http_download_url,
+                "http_download_url",
+                "http",
+                "download_url",
+                "for the HTTP backend",
+            )?;
+            let upload_url = config::required(
+                &http.http_upload_url,
+                "http_upload_url",
+                "http",
+                "upload_url",
+                "for the HTTP backend",
+            )?;
+            Arc::new(HttpBackend {
+                client: backend::http::client()?,
+                download_url: Url::parse(&download_url)
+                    .context(format!("Invalid download URL {download_url}"))?,
+                upload_url: Url::parse(&upload_url)
+                    .context(format!("Invalid upload URL {upload_url}"))?,
+                upload_size: http
+                    .http_upload_size
+                    .unwrap_or(backend::http::DEFAULT_UPLOAD_SIZE),
+            })
+        }
+    };
+    Ok(backend)
+}
+
+/// Human-readable output, on stderr when stdout carries line protocol
+#[derive(Clone, Copy)]
+enum Console {
+    Stdout,
+    Stderr,
+}
+
+impl Console {
+    fn new(settings: &Settings) -> Self {
+        match settings.influx.line_protocol.as_deref() {
+            Some("-") => Console::Stderr,
+            _ => Console::Stdout,
+        }
+    }
+
+    fn println(self, text: impl Display) {
+        match self {
+            Console::Stdout => println!("{text}"),
+            Console::Stderr => eprintln!("{text}"),
+        }
+    }
+}
+
+async fn run(
+    backend: &dyn MeasurementBackend,
+    sinks: &[Box<dyn Sink>],
+    console: Console,
+) -> anyhow::Result<()> {
+    console.println(format!("Running {} speedtest...", backend.name()).cyan());
+    let measurement = match backend.measure().await {
+        Ok(measurement) => measurement,
+        Err(e) => {
+            let failure = Failure::new(backend.name(), &e);
+            error!(
+                backend = %failure.backend,
+                error_type = failure.kind.as_str(),
+                error = %failure.message,
+                "Speedtest failed"
+            );
+            store(sinks, &Record::Failure(failure), console).await;
+            return Err(e);
+        }
+    };
 
-    print_output(&output);
+    print_measurement(&measurement, console);
 
-    influx::store(connection_params, output).await?;
+    store(sinks, &Record::Measurement(Box::new(measurement)), console).await;
 
     Ok(())
 }
 
+/// A failing sink doesn't stop the others
+async fn store(sinks: &[Box<dyn Sink>], record: &Record, console: Console) {
+    for sink in sinks {
+        if let Err(e) = sink.store(record).await {
+            error!(sink = sink.name(), error = %format!("{e:#}"), "Failed to store the results");
+            console.println(format_args!("{}: {:#}", "Error".red(), e));
+        }
+    }
+}
+
 async fn run_scheduled(
-    connection_params: ConnectionParams,
+    backend: Arc<dyn MeasurementBackend>,
+    sinks: Vec<Box<dyn Sink>>,
     duration_string: String,
+    console: Console,
 ) -> anyhow::Result<()> {
-    println!(
+    let period: Duration = DurationString::from_string(duration_string.clone())
+        .map_err(|e| anyhow::anyhow!("Invalid interval {duration_string}: {e}"))?
+        .into();
+    console.println(format_args!(
         "Scheduling speedtest to run every {}",
         &duration_string.cyan()
-    );
-    println!();
+    ));
+    console.println("");
 
     let forever = task::spawn(async move {
-        let mut interval =
-            time::interval(DurationString::from_string(duration_string).unwrap().into());
+        let mut interval = time::interval(period);
 
         loop {
             interval.tick().await;
-            match run(connection_params.clone()).await {
-                Ok(_) => println!(),
-                Err(e) => println!("{}: {}", "Error".red(), e),
+            match run(backend.as_ref(), &sinks, console).await {
+                Ok(_) => console.println(""),
+                Err(e) => console.println(format_args!("{}: {}", "Error".red(), e)),
             }
 
-            println!();
+            console.println("");
         }
     });
 
@@ -99,52 +376,78 @@ async fn run_scheduled(
     Ok(())
 }
 
-fn print_output(output: &Output) {
-    println!();
-    println!(
+fn init_logging() {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+fn print_measurement(measurement: &Measurement, console: Console) {
+    console.println("");
+    console.println(format_args!(
         "Bandwidth:\t\t🔻{} 🔺{}",
-        format_bandwidth(output.download.bandwidth).cyan(),
-        format_bandwidth(output.upload.bandwidth).cyan(),
-    );
+        format_bandwidth(measurement.download.bandwidth).cyan(),
+        format_bandwidth(measurement.upload.bandwidth).cyan(),
+    ));
 
     // let packet_loss_style = if output.packet_loss > 0.0 {
     //     Style::new().yellow()
     // } else {
     //     Style::new().green()
     // };
-    let packet_loss_value = match output.packet_loss {
+    let packet_loss_value = match measurement.packet_loss {
         Some(val) => format!("{val}%"),
         None => String::from("not available"),
     };
-    println!("Packet loss:\t\t{}", packet_loss_value.yellow());
-    println!(
-        "Idle latency:\t\t{} ({}/{}) {}",
-        format!("{:.0}ms", output.ping.latency).yellow(),
-        format!("{:.0}ms", output.ping.low).green(),
-        format!("{:.0}ms", output.ping.high).red(),
-        format!("{:.0}ms jitter", output.ping.jitter).yellow()
-    );
-    println!(
-        "Download latency:\t{} ({}/{}) {}",
-        format!("{:.0}ms", output.download.latency.iqm).yellow(),
-        format!("{:.0}ms", output.download.latency.low).green(),
-        format!("{:.0}ms", output.download.latency.high).red(),
-        format!("{:.0}ms jitter", output.download.latency.jitter).yellow(),
-    );
-    println!(
-        "Upload latency:\t\t{} ({}/{}) {}",
-        format!("{:.0}ms", output.upload.latency.iqm).yellow(),
-        format!("{:.0}ms", output.upload.latency.low).green(),
-        format!("{:.0}ms", output.upload.latency.high).red(),
-        format!("{:.0}ms jitter", output.upload.latency.jitter).yellow(),
-    );
-    println!("---");
-    println!("Server:\t\t\t{}", output.server.host.dimmed());
-    println!("Location:\t\t{}", output.server.location.dimmed());
-    println!("Result URL:\t\t{}", output.result.url.dimmed().underline())
-}
-
-fn format_bandwidth(bytes: u32) -> String {
+    console.println(format_args!(
+        "Packet loss:\t\t{}",
+        packet_loss_value.yellow()
+    ));
+    if let Some(ping) = &measurement.ping {
+        let jitter = match ping.jitter {
+            Some(jitter) => format!("{jitter:.0}ms jitter"),
+            None => String::new(),
+        };
+        console.println(format_args!(
+            "Idle latency:\t\t{} ({}/{}) {}",
+            format!("{:.0}ms", ping.latency).yellow(),
+            format!("{:.0}ms", ping.low).green(),
+            format!("{:.0}ms", ping.high).red(),
+            jitter.yellow()
+        ));
+    }
+    for (label, direction) in [
+        ("Download latency:\t", &measurement.download),
+        ("Upload latency:\t\t", &measurement.upload),
+    ] {
+        if let Some(latency) = &direction.latency {
+            console.println(format_args!(
+                "{label}{} ({}/{}) {}",
+                format!("{:.0}ms", latency.iqm).yellow(),
+                format!("{:.0}ms", latency.low).green(),
+                format!("{:.0}ms", latency.high).red(),
+                format!("{:.0}ms jitter", latency.jitter).yellow(),
+            ));
+        }
+    }
+    console.println("---");
+    console.println(format_args!(
+        "Server:\t\t\t{}",
+        measurement.server.host.dimmed()
+    ));
+    if let Some(location) = &measurement.server.location {
+        console.println(format_args!("Location:\t\t{}", location.dimmed()));
+    }
+    if let Some(url) = &measurement.result_url {
+        console.println(format_args!("Result URL:\t\t{}", url.dimmed().underline()));
+    }
+}
+
+fn format_bandwidth(bytes: u64) -> String {
     let mbits = bytes as f32 * 8.0 / 1000.0 / 1000.0;
     format!("{mbits:.0} Mbit/s")
 }
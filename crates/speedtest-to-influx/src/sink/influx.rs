@@ -0,0 +1,421 @@
+use std::{fmt, fs::OpenOptions, io::Write, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use influxdb::{InfluxDbWriteable, Query, WriteQuery};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::Deserialize;
+use tokio::time;
+use tracing::{error, info, warn};
+
+use super::Sink;
+use crate::{
+    backend::{Failure, Measurement, Record},
+    spool::{self, Spool},
+};
+
+const MAX_ATTEMPTS: u32 = 3;
+/// Doubled after each failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+pub const DEFAULT_MEASUREMENT_PREFIX: &str = "speedtest";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfluxApi {
+    /// Username, password and database
+    V1,
+    /// Org, bucket and token, also works with InfluxDB 3.x
+    V2,
+}
+
+#[derive(Clone)]
+pub struct ConnectionParams {
+    pub destination: Destination,
+    /// Measurements are named `<prefix>_ping`, `<prefix>_download` and so on
+    pub measurement_prefix: String,
+    /// Added to every point
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub enum Destination {
+    V1 {
+        url: String,
+        username: String,
+        password: String,
+        database: String,
+    },
+    V2 {
+        url: String,
+        /// Not used by InfluxDB 3.x
+        org: Option<String>,
+        bucket: String,
+        token: String,
+    },
+    /// Appends line protocol to a file, for Telegraf's file input for example. Prints to stdout
+    /// without a path.
+    LineProtocol { path: Option<PathBuf> },
+}
+
+/// Points that InfluxDB refused, so storing them again would fail the same way
+#[derive(Debug)]
+pub struct RejectedWrite {
+    pub message: String,
+}
+
+impl fmt::Display for RejectedWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InfluxDB rejected the write: {}", self.message)
+    }
+}
+
+impl std::error::Error for RejectedWrite {}
+
+impl RejectedWrite {
+    pub fn is_cause_of(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| cause.is::<RejectedWrite>())
+    }
+}
+
+impl Destination {
+    /// Part of the default spool file name, so that results spooled for one destination are not
+    /// replayed to another one
+    pub fn spool_key(&self) -> String {
+        let key = match self {
+            Destination::V1 { url, database, .. } => format!("{url}-{database}"),
+            Destination::V2 {
+                url, org, bucket, ..
+            } => format!("{url}-{}-{bucket}", org.as_deref().unwrap_or_default()),
+            Destination::LineProtocol { path: Some(path) } => format!("file-{}", path.display()),
+            Destination::LineProtocol { path: None } => "stdout".to_string(),
+        };
+        key.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+pub struct InfluxSink {
+    pub params: ConnectionParams,
+    pub spool: Spool,
+}
+
+#[async_trait]
+impl Sink for InfluxSink {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    async fn store(&self, record: &Record) -> anyhow::Result<()> {
+        spool::store_or_spool(&self.params, &self.spool, record.clone()).await
+    }
+}
+
+#[derive(InfluxDbWriteable)]
+struct PingMeasurement {
+    time: DateTime<Utc>,
+    ping: f32,
+    ping_low: f32,
+    ping_high: f32,
+    jitter: Option<f32>,
+}
+
+#[derive(InfluxDbWriteable)]
+struct BandwidthMeasurement {
+    time: DateTime<Utc>,
+    bandwidth: i64, // bytes
+    latency_jitter: Option<f32>,
+    latency_low: Option<f32>,
+    latency_high: Option<f32>,
+    latency_iqm: Option<f32>,
+    packet_loss: Option<f32>, // not always available
+}
+
+#[derive(InfluxDbWriteable)]
+struct FailureMeasurement {
+    time: DateTime<Utc>,
+    message: String,
+}
+
+pub async fn store(params: ConnectionParams, records: &[Record]) -> anyhow::Result<()> {
+    let queries = records
+        .iter()
+        .flat_map(|record| match record {
+            Record::Measurement(measurement) => measurement_queries(&params, measurement),
+            Record::Failure(failure) => vec![failure_query(&params, failure)],
+        })
+        .collect();
+
+    write(params.destination, queries).await
+}
+
+fn measurement_queries(params: &ConnectionParams, measurement: &Measurement) -> Vec<WriteQuery> {
+    let mut queries = vec![];
+    if let Some(ping) = &measurement.ping {
+        let query = PingMeasurement {
+            time: measurement.timestamp,
+            ping: ping.latency,
+            ping_low: ping.low,
+            ping_high: ping.high,
+            jitter: ping.jitter,
+        }
+        .into_query(format!("{}_ping", params.measurement_prefix));
+        queries.push(add_tags(query, params, measurement));
+    }
+    for (name, direction) in [
+        ("download", &measurement.download),
+        ("upload", &measurement.upload),
+    ] {
+        let latency = direction.latency.as_ref();
+        let query = BandwidthMeasurement {
+            time: measurement.timestamp,
+            bandwidth: direction.bandwidth as i64,
+            latency_jitter: latency.map(|l| l.jitter),
+            latency_low: latency.map(|l| l.low),
+            latency_high: latency.map(|l| l.high),
+            latency_iqm: latency.map(|l| l.iqm),
+            packet_loss: measurement.packet_loss,
+        }
+        .into_query(format!("{}_{name}", params.measurement_prefix));
+        queries.push(add_tags(query, params, measurement));
+    }
+    queries
+}
+
+fn failure_query(params: &ConnectionParams, failure: &Failure) -> WriteQuery {
+    let query = FailureMeasurement {
+        time: failure.timestamp,
+        message: failure.message.clone(),
+    }
+    .into_query(format!("{}_failure", params.measurement_prefix))
+    .add_tag("backend", failure.backend.clone())
+    .add_tag("error_type", failure.kind.as_str());
+    add_extra_tags(query, params)
+}
+
+/// Retries with an increasing delay, so that a restarting InfluxDB doesn't lose results. Rejected
+/// writes are not retried.
+async fn write(destination: Destination, queries: Vec<WriteQuery>) -> anyhow::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match send(&destination, &queries).await {
+            Ok(_) => {
+                info!(attempt, points = queries.len(), "Stored results");
+                return Ok(());
+            }
+            Err(e) if attempt < MAX_ATTEMPTS && !RejectedWrite::is_cause_of(&e) => {
+                let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                warn!(attempt, error = %format!("{e:#}"), retry_in = ?delay, "Write failed");
+                time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                error!(attempt, error = %format!("{e:#}"), "Write failed, giving up");
+                return Err(e).context(format!("Write failed after {MAX_ATTEMPTS} attempts"));
+            }
+        }
+    }
+}
+
+async fn send(destination: &Destination, queries: &[WriteQuery]) -> anyhow::Result<()> {
+    match destination {
+        Destination::V1 {
+            url,
+            username,
+            password,
+            database,
+        } => {
+            let request = reqwest::Client::new()
+                .post(format!("{}/write", url.trim_end_matches('/')))
+                .query(&[
+                    ("db", database.as_str()),
+                    ("precision", "ns"),
+                    ("u", username.as_str()),
+                    ("p", password.as_str()),
+                ]);
+            post(request, queries).await?;
+        }
+        Destination::V2 {
+            url,
+            org,
+            bucket,
+            token,
+        } => {
+            let mut query = vec![("bucket", bucket.as_str()), ("precision", "ns")];
+            if let Some(org) = org {
+                query.push(("org", org.as_str()));
+            }
+            let request = reqwest::Client::new()
+                .post(format!("{}/api/v2/write", url.trim_end_matches('/')))
+                .query(&query)
+                .header("Authorization", format!("Token {token}"));
+            post(request, queries).await?;
+        }
+        Destination::LineProtocol { path: Some(path) } => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("Can't open {}", path.display()))?;
+            writeln!(file, "{}", line_protocol(queries)?)
+                .context(format!("Failed to write {}", path.display()))?;
+        }
+        Destination::LineProtocol { path: None } => println!("{}", line_protocol(queries)?),
+    }
+    Ok(())
+}
+
+async fn post(request: RequestBuilder, queries: &[WriteQuery]) -> anyhow::Result<()> {
+    let response = request
+        .body(line_protocol(queries)?)
+        .send()
+        .await
+        .context("InfluxDB write request failed")?;
+    let status = response.status();
+    // Credentials, a missing database and rate limits can be fixed without changing the points
+    let rejected = status.is_client_error()
+        && !matches!(
+            status,
+            StatusCode::UNAUTHORIZED
+                | StatusCode::FORBIDDEN
+                | StatusCode::NOT_FOUND
+                | StatusCode::TOO_MANY_REQUESTS
+        );
+    if rejected {
+        let body = response.text().await.unwrap_or_default();
+        return Err(RejectedWrite {
+            message: format!("{status} {}", body.trim()),
+        }
+        .into());
+    }
+    response
+        .error_for_status()
+        .context("InfluxDB write request failed")?;
+    Ok(())
+}
+
+/// Timestamps are in nanoseconds
+fn line_protocol(queries: &[WriteQuery]) -> anyhow::Result<String> {
+    let lines = queries
+        .iter()
+        .map(|query| query.build().map(|query| query.get()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// Tags that the backend didn't measure are left out
+fn add_tags(
+    mut query: WriteQuery,
+    params: &ConnectionParams,
+    measurement: &Measurement,
+) -> WriteQuery {
+    query = query
+        .add_tag("backend", measurement.backend.clone())
+        .add_tag("server_host", measurement.server.host.clone());
+    if let Some(location) = &measurement.server.location {
+        query = query.add_tag("server_location", location.clone());
+    }
+    if let Some(isp) = &measurement.isp {
+        query = query.add_tag("isp", isp.clone());
+    }
+    if let Some(interface) = &measurement.interface {
+        query = query.add_tag("internal_ip", interface.internal_ip.clone());
+        if let Some(mac_addr) = &interface.mac_addr {
+            query = query.add_tag("mac_addr", mac_addr.clone());
+        }
+    }
+    add_extra_tags(query, params)
+}
+
+fn add_extra_tags(mut query: WriteQuery, params: &ConnectionParams) -> WriteQuery {
+    for (key, value) in &params.tags {
+        query = query.add_tag(key.clone(), value.clone());
+    }
+    query
+}
+
+/// Parses `key=value`
+pub fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected key=value, got {tag}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DirectionMeasurement, Server};
+
+    #[test]
+    fn line_protocol_with_prefix_and_tags() {
+        let params = ConnectionParams {
+            destination: Destination::LineProtocol { path: None },
+            measurement_prefix: "net".to_string(),
+            tags: vec![parse_tag("site=home").unwrap()],
+        };
+        let measurement = Measurement {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            backend: "http".to_string(),
+            ping: None,
+            download: DirectionMeasurement {
+                bandwidth: 1000,
+                latency: None,
+            },
+            upload: DirectionMeasurement {
+                bandwidth: 500,
+                latency: None,
+            },
+            packet_loss: None,
+            isp: None,
+            interface: None,
+            server: Server {
+                host: "example.com".to_string(),
+                location: None,
+            },
+            result_url: None,
+        };
+
+        let lines = line_protocol(&measurement_queries(&params, &measurement)).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("net_download,"));
+        assert!(lines[0].contains("site=home"));
+        assert!(lines[0].contains("bandwidth=1000i"));
+        assert!(lines[0].ends_with(" 1700000000000000000"));
+        assert!(lines[1].starts_with("net_upload,"));
+    }
+
+    #[test]
+    fn spool_key_per_destination() {
+        let v1 = Destination::V1 {
+            url: "http://localhost:8086/".to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            database: "speedtest".to_string(),
+        };
+        assert_eq!(v1.spool_key(), "http-localhost-8086-speedtest");
+        let v2 = Destination::V2 {
+            url: "https://influx.example.com".to_string(),
+            org: Some("home".to_string()),
+            bucket: "net".to_string(),
+            token: "token".to_string(),
+        };
+        assert_eq!(v2.spool_key(), "https-influx-example-com-home-net");
+        let stdout = Destination::LineProtocol { path: None };
+        assert_eq!(stdout.spool_key(), "stdout");
+    }
+
+    #[test]
+    fn tags_need_key_and_value() {
+        assert!(parse_tag("site=home").is_ok());
+        assert!(parse_tag("site").is_err());
+        assert!(parse_tag("=home").is_err());
+    }
+}
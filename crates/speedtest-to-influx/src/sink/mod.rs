@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::backend::Record;
+
+pub mod influx;
+pub mod mqtt;
+pub mod prometheus;
+pub mod sqlite;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// InfluxDB or line protocol, with a spool while it's unreachable
+    Influx,
+    /// `/metrics` endpoint with the last result, only useful with `schedule`
+    Prometheus,
+    /// History table in a local database
+    Sqlite,
+    /// State topic with Home Assistant discovery
+    Mqtt,
+}
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn store(&self, record: &Record) -> anyhow::Result<()>;
+
+    /// Called before the process exits, for sinks that send in the background
+    async fn shutdown(&self) {}
+}
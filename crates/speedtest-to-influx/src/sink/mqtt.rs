@@ -0,0 +1,281 @@
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, QoS};
+use serde_json::json;
+use tokio::{
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::warn;
+
+use super::Sink;
+use crate::backend::{Failure, Measurement, Record};
+
+pub const DEFAULT_PORT: u16 = 1883;
+pub const DEFAULT_TOPIC: &str = "speedtest";
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Messages waiting for the broker, publishing fails above this instead of waiting
+const QUEUE_CAPACITY: usize = 10;
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Results go to `<topic>/state`, failures to `<topic>/failure`
+    pub topic: String,
+    /// Home Assistant discovery is disabled without a prefix
+    pub discovery_prefix: Option<String>,
+}
+
+/// Sensors announced to Home Assistant: key in the state payload, name, unit and device class
+const SENSORS: [(&str, &str, &str, Option<&str>); 5] = [
+    ("download", "Download", "Mbit/s", Some("data_rate")),
+    ("upload", "Upload", "Mbit/s", Some("data_rate")),
+    ("ping", "Ping", "ms", Some("duration")),
+    ("jitter", "Jitter", "ms", Some("duration")),
+    ("packet_loss", "Packet loss", "%", None),
+];
+
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+    /// Sends the queued messages to the broker
+    event_loop: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn store(&self, record: &Record) -> anyhow::Result<()> {
+        let (topic, payload, retain) = match record {
+            Record::Measurement(measurement) => {
+                (format!("{}/state", self.topic), state(measurement), true)
+            }
+            Record::Failure(f) => (format!("{}/failure", self.topic), failure(f), false),
+        };
+        self.client
+            .try_publish(topic, QoS::AtLeastOnce, retain, payload.to_string())
+            .context("Failed to publish to MQTT, the broker is unreachable")?;
+        Ok(())
+    }
+
+    /// Waits until the queued messages are sent
+    async fn shutdown(&self) {
+        let Some(event_loop) = self.event_loop.lock().unwrap().take() else {
+            return;
+        };
+        if let Err(e) = self.client.try_disconnect() {
+            warn!(error = %e, "Failed to disconnect from MQTT");
+            return;
+        }
+        if time::timeout(SHUTDOWN_TIMEOUT, event_loop).await.is_err() {
+            warn!("Timed out sending the MQTT messages");
+        }
+    }
+}
+
+impl MqttSink {
+    pub async fn connect(config: &MqttConfig) -> anyhow::Result<Self> {
+        let mut options = MqttOptions::new(
+            format!("speedtest-to-influx-{}", std::process::id()),
+            &config.host,
+            config.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let event_loop = task::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!(error = %e, "MQTT connection failed");
+                        time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+
+        let sink = MqttSink {
+            client,
+            topic: config.topic.clone(),
+            event_loop: Mutex::new(Some(event_loop)),
+        };
+        if let Some(prefix) = &config.discovery_prefix {
+            sink.publish_discovery(prefix).await?;
+        }
+        Ok(sink)
+    }
+
+    /// Retained config messages, so that Home Assistant creates the sensors on its own
+    async fn publish_discovery(&self, prefix: &str) -> anyhow::Result<()> {
+        let node_id = self
+            .topic
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        for (key, name, unit, device_class) in SENSORS {
+            let mut config = json!({
+                "name": name,
+                "unique_id": format!("{node_id}_{key}"),
+                "state_topic": format!("{}/state", self.topic),
+                "value_template": format!("{{{{ value_json.{key} }}}}"),
+                "unit_of_measurement": unit,
+                "state_class": "measurement",
+                "device": {
+                    "identifiers": [node_id],
+                    "name": "Speedtest",
+                    "model": "speedtest-to-influx",
+                },
+            });
+            if let Some(device_class) = device_class {
+                config["device_class"] = json!(device_class);
+            }
+            self.client
+                .try_publish(
+                    format!("{prefix}/sensor/{node_id}/{key}/config"),
+                    QoS::AtLeastOnce,
+                    true,
+                    config.to_string(),
+                )
+                .context("Failed to publish the Home Assistant discovery config")?;
+        }
+        Ok(())
+    }
+}
+
+fn state(measurement: &Measurement) -> serde_json::Value {
+    let mbits = |bytes: u64| (bytes as f64 * 8.0 / 1000.0 / 1000.0 * 100.0).round() / 100.0;
+    let ping = measurement.ping.as_ref();
+    json!({
+        "timestamp": measurement.timestamp.to_rfc3339(),
+        "backend": measurement.backend,
+        "server": measurement.server.host,
+        "download": mbits(measurement.download.bandwidth),
+        "upload": mbits(measurement.upload.bandwidth),
+        "ping": ping.map(|p| p.latency),
+        "jitter": ping.and_then(|p| p.jitter),
+        "packet_loss": measurement.packet_loss,
+    })
+}
+
+fn failure(failure: &Failure) -> serde_json::Value {
+    json!({
+        "timestamp": failure.timestamp.to_rfc3339(),
+        "backend": failure.backend,
+        "error_type": failure.kind.as_str(),
+        "message": failure.message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::backend::{DirectionMeasurement, FailureKind, Ping, Server};
+
+    #[test]
+    fn state_is_in_megabits() {
+        let measurement = Measurement {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            backend: "speedtest".to_string(),
+            ping: Some(Ping {
+                latency: 8.5,
+                low: 7.0,
+                high: 11.0,
+                jitter: Some(0.75),
+            }),
+            download: DirectionMeasurement {
+                bandwidth: 11_800_000,
+                latency: None,
+            },
+            upload: DirectionMeasurement {
+                bandwidth: 1_234_567,
+                latency: None,
+            },
+            packet_loss: Some(0.5),
+            isp: None,
+            interface: None,
+            server: Server {
+                host: "speedtest.example.com".to_string(),
+                location: None,
+            },
+            result_url: None,
+        };
+
+        assert_eq!(
+            state(&measurement),
+            json!({
+                "timestamp": "2023-11-14T22:13:20+00:00",
+                "backend": "speedtest",
+                "server": "speedtest.example.com",
+                "download": 94.4,
+                "upload": 9.88,
+                "ping": 8.5,
+                "jitter": 0.75,
+                "packet_loss": 0.5,
+            })
+        );
+    }
+
+    #[test]
+    fn failure_has_error_type() {
+        let payload = failure(&Failure {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            backend: "http".to_string(),
+            kind: FailureKind::NoNetwork,
+            message: "Network is unreachable".to_string(),
+        });
+        assert_eq!(payload["error_type"], "no_network");
+        assert_eq!(payload["message"], "Network is unreachable");
+    }
+
+    #[tokio::test]
+    async fn unreachable_broker_fails_instead_of_blocking() {
+        // Nothing listens on the port once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let sink = MqttSink::connect(&MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: None,
+            password: None,
+            topic: DEFAULT_TOPIC.to_string(),
+            discovery_prefix: Some(DEFAULT_DISCOVERY_PREFIX.to_string()),
+        })
+        .await
+        .unwrap();
+        let record = Record::Failure(Failure {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            backend: "http".to_string(),
+            kind: FailureKind::NoNetwork,
+            message: "Network is unreachable".to_string(),
+        });
+
+        let mut results = vec![];
+        for _ in 0..QUEUE_CAPACITY {
+            results.push(sink.store(&record).await);
+        }
+        assert!(results.iter().any(Result::is_err));
+        time::timeout(SHUTDOWN_TIMEOUT * 2, sink.shutdown())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task,
+};
+use tracing::{info, warn};
+
+use super::Sink;
+use crate::backend::{Measurement, Record};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:9516";
+
+#[derive(Default)]
+struct Metrics {
+    last: Option<Measurement>,
+    /// By error type
+    failures: BTreeMap<&'static str, u64>,
+}
+
+/// Serves the last result as gauges and the failures as counters
+pub struct PrometheusSink {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+#[async_trait]
+impl Sink for PrometheusSink {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    async fn store(&self, record: &Record) -> anyhow::Result<()> {
+        let mut metrics = self.metrics.lock().unwrap();
+        match record {
+            Record::Measurement(measurement) => metrics.last = Some(*measurement.clone()),
+            Record::Failure(failure) => {
+                *metrics.failures.entry(failure.kind.as_str()).or_default() += 1
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PrometheusSink {
+    pub async fn serve(address: SocketAddr) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(address)
+            .await
+            .context(format!("Can't listen on {address}"))?;
+        info!(%address, "Serving Prometheus metrics at /metrics");
+
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let served = metrics.clone();
+        task::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let metrics = served.clone();
+                        task::spawn(async move {
+                            if let Err(e) = respond(stream, &metrics).await {
+                                warn!(error = %e, "Failed to serve metrics");
+                            }
+                        });
+                    }
+                    Err(e) => warn!(error = %e, "Failed to accept connection"),
+                }
+            }
+        });
+
+        Ok(PrometheusSink { metrics })
+    }
+}
+
+/// Just enough HTTP for a scraper
+async fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> anyhow::Result<()> {
+    let mut request = [0; 1024];
+    let length = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..length]);
+
+    let response = if request.starts_with("GET /metrics ") {
+        let body = render(&metrics.lock().unwrap());
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn render(metrics: &Metrics) -> String {
+    let mut output = String::new();
+    if let Some(last) = &metrics.last {
+        let labels = format!(
+            "backend=\"{}\",server=\"{}\"",
+            escape_label(&last.backend),
+            escape_label(&last.server.host)
+        );
+        let ping = last.ping.as_ref();
+        let gauges = [
+            (
+                "speedtest_download_bytes_per_second",
+                "Download bandwidth of the last speedtest",
+                Some(last.download.bandwidth as f64),
+            ),
+            (
+                "speedtest_upload_bytes_per_second",
+                "Upload bandwidth of the last speedtest",
+                Some(last.upload.bandwidth as f64),
+            ),
+            (
+                "speedtest_ping_milliseconds",
+                "Idle latency of the last speedtest",
+                ping.map(|p| p.latency as f64),
+            ),
+            (
+                "speedtest_jitter_milliseconds",
+                "Idle jitter of the last speedtest",
+                ping.and_then(|p| p.jitter).map(f64::from),
+            ),
+            (
+                "speedtest_packet_loss_percent",
+                "Packet loss of the last speedtest",
+                last.packet_loss.map(f64::from),
+            ),
+            (
+                "speedtest_last_success_timestamp_seconds",
+                "Time of the last successful speedtest",
+                Some(last.timestamp.timestamp() as f64),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            if let Some(value) = value {
+                let _ = write!(
+                    output,
+                    "# HELP {name} {help}\n# TYPE {name} gauge\n{name}{{{labels}}} {value}\n"
+                );
+            }
+        }
+    }
+
+    let name = "speedtest_failures_total";
+    let _ = write!(
+        output,
+        "# HELP {name} Failed speedtests since the start\n# TYPE {name} counter\n"
+    );
+    for (error_type, count) in &metrics.failures {
+        let _ = writeln!(output, "{name}{{error_type=\"{error_type}\"}} {count}");
+    }
+    output
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DirectionMeasurement, FailureKind, Server};
+
+    #[test]
+    fn renders_last_result_and_failures() {
+        let metrics = Metrics {
+            last: Some(Measurement {
+                timestamp: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                backend: "iperf3".to_string(),
+                ping: None,
+                download: DirectionMeasurement {
+                    bandwidth: 1000,
+                    latency: None,
+                },
+                upload: DirectionMeasurement {
+                    bandwidth: 500,
+                    latency: None,
+                },
+                packet_loss: None,
+                isp: None,
+                interface: None,
+                server: Server {
+                    host: "nas.\"local\"".to_string(),
+                    location: None,
+                },
+                result_url: None,
+            }),
+            failures: BTreeMap::from([(FailureKind::Dns.as_str(), 2)]),
+        };
+
+        let output = render(&metrics);
+        assert!(output.contains(
+            "speedtest_download_bytes_per_second{backend=\"iperf3\",server=\"nas.\\\"local\\\"\"} 1000\n"
+        ));
+        assert!(!output.contains("speedtest_ping_milliseconds"));
+        assert!(output.contains("speedtest_last_success_timestamp_seconds{backend=\"iperf3\",server=\"nas.\\\"local\\\"\"} 1700000000\n"));
+        assert!(output.contains("speedtest_failures_total{error_type=\"dns\"} 2\n"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use directories::ProjectDirs;
+use rusqlite::{params, Connection};
+
+use super::Sink;
+use crate::backend::Record;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS measurements (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    backend TEXT NOT NULL,
+    server_host TEXT NOT NULL,
+    server_location TEXT,
+    isp TEXT,
+    download_bandwidth INTEGER NOT NULL,
+    upload_bandwidth INTEGER NOT NULL,
+    ping_latency REAL,
+    ping_jitter REAL,
+    packet_loss REAL,
+    result_url TEXT
+);
+CREATE TABLE IF NOT EXISTS failures (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    backend TEXT NOT NULL,
+    error_type TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+";
+
+/// Keeps the history of the results, bandwidths are in bytes per second
+pub struct SqliteSink {
+    connection: Mutex<Connection>,
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn store(&self, record: &Record) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        match record {
+            Record::Measurement(m) => connection.execute(
+                "INSERT INTO measurements (timestamp, backend, server_host, server_location, isp,
+                    download_bandwidth, upload_bandwidth, ping_latency, ping_jitter, packet_loss,
+                    result_url)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    m.timestamp.to_rfc3339(),
+                    m.backend,
+                    m.server.host,
+                    m.server.location,
+                    m.isp,
+                    m.download.bandwidth as i64,
+                    m.upload.bandwidth as i64,
+                    m.ping.as_ref().map(|p| p.latency),
+                    m.ping.as_ref().and_then(|p| p.jitter),
+                    m.packet_loss,
+                    m.result_url,
+                ],
+            ),
+            Record::Failure(f) => connection.execute(
+                "INSERT INTO failures (timestamp, backend, error_type, message)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    f.timestamp.to_rfc3339(),
+                    f.backend,
+                    f.kind.as_str(),
+                    f.message
+                ],
+            ),
+        }
+        .context("Failed to insert into the history database")?;
+        Ok(())
+    }
+}
+
+impl SqliteSink {
+    pub fn default_path() -> PathBuf {
+        match ProjectDirs::from("com.oliverfalvai.homecooked", "", "speedtest-to-influx") {
+            Some(project_dirs) => project_dirs.data_dir().join("history.sqlite"),
+            None => env::temp_dir().join("speedtest-to-influx-history.sqlite"),
+        }
+    }
+
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let connection =
+            Connection::open(path).context(format!("Can't open {}", path.display()))?;
+        Self::with_connection(connection)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteSink {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::backend::{DirectionMeasurement, Failure, FailureKind, Measurement, Ping, Server};
+
+    #[tokio::test]
+    async fn records_are_stored() {
+        let sink = SqliteSink::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let measurement = Measurement {
+            timestamp,
+            backend: "iperf3".to_string(),
+            ping: Some(Ping {
+                latency: 12.5,
+                low: 10.0,
+                high: 15.0,
+                jitter: None,
+            }),
+            download: DirectionMeasurement {
+                bandwidth: 11_800_000,
+                latency: None,
+            },
+            upload: DirectionMeasurement {
+                bandwidth: 5_000_000,
+                latency: None,
+            },
+            packet_loss: None,
+            isp: None,
+            interface: None,
+            server: Server {
+                host: "nas.local".to_string(),
+                location: None,
+            },
+            result_url: None,
+        };
+        sink.store(&Record::Measurement(Box::new(measurement)))
+            .await
+            .unwrap();
+        sink.store(&Record::Failure(Failure {
+            timestamp,
+            backend: "iperf3".to_string(),
+            kind: FailureKind::Dns,
+            message: "Can't resolve nas.local".to_string(),
+        }))
+        .await
+        .unwrap();
+
+        let connection = sink.connection.lock().unwrap();
+        let measurement: (String, String, i64, i64, Option<f64>, Option<f64>) = connection
+            .query_row(
+                "SELECT timestamp, server_host, download_bandwidth, upload_bandwidth,
+                    ping_latency, ping_jitter
+                FROM measurements",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            measurement,
+            (
+                "2023-11-14T22:13:20+00:00".to_string(),
+                "nas.local".to_string(),
+                11_800_000,
+                5_000_000,
+                Some(12.5),
+                None
+            )
+        );
+        let failure: (String, String) = connection
+            .query_row("SELECT error_type, message FROM failures", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(
+            failure,
+            ("dns".to_string(), "Can't resolve nas.local".to_string())
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use directories::ProjectDirs;
+use owo_colors::OwoColorize;
+use tracing::{info, warn};
+
+use crate::{
+    backend::Record,
+    sink::influx::{self, ConnectionParams, Destination, RejectedWrite},
+};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// Records that couldn't be stored yet, one JSON object per line, oldest first
+pub struct Spool {
+    pub path: PathBuf,
+    /// The oldest records are dropped above this
+    pub max_entries: usize,
+}
+
+impl Spool {
+    /// One spool per destination, so that switching destinations doesn't replay the records
+    pub fn default_path(destination: &Destination) -> PathBuf {
+        let file_name = format!("spool-{}.jsonl", destination.spool_key());
+        match ProjectDirs::from("com.oliverfalvai.homecooked", "", "speedtest-to-influx") {
+            Some(project_dirs) => project_dirs.data_dir().join(file_name),
+            None => env::temp_dir().join(format!("speedtest-to-influx-{file_name}")),
+        }
+    }
+
+    pub fn append(&self, record: &Record) -> anyhow::Result<()> {
+        append_line(&self.path, record)?;
+        let records = self.read()?;
+        if records.len() > self.max_entries {
+            let dropped = records.len() - self.max_entries;
+            warn!(dropped, path = %self.path.display(), "Spool is full, dropping the oldest records");
+            self.replace(&records[dropped..])?;
+        }
+        Ok(())
+    }
+
+    /// Lines that can't be parsed are skipped, so that one broken line doesn't block the rest
+    pub fn read(&self) -> anyhow::Result<Vec<Record>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let content = fs::read_to_string(&self.path)
+            .context(format!("Can't read spool {}", self.path.display()))?;
+        let mut records = vec![];
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    warn!(line = i + 1, error = %e, path = %self.path.display(), "Skipping invalid spool entry")
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Overwrites the spool with the records that are still not stored
+    pub fn replace(&self, records: &[Record]) -> anyhow::Result<()> {
+        if records.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)
+                    .context(format!("Failed to remove spool {}", self.path.display()))?;
+            }
+            return Ok(());
+        }
+        let mut content = String::new();
+        for record in records {
+            content.push_str(&serde_json::to_string(record)?);
+            content.push('\n');
+        }
+        // Renamed into place so that an interrupted write doesn't lose the spool
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, content).context(format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+
+    /// Records that InfluxDB rejected are kept next to the spool, for a look at what was wrong
+    pub fn rejected_path(&self) -> PathBuf {
+        self.path.with_extension("rejected.jsonl")
+    }
+
+    pub fn reject(&self, record: &Record, error: &anyhow::Error) -> anyhow::Result<()> {
+        let path = self.rejected_path();
+        append_line(&path, record)?;
+        warn!(error = %format!("{error:#}"), path = %path.display(), "Set aside a rejected record");
+        Ok(())
+    }
+}
+
+fn append_line(path: &Path, record: &Record) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Can't open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+        .context(format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+const FLUSH_BATCH: usize = 500;
+
+/// Replays the spool first, so that the records are stored in order. The record is spooled if
+/// InfluxDB is unreachable, and set aside if InfluxDB rejected it, which fails like an
+/// unspoolable record does.
+pub async fn store_or_spool(
+    params: &ConnectionParams,
+    spool: &Spool,
+    record: Record,
+) -> anyhow::Result<()> {
+    let stored = match flush(params, spool).await {
+        Ok(_) => influx::store(params.clone(), std::slice::from_ref(&record)).await,
+        Err(e) => Err(e),
+    };
+    match stored {
+        Ok(()) => Ok(()),
+        Err(e) if RejectedWrite::is_cause_of(&e) => {
+            spool.reject(&record, &e)?;
+            Err(e)
+        }
+        Err(e) => {
+            spool.append(&record)?;
+            warn!(error = %e, path = %spool.path.display(), "Saved the results to the spool");
+            eprintln!(
+                "{}: InfluxDB is unreachable, the results will be stored on the next successful run",
+                "Warning".yellow()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Stores the spooled records in batches, returns how many were stored
+pub async fn flush(params: &ConnectionParams, spool: &Spool) -> anyhow::Result<usize> {
+    let mut records = spool.read()?;
+    let mut stored = 0;
+    while !records.is_empty() {
+        let batch = records.len().min(FLUSH_BATCH);
+        stored += store_batch(params, spool, &records[..batch]).await?;
+        records.drain(..batch);
+        spool.replace(&records)?;
+    }
+    if stored > 0 {
+        info!(records = stored, "Replayed the spool");
+    }
+    Ok(stored)
+}
+
+/// A rejected batch is stored record by record, so that only the rejected records are set aside
+async fn store_batch(
+    params: &ConnectionParams,
+    spool: &Spool,
+    records: &[Record],
+) -> anyhow::Result<usize> {
+    match influx::store(params.clone(), records).await {
+        Err(e) if RejectedWrite::is_cause_of(&e) => {
+            let mut stored = 0;
+            for record in records {
+                match influx::store(params.clone(), std::slice::from_ref(record)).await {
+                    Ok(()) => stored += 1,
+                    Err(e) if RejectedWrite::is_cause_of(&e) => spool.reject(record, &e)?,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(stored)
+        }
+        result => result.map(|()| records.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+        thread,
+    };
+
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::backend::{Failure, FailureKind};
+
+    fn failure(message: &str) -> Record {
+        Record::Failure(Failure {
+            timestamp: Utc::now(),
+            backend: "ookla".to_string(),
+            kind: FailureKind::Dns,
+            message: message.to_string(),
+        })
+    }
+
+    fn messages(spool: &Spool) -> Vec<String> {
+        spool
+            .read()
+            .unwrap()
+            .into_iter()
+            .map(|record| match record {
+                Record::Failure(failure) => failure.message,
+                Record::Measurement(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oldest_records_are_dropped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("spool.jsonl");
+        let spool = Spool {
+            path: path.clone(),
+            max_entries: 2,
+        };
+
+        for message in ["first", "second", "third"] {
+            spool.append(&failure(message)).unwrap();
+        }
+        assert_eq!(messages(&spool), vec!["second", "third"]);
+
+        spool.replace(&[]).unwrap();
+        assert!(!path.exists());
+        assert!(messages(&spool).is_empty());
+    }
+
+    /// InfluxDB v2 write endpoint that rejects points containing "rejected"
+    fn serve(listener: TcpListener) {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = String::new();
+            reader
+                .take(content_length)
+                .read_to_string(&mut body)
+                .unwrap();
+            let status = if body.contains("rejected") {
+                "400 Bad Request"
+            } else {
+                "204 No Content"
+            };
+            let _ = write!(
+                &stream,
+                "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_records_are_set_aside() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || serve(listener));
+        let params = ConnectionParams {
+            destination: Destination::V2 {
+                url,
+                org: None,
+                bucket: "speedtest".to_string(),
+                token: "token".to_string(),
+            },
+            measurement_prefix: "speedtest".to_string(),
+            tags: vec![],
+        };
+        let dir = TempDir::new().unwrap();
+        let spool = Spool {
+            path: dir.path().join("spool.jsonl"),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        };
+        let rejected = Spool {
+            path: spool.rejected_path(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        };
+
+        for message in ["first", "rejected", "third"] {
+            spool.append(&failure(message)).unwrap();
+        }
+        assert_eq!(flush(&params, &spool).await.unwrap(), 2);
+        assert!(messages(&spool).is_empty());
+        assert_eq!(messages(&rejected), vec!["rejected"]);
+
+        let error = store_or_spool(&params, &spool, failure("rejected again"))
+            .await
+            .unwrap_err();
+        assert!(RejectedWrite::is_cause_of(&error));
+        assert!(messages(&spool).is_empty());
+        assert_eq!(messages(&rejected), vec!["rejected", "rejected again"]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::Command,
+    thread,
+};
+
+use tempfile::{NamedTempFile, TempDir};
+
+/// Answers every request with a tiny body, one request per connection
+fn serve(listener: TcpListener) {
+    for stream in listener.incoming().flatten() {
+        let _ = respond(stream);
+    }
+}
+
+fn respond(stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    reader.take(content_length).read_to_end(&mut vec![])?;
+
+    let body = if request_line.starts_with("HEAD") {
+        ""
+    } else {
+        "data"
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\n{body}"
+    )
+}
+
+#[test]
+fn stdout_only_carries_line_protocol() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    thread::spawn(move || serve(listener));
+    let config = NamedTempFile::new().unwrap();
+    let spool = TempDir::new().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_speedtest-to-influx"))
+        .arg("run")
+        .arg("--config")
+        .arg(config.path())
+        .args(["--backend", "http", "--http-upload-size", "1000"])
+        .args(["--http-download-url", &url, "--http-upload-url", &url])
+        .args(["--line-protocol", "-"])
+        .arg("--spool")
+        .arg(spool.path().join("spool.jsonl"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(!lines.is_empty());
+    for line in lines {
+        assert!(line.starts_with("speedtest_"), "unexpected line: {line}");
+    }
+    assert!(stderr.contains("Running http speedtest..."));
+    assert!(stderr.contains("Bandwidth:"));
+}